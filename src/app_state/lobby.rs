@@ -6,12 +6,15 @@ use bevy_egui::{
     EguiContext,
 };
 use libp2p::gossipsub::GossipsubEvent;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     cleanup::CleanupConfig,
+    latency::PeerLatencies,
     network::{NetworkBehaviourEvent, NetworkEvent, NetworkManager, NetworkTopic},
     party::Party,
-    player::{Player, PlayerId},
+    player::{Player, PlayerId, PlayerRole},
+    resources::SimulationSettings,
 };
 
 use super::AppState;
@@ -20,11 +23,26 @@ pub struct LobbyPlugin;
 
 impl Plugin for LobbyPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_event::<LobbyUIEvent>()
+        app.add_plugin(LobbyNetworkPlugin)
             .insert_resource(LobbyUI {
                 current_chat_message: "".into(),
                 chat_messages: Vec::new(),
             })
+            .add_system_set(
+                SystemSet::on_update(AppState::InLobby)
+                    .with_system(receive_chat_messages.system())
+                    .with_system(lobby_ui.system().before("lobby_ui_events")),
+            );
+    }
+}
+
+/// Joins, roles and the start of the game, driven by `LobbyUIEvent`s instead of the lobby window
+/// so that lobbies can also run headless.
+pub struct LobbyNetworkPlugin;
+
+impl Plugin for LobbyNetworkPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_event::<LobbyUIEvent>()
             .add_system_set(
                 SystemSet::on_update(AppState::JoiningLobby)
                     .with_system(handle_join_lobby_events.system()),
@@ -36,9 +54,8 @@ impl Plugin for LobbyPlugin {
                 SystemSet::on_update(AppState::InLobby)
                     .with_system(handle_host_events.system())
                     .with_system(handle_client_events.system())
-                    .with_system(receive_chat_messages.system())
-                    .with_system(lobby_ui.system().label("lobby_ui"))
-                    .with_system(handle_lobby_ui_events.system().after("lobby_ui")),
+                    .with_system(receive_player_roles.system())
+                    .with_system(handle_lobby_ui_events.system().label("lobby_ui_events")),
             )
             .add_system_set(
                 SystemSet::on_exit(AppState::InLobby).with_system(unsubscribe_all_topics.system()),
@@ -64,9 +81,16 @@ struct LobbyUI {
     chat_messages: Vec<LobbyChatMessage>,
 }
 
-enum LobbyUIEvent {
+pub enum LobbyUIEvent {
     Leave,
     SendChatMessage(String),
+    SetRole(PlayerRole),
+    StartGame,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StartGameMessage {
+    settings: SimulationSettings,
 }
 
 fn handle_join_lobby_events(
@@ -78,7 +102,11 @@ fn handle_join_lobby_events(
 ) {
     for event in network_events.iter() {
         match event {
-            NetworkEvent::ConnectionEstablished { .. } => {
+            // Publishing as soon as the connection is established fails when the subscriptions of
+            // the host have not arrived yet
+            NetworkEvent::Behaviour(NetworkBehaviourEvent::Gossipsub(
+                GossipsubEvent::Subscribed { topic, .. },
+            )) if NetworkTopic::new("join_request").hash() == *topic => {
                 let data = serde_json::to_vec(&party.players[&party.host_id]).unwrap();
                 network_manager.publish(NetworkTopic::new("join_request"), data);
             }
@@ -126,11 +154,14 @@ fn handle_join_lobby_events(
 
 fn setup_lobby(mut network_manager: ResMut<NetworkManager>) {
     network_manager.subscribe(NetworkTopic::new("chat"));
+    network_manager.subscribe(NetworkTopic::new("start_game"));
+    network_manager.subscribe(NetworkTopic::new("player_role"));
 }
 
 fn lobby_ui(
     egui_context: Res<EguiContext>,
     party: Res<Party>,
+    latencies: Res<PeerLatencies>,
     network_manager: Res<NetworkManager>,
     mut lobby_ui: ResMut<LobbyUI>,
     mut lobby_ui_events: EventWriter<LobbyUIEvent>,
@@ -160,13 +191,50 @@ fn lobby_ui(
 
                 ui.label(&player.name);
                 ui.checkbox(&mut false, "Ready");
+
+                if player.role == PlayerRole::Spectator {
+                    ui.label("👁 Spectating");
+                }
+
+                if let Some(round_trip_time) = latencies.round_trip_times.get(&player.id) {
+                    ui.label(format!("{:.0} ms", round_trip_time * 1000.0));
+                }
             });
         }
 
         ui.separator();
 
+        let local_player = &party.players[&network_manager.local_peer_id().into()];
+        let mut spectating = local_player.role == PlayerRole::Spectator;
+
+        if ui.checkbox(&mut spectating, "Spectate").changed() {
+            lobby_ui_events.send(LobbyUIEvent::SetRole(if spectating {
+                PlayerRole::Spectator
+            } else {
+                PlayerRole::Player
+            }));
+        }
+
+        ui.separator();
+
         ui.horizontal(|ui| {
-            egui::Button::new("Start Game").enabled(false).ui(ui);
+            let is_host = party.host_id == network_manager.local_peer_id().into();
+
+            if egui::Button::new("Start Game")
+                .enabled(is_host)
+                .ui(ui)
+                .clicked()
+            {
+                lobby_ui_events.send(LobbyUIEvent::StartGame);
+            }
+
+            if is_host {
+                let settings = latencies.simulation_settings();
+                ui.label(format!(
+                    "{} ticks/s, {} ticks input delay",
+                    settings.tick_rate, settings.input_delay
+                ));
+            }
         });
 
         ui.separator();
@@ -223,6 +291,9 @@ fn lobby_ui(
 fn handle_lobby_ui_events(
     mut cleanup_config: ResMut<CleanupConfig>,
     mut app_state: ResMut<State<AppState>>,
+    mut party: ResMut<Party>,
+    mut simulation_settings: ResMut<SimulationSettings>,
+    latencies: Res<PeerLatencies>,
     mut lobby_ui_events: EventReader<LobbyUIEvent>,
     mut network_manager: ResMut<NetworkManager>,
 ) {
@@ -235,6 +306,25 @@ fn handle_lobby_ui_events(
             LobbyUIEvent::SendChatMessage(message) => {
                 network_manager.publish(NetworkTopic::new("chat"), message.as_bytes());
             }
+            LobbyUIEvent::SetRole(role) => {
+                let local_player_id = network_manager.local_peer_id().into();
+                party.players.get_mut(&local_player_id).unwrap().role = *role;
+
+                let data = serde_json::to_vec(role).unwrap();
+                network_manager.publish(NetworkTopic::new("player_role"), data);
+            }
+            LobbyUIEvent::StartGame => {
+                *simulation_settings = latencies.simulation_settings();
+
+                let data = serde_json::to_vec(&StartGameMessage {
+                    settings: *simulation_settings,
+                })
+                .unwrap();
+                network_manager.publish(NetworkTopic::new("start_game"), data);
+
+                cleanup_config.next_state_after_cleanup = Some(AppState::InGame);
+                app_state.set(AppState::Cleanup).unwrap();
+            }
         }
     }
 }
@@ -255,6 +345,14 @@ fn handle_host_events(
         {
             if NetworkTopic::new("join_request").hash() == message.topic {
                 match serde_json::from_slice::<'_, Player>(&message.data) {
+                    Ok(player) if message.source != Some(player.id.into()) => {
+                        let error = format!(
+                            "Rejected join_request from {:?} claiming to be {:?}",
+                            message.source, player.id
+                        );
+                        warn!("{}", error);
+                        network_manager.publish(NetworkTopic::new("join_rejected"), error);
+                    }
                     Ok(player) => {
                         let party_json = serde_json::to_vec(party.deref()).unwrap();
                         party.players.insert(player.id, player);
@@ -274,6 +372,9 @@ fn handle_host_events(
 }
 
 fn handle_client_events(
+    mut cleanup_config: ResMut<CleanupConfig>,
+    mut app_state: ResMut<State<AppState>>,
+    mut simulation_settings: ResMut<SimulationSettings>,
     mut party: ResMut<Party>,
     network_manager: Res<NetworkManager>,
     mut network_events: EventReader<NetworkEvent>,
@@ -287,7 +388,9 @@ fn handle_client_events(
             NetworkEvent::Behaviour(NetworkBehaviourEvent::Gossipsub(
                 GossipsubEvent::Message { message, .. },
             )) => {
-                if NetworkTopic::new("joined").hash() == message.topic {
+                if NetworkTopic::new("joined").hash() == message.topic
+                    && message.source == Some(party.host_id.into())
+                {
                     match serde_json::from_slice::<'_, Player>(&message.data) {
                         Ok(player) => {
                             party.players.insert(player.id, player);
@@ -300,6 +403,23 @@ fn handle_client_events(
                             error!("{}", error);
                         }
                     };
+                } else if NetworkTopic::new("start_game").hash() == message.topic
+                    && message.source == Some(party.host_id.into())
+                {
+                    match serde_json::from_slice::<'_, StartGameMessage>(&message.data) {
+                        Ok(start_game) => {
+                            *simulation_settings = start_game.settings;
+                            cleanup_config.next_state_after_cleanup = Some(AppState::InGame);
+                            app_state.set(AppState::Cleanup).unwrap();
+                        }
+                        Err(error) => {
+                            let error = format!(
+                                "Cannot parse StartGameMessage from start_game message: {:?}",
+                                error
+                            );
+                            error!("{}", error);
+                        }
+                    };
                 }
             }
             _ => (),
@@ -329,8 +449,39 @@ fn receive_chat_messages(
     }
 }
 
+fn receive_player_roles(mut party: ResMut<Party>, mut network_events: EventReader<NetworkEvent>) {
+    for event in network_events.iter() {
+        if let NetworkEvent::Behaviour(NetworkBehaviourEvent::Gossipsub(
+            GossipsubEvent::Message { message, .. },
+        )) = event
+        {
+            if NetworkTopic::new("player_role").hash() == message.topic {
+                let player = match message
+                    .source
+                    .and_then(|source| party.players.get_mut(&source.into()))
+                {
+                    Some(player) => player,
+                    None => continue,
+                };
+
+                match serde_json::from_slice::<'_, PlayerRole>(&message.data) {
+                    Ok(role) => player.role = role,
+                    Err(error) => {
+                        error!(
+                            "Cannot parse PlayerRole from player_role message: {:?}",
+                            error
+                        );
+                    }
+                };
+            }
+        }
+    }
+}
+
 fn unsubscribe_all_topics(mut network_manager: ResMut<NetworkManager>) {
     network_manager.unsubscribe(NetworkTopic::new("chat"));
+    network_manager.unsubscribe(NetworkTopic::new("start_game"));
+    network_manager.unsubscribe(NetworkTopic::new("player_role"));
     network_manager.unsubscribe(NetworkTopic::new("joined"));
     network_manager.unsubscribe(NetworkTopic::new("join_accepted"));
     network_manager.unsubscribe(NetworkTopic::new("join_rejected"));
@@ -0,0 +1,76 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::{
+    app_state::AppState,
+    player::PlayerId,
+    tick::{on_tick, AddTickEvent},
+    Health,
+};
+
+pub struct DamagePlugin;
+
+impl Plugin for DamagePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(DamageRules::default())
+            .add_tick_event::<DamageEvent>()
+            .add_system_set(
+                on_tick(AppState::InGame).with_system(apply_damage.system().label("apply_damage")),
+            );
+    }
+}
+
+/// How players may hurt each other.
+pub struct DamageRules {
+    pub friendly_fire: bool,
+    /// Multiplier applied to damage dealt to teammates when friendly fire is on.
+    pub friendly_fire_multiplier: f32,
+}
+
+impl Default for DamageRules {
+    fn default() -> Self {
+        Self {
+            friendly_fire: false,
+            friendly_fire_multiplier: 0.5,
+        }
+    }
+}
+
+impl DamageRules {
+    /// Multiplier for damage dealt by `attacker` to the character of `target`, or `None` if the
+    /// hit does not count at all. Players never hurt themselves, and `None` attackers are enemies
+    /// or the environment.
+    pub fn player_damage_multiplier(
+        &self,
+        attacker: Option<PlayerId>,
+        target: PlayerId,
+    ) -> Option<f32> {
+        match attacker {
+            None => Some(1.0),
+            Some(attacker) if attacker == target => None,
+            Some(_) if self.friendly_fire => Some(self.friendly_fire_multiplier),
+            Some(_) => None,
+        }
+    }
+}
+
+/// Character controlled by a player. Shots fired by its weapons are tagged with the player.
+pub struct PlayerCharacter(pub PlayerId);
+
+/// Damage dealt to anything with `Health`. Enemies take `EnemyHitEvent`s instead, which also
+/// drive their reactions.
+pub struct DamageEvent {
+    pub target: Entity,
+    pub attacker: Option<PlayerId>,
+    pub amount: f32,
+    pub position: Point<f32>,
+    pub direction: UnitVector<f32>,
+}
+
+fn apply_damage(mut damage_events: EventReader<DamageEvent>, mut query: Query<&mut Health>) {
+    for damage_event in damage_events.iter() {
+        if let Ok(mut health) = query.get_mut(damage_event.target) {
+            health.0 -= damage_event.amount;
+        }
+    }
+}
@@ -1,21 +1,38 @@
 use bevy::prelude::*;
 
+use crate::{
+    pool::{Pooled, Released},
+    tick::tick_due,
+};
+
 pub struct DespawnPlugin;
 
 impl Plugin for DespawnPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_system_to_stage(CoreStage::Last, despawn.system());
+        app.add_system_to_stage(
+            CoreStage::Last,
+            despawn.system().with_run_criteria(tick_due.system()),
+        );
     }
 }
 
-pub struct DespawnAfter(pub f32);
-
-fn despawn(mut commands: Commands, time: Res<Time>, query: Query<(Entity, &mut DespawnAfter)>) {
-    query.for_each_mut(|(entity, mut despawn_after)| {
-        despawn_after.0 -= time.delta_seconds();
+/// Ticks left before the entity is despawned. Zero despawns it at the end of the current tick.
+pub struct DespawnAfter(pub usize);
 
-        if despawn_after.0 <= 0.0 {
-            commands.entity(entity).despawn_recursive();
+fn despawn(mut commands: Commands, query: Query<(Entity, &mut DespawnAfter, Option<&Pooled>)>) {
+    query.for_each_mut(|(entity, mut despawn_after, pooled)| {
+        if despawn_after.0 > 0 {
+            despawn_after.0 -= 1;
+        } else {
+            // Pooled entities go back to their pool to be reused
+            if pooled.is_some() {
+                commands
+                    .entity(entity)
+                    .remove::<DespawnAfter>()
+                    .insert(Released);
+            } else {
+                commands.entity(entity).despawn_recursive();
+            }
         }
     });
 }
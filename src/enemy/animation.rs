@@ -0,0 +1,536 @@
+use std::{collections::HashMap, path::Path};
+
+use bevy::{
+    asset::{AssetLoader, AssetPath, BoxedFuture, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    render::{mesh::Indices, pipeline::PrimitiveTopology},
+};
+use bevy_rapier3d::prelude::*;
+use gltf::animation::{util::ReadOutputs, Interpolation};
+use serde::Deserialize;
+
+use super::{attack::EnemyAttackState, EnemyBehavior};
+
+/// Animation an enemy model can play.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum EnemyClip {
+    Idle,
+    Walk,
+    Attack,
+    Death,
+}
+
+impl EnemyClip {
+    fn looping(self) -> bool {
+        matches!(self, EnemyClip::Idle | EnemyClip::Walk)
+    }
+}
+
+/// Keyframed node animations of one model, loaded from a `.clips.json` file.
+///
+/// The file names a glTF document and maps each `EnemyClip` to one of its animations:
+///
+/// ```json
+/// { "source": "scene.gltf", "clips": { "Walk": "Armature|Walk" } }
+/// ```
+///
+/// Bevy does not skin meshes, so the skinned mesh of the document is also cut into rigid parts,
+/// one per joint, which follow the animated joint nodes in place of the mesh.
+#[derive(TypeUuid)]
+#[uuid = "3f7d2b9a-6c1e-4e58-b0a4-7d91c5e2f813"]
+pub struct EnemyAnimationSet {
+    pub clips: HashMap<EnemyClip, AnimationClip>,
+    pub parts: Vec<ModelPart>,
+}
+
+/// Triangles of the skinned mesh mostly weighted to one joint, in the space of that joint.
+pub struct ModelPart {
+    pub node: String,
+    pub mesh: Handle<Mesh>,
+}
+
+pub struct AnimationClip {
+    pub duration: f32,
+    pub curves: Vec<AnimationCurve>,
+}
+
+/// Keyframes of one property of the node with the given name.
+pub struct AnimationCurve {
+    pub node: String,
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+    pub step: bool,
+}
+
+pub enum Keyframes {
+    Translation(Vec<Vec3>),
+    Rotation(Vec<Quat>),
+    Scale(Vec<Vec3>),
+}
+
+impl AnimationCurve {
+    /// Writes the value of the curve at `time` into `transform`.
+    fn apply(&self, time: f32, transform: &mut Transform) {
+        let next = self
+            .times
+            .iter()
+            .position(|&keyframe_time| keyframe_time > time)
+            .unwrap_or(self.times.len());
+        let (from, to, t) = if next == 0 {
+            (0, 0, 0.0)
+        } else if next == self.times.len() || self.step {
+            (next - 1, next - 1, 0.0)
+        } else {
+            let start = self.times[next - 1];
+            let end = self.times[next];
+
+            (next - 1, next, (time - start) / (end - start))
+        };
+
+        match &self.keyframes {
+            Keyframes::Translation(values) => {
+                transform.translation = values[from].lerp(values[to], t);
+            }
+            Keyframes::Rotation(values) => {
+                transform.rotation = values[from].slerp(values[to], t);
+            }
+            Keyframes::Scale(values) => {
+                transform.scale = values[from].lerp(values[to], t);
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AnimationSetDescription {
+    source: String,
+    clips: HashMap<EnemyClip, String>,
+}
+
+#[derive(Default)]
+pub struct EnemyAnimationSetLoader;
+
+impl AssetLoader for EnemyAnimationSetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let description = serde_json::from_slice::<'_, AnimationSetDescription>(bytes)?;
+            let directory = load_context
+                .path()
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .to_path_buf();
+
+            let source = load_context
+                .read_asset_bytes(directory.join(&description.source))
+                .await?;
+            let gltf = gltf::Gltf::from_slice(&source)?;
+
+            let mut buffers = Vec::new();
+
+            for buffer in gltf.buffers() {
+                match buffer.source() {
+                    gltf::buffer::Source::Uri(uri) => {
+                        buffers.push(load_context.read_asset_bytes(directory.join(uri)).await?);
+                    }
+                    gltf::buffer::Source::Bin => {
+                        buffers.push(gltf.blob.clone().unwrap_or_default());
+                    }
+                }
+            }
+
+            let mut clips = HashMap::new();
+
+            for (clip, animation_name) in description.clips.iter() {
+                let animation = gltf
+                    .animations()
+                    .find(|animation| animation.name() == Some(animation_name.as_str()))
+                    .ok_or_else(|| anyhow::anyhow!("Missing animation {}", animation_name))?;
+
+                let mut curves = Vec::new();
+                let mut duration: f32 = 0.0;
+
+                for channel in animation.channels() {
+                    let reader = channel.reader(|buffer| Some(buffers[buffer.index()].as_slice()));
+                    let node = channel
+                        .target()
+                        .node()
+                        .name()
+                        .unwrap_or_default()
+                        .to_string();
+                    let interpolation = channel.sampler().interpolation();
+
+                    let times: Vec<f32> = match reader.read_inputs() {
+                        Some(inputs) => inputs.collect(),
+                        None => continue,
+                    };
+
+                    // Cubic spline outputs hold an in-tangent, value and out-tangent per keyframe
+                    let values = |index: usize| match interpolation {
+                        Interpolation::CubicSpline => index % 3 == 1,
+                        _ => true,
+                    };
+
+                    let keyframes = match reader.read_outputs() {
+                        Some(ReadOutputs::Translations(outputs)) => Keyframes::Translation(
+                            outputs
+                                .enumerate()
+                                .filter(|&(index, _)| values(index))
+                                .map(|(_, value)| Vec3::from(value))
+                                .collect(),
+                        ),
+                        Some(ReadOutputs::Rotations(outputs)) => Keyframes::Rotation(
+                            outputs
+                                .into_f32()
+                                .enumerate()
+                                .filter(|&(index, _)| values(index))
+                                .map(|(_, value)| {
+                                    Quat::from_xyzw(value[0], value[1], value[2], value[3])
+                                })
+                                .collect(),
+                        ),
+                        Some(ReadOutputs::Scales(outputs)) => Keyframes::Scale(
+                            outputs
+                                .enumerate()
+                                .filter(|&(index, _)| values(index))
+                                .map(|(_, value)| Vec3::from(value))
+                                .collect(),
+                        ),
+                        _ => continue,
+                    };
+
+                    duration = duration.max(times.last().copied().unwrap_or(0.0));
+
+                    curves.push(AnimationCurve {
+                        node,
+                        times,
+                        keyframes,
+                        step: interpolation == Interpolation::Step,
+                    });
+                }
+
+                clips.insert(*clip, AnimationClip { duration, curves });
+            }
+
+            let mut parts = Vec::new();
+
+            for (node, mesh) in split_skinned_mesh(&gltf, &buffers) {
+                let label = format!("Part/{}", node);
+                load_context.set_labeled_asset(&label, LoadedAsset::new(mesh));
+                let mesh = load_context.get_handle(AssetPath::new_ref(
+                    load_context.path(),
+                    Some(label.as_str()),
+                ));
+
+                parts.push(ModelPart { node, mesh });
+            }
+
+            load_context.set_default_asset(LoadedAsset::new(EnemyAnimationSet { clips, parts }));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["clips.json"]
+    }
+}
+
+#[derive(Default)]
+struct PartGeometry {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+}
+
+/// Cuts the first skinned mesh of the document into one mesh per joint, by giving each triangle to
+/// the joint with the most weight on its vertices.
+///
+/// Vertices are moved from the bind pose into the space of their joint, so a part placed as a child
+/// of its joint node follows it like the skinned mesh would, minus the blending across joints.
+fn split_skinned_mesh(gltf: &gltf::Gltf, buffers: &[Vec<u8>]) -> Vec<(String, Mesh)> {
+    let (mesh, skin) = match gltf
+        .nodes()
+        .find_map(|node| Some((node.mesh()?, node.skin()?)))
+    {
+        Some(skinned) => skinned,
+        None => return Vec::new(),
+    };
+    let read_buffer = |buffer: gltf::Buffer| Some(buffers[buffer.index()].as_slice());
+
+    let joint_names: Vec<String> = skin
+        .joints()
+        .map(|joint| joint.name().unwrap_or_default().to_string())
+        .collect();
+    let inverse_bind_matrices: Vec<Mat4> =
+        match skin.reader(read_buffer).read_inverse_bind_matrices() {
+            Some(matrices) => matrices
+                .map(|matrix| Mat4::from_cols_array_2d(&matrix))
+                .collect(),
+            None => vec![Mat4::IDENTITY; joint_names.len()],
+        };
+    let normal_matrices: Vec<Mat4> = inverse_bind_matrices
+        .iter()
+        .map(|matrix| matrix.inverse().transpose())
+        .collect();
+
+    let mut geometries: Vec<PartGeometry> =
+        joint_names.iter().map(|_| Default::default()).collect();
+
+    for primitive in mesh.primitives() {
+        let reader = primitive.reader(read_buffer);
+
+        let (positions, normals, joints, weights) = match (
+            reader.read_positions(),
+            reader.read_normals(),
+            reader.read_joints(0),
+            reader.read_weights(0),
+        ) {
+            (Some(positions), Some(normals), Some(joints), Some(weights)) => (
+                positions.collect::<Vec<_>>(),
+                normals.collect::<Vec<_>>(),
+                joints.into_u16().collect::<Vec<_>>(),
+                weights.into_f32().collect::<Vec<_>>(),
+            ),
+            _ => continue,
+        };
+        let uvs: Vec<[f32; 2]> = match reader.read_tex_coords(0) {
+            Some(uvs) => uvs.into_f32().collect(),
+            None => vec![[0.0, 0.0]; positions.len()],
+        };
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+
+        for triangle in indices.chunks_exact(3) {
+            let mut joint_weights = vec![0.0; joint_names.len()];
+
+            for &vertex in triangle {
+                for (&joint, &weight) in joints[vertex as usize]
+                    .iter()
+                    .zip(weights[vertex as usize].iter())
+                {
+                    if let Some(joint_weight) = joint_weights.get_mut(joint as usize) {
+                        *joint_weight += weight;
+                    }
+                }
+            }
+
+            let joint = joint_weights
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+                .map_or(0, |(joint, _)| joint);
+            let geometry = &mut geometries[joint];
+
+            for &vertex in triangle {
+                let vertex = vertex as usize;
+
+                geometry.positions.push(
+                    inverse_bind_matrices[joint]
+                        .transform_point3(positions[vertex].into())
+                        .into(),
+                );
+                geometry.normals.push(
+                    normal_matrices[joint]
+                        .transform_vector3(normals[vertex].into())
+                        .normalize()
+                        .into(),
+                );
+                geometry.uvs.push(uvs[vertex]);
+            }
+        }
+    }
+
+    joint_names
+        .into_iter()
+        .zip(geometries)
+        .filter(|(_, geometry)| !geometry.positions.is_empty())
+        .map(|(node, geometry)| {
+            let vertex_count = geometry.positions.len() as u32;
+            let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+            mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, geometry.positions);
+            mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, geometry.normals);
+            mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, geometry.uvs);
+            mesh.set_indices(Some(Indices::U32((0..vertex_count).collect())));
+
+            (node, mesh)
+        })
+        .collect()
+}
+
+/// Cosmetic animation state of an enemy model.
+///
+/// Clips are picked from the simulation state but advanced with frame time and never read back,
+/// so animations cannot affect the deterministic simulation.
+pub struct EnemyAnimator {
+    pub animations: Handle<EnemyAnimationSet>,
+    pub clip: EnemyClip,
+    pub time: f32,
+    /// Model nodes by name, found once the model scene has spawned.
+    nodes: HashMap<String, Entity>,
+}
+
+impl EnemyAnimator {
+    pub fn new(animations: Handle<EnemyAnimationSet>) -> Self {
+        Self {
+            animations,
+            clip: EnemyClip::Idle,
+            time: 0.0,
+            nodes: HashMap::new(),
+        }
+    }
+}
+
+/// Horizontal speed above which an enemy plays its walk animation.
+const WALK_SPEED_THRESHOLD: f32 = 0.1;
+
+pub(super) fn select_enemy_clip(
+    mut enemy_query: Query<(
+        &EnemyBehavior,
+        &EnemyAttackState,
+        &RigidBodyVelocity,
+        &mut EnemyAnimator,
+    )>,
+) {
+    for (behavior, attack, velocity, mut animator) in enemy_query.iter_mut() {
+        let speed = Vec2::new(velocity.linvel.x, velocity.linvel.z).length();
+
+        let clip = if let EnemyBehavior::Death = *behavior {
+            EnemyClip::Death
+        } else if attack.is_attacking() {
+            EnemyClip::Attack
+        } else if speed > WALK_SPEED_THRESHOLD {
+            EnemyClip::Walk
+        } else {
+            EnemyClip::Idle
+        };
+
+        if animator.clip != clip {
+            animator.clip = clip;
+            animator.time = 0.0;
+        }
+    }
+}
+
+fn collect_model_nodes(
+    entity: Entity,
+    children_query: &Query<&Children>,
+    name_query: &Query<&Name>,
+    mesh_query: &Query<&Handle<Mesh>>,
+    nodes: &mut HashMap<String, Entity>,
+    meshes: &mut Vec<Entity>,
+) {
+    if let Ok(name) = name_query.get(entity) {
+        nodes.entry(name.as_str().to_string()).or_insert(entity);
+    }
+
+    if mesh_query.get(entity).is_ok() {
+        meshes.push(entity);
+    }
+
+    if let Ok(children) = children_query.get(entity) {
+        for &child in children.iter() {
+            collect_model_nodes(child, children_query, name_query, mesh_query, nodes, meshes);
+        }
+    }
+}
+
+/// Samples the current clip of every enemy onto its model nodes.
+///
+/// Once the model scene has spawned, its skinned mesh is hidden and the rigid parts of the set are
+/// attached to their joint nodes with the same material.
+///
+/// Clips missing from the set fall back to the first frame of the walk cycle, which is how the
+/// zombie model, shipped without an idle animation, stands still.
+pub(super) fn play_enemy_animations(
+    mut commands: Commands,
+    time: Res<Time>,
+    animation_sets: Res<Assets<EnemyAnimationSet>>,
+    mut animator_query: Query<(Entity, &mut EnemyAnimator)>,
+    children_query: Query<&Children>,
+    name_query: Query<&Name>,
+    mesh_query: Query<&Handle<Mesh>>,
+    mut skinned_mesh_query: Query<(&Handle<StandardMaterial>, &mut Visible)>,
+    mut transform_query: Query<&mut Transform>,
+) {
+    for (entity, mut animator) in animator_query.iter_mut() {
+        let animation_set = match animation_sets.get(&animator.animations) {
+            Some(animation_set) => animation_set,
+            None => continue,
+        };
+
+        // Scenes spawn a few frames after the enemy
+        if animator.nodes.is_empty() {
+            let mut nodes = HashMap::new();
+            let mut meshes = Vec::new();
+            collect_model_nodes(
+                entity,
+                &children_query,
+                &name_query,
+                &mesh_query,
+                &mut nodes,
+                &mut meshes,
+            );
+
+            let mut material = None;
+
+            // Only scenes with joints are cut into parts, capsule models have no named nodes
+            if nodes.is_empty() || animation_set.parts.is_empty() {
+                meshes.clear();
+            }
+
+            for mesh in meshes {
+                if let Ok((mesh_material, mut visible)) = skinned_mesh_query.get_mut(mesh) {
+                    material = Some(mesh_material.clone());
+                    visible.is_visible = false;
+                }
+            }
+
+            if let Some(material) = material {
+                for part in animation_set.parts.iter() {
+                    if let Some(&node) = nodes.get(&part.node) {
+                        commands.entity(node).with_children(|parent| {
+                            parent.spawn_bundle(PbrBundle {
+                                mesh: part.mesh.clone(),
+                                material: material.clone(),
+                                ..Default::default()
+                            });
+                        });
+                    }
+                }
+            }
+
+            animator.nodes = nodes;
+        }
+
+        let (clip, clip_time) = match animation_set.clips.get(&animator.clip) {
+            Some(clip) => {
+                let clip_time = if animator.clip.looping() && clip.duration > 0.0 {
+                    (animator.time + time.delta_seconds()) % clip.duration
+                } else {
+                    (animator.time + time.delta_seconds()).min(clip.duration)
+                };
+
+                animator.time = clip_time;
+                (clip, clip_time)
+            }
+            None => match animation_set.clips.get(&EnemyClip::Walk) {
+                Some(clip) => (clip, 0.0),
+                None => continue,
+            },
+        };
+
+        for curve in clip.curves.iter() {
+            if let Some(&node) = animator.nodes.get(&curve.node) {
+                if let Ok(mut transform) = transform_query.get_mut(node) {
+                    curve.apply(clip_time, &mut transform);
+                }
+            }
+        }
+    }
+}
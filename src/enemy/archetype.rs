@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use bevy::{prelude::*, reflect::TypeUuid};
+use bevy_rapier3d::prelude::*;
+use serde::Deserialize;
+
+use super::{
+    animation::EnemyAnimationSet,
+    behavior_tree::{default_behavior_tree, BehaviorNode},
+    boss::BossDefinition,
+};
+use crate::weapons::pickup::PickupDrop;
+
+/// Stats and looks of one kind of enemy, loaded from an `.enemy.json` file.
+#[derive(Deserialize, TypeUuid)]
+#[uuid = "9e2f4c61-7a3b-4b0d-8f15-3c6d2e8a9b70"]
+pub struct EnemyArchetype {
+    pub health: f32,
+    pub wander_speed: f32,
+    pub chase_speed: f32,
+    /// Distance up to which the enemy can see a character.
+    pub aggro_range: f32,
+    /// Width of the sight cone, in degrees.
+    #[serde(default = "default_field_of_view")]
+    pub field_of_view: f32,
+    /// Seconds a character stays remembered after it was last seen or heard.
+    #[serde(default = "default_memory_seconds")]
+    pub memory_seconds: f32,
+    pub damage: f32,
+    pub attack_range: f32,
+    /// Seconds between the end of an attack and the start of the next one.
+    pub attack_cooldown: f32,
+    /// Seconds an attack is telegraphed before it strikes.
+    #[serde(default = "default_windup_seconds")]
+    pub windup_seconds: f32,
+    /// Seconds the melee hitbox stays active.
+    #[serde(default = "default_strike_seconds")]
+    pub strike_seconds: f32,
+    /// Seconds the enemy is stunned after being hit.
+    #[serde(default = "default_stagger_seconds")]
+    pub stagger_seconds: f32,
+    /// Impulse applied to the enemy when it is hit.
+    #[serde(default = "default_knockback")]
+    pub knockback: f32,
+    pub attack: EnemyAttackKind,
+    /// Decision making of the enemy. Attacks what it sees and wanders otherwise when missing.
+    #[serde(default = "default_behavior_tree")]
+    pub behavior: BehaviorNode,
+    pub collider: EnemyCollider,
+    /// Parts of the enemy, such as its head, taking more damage than the rest of the body.
+    #[serde(default)]
+    pub weak_points: Vec<WeakPointDefinition>,
+    /// Phases and special attacks, for boss enemies.
+    #[serde(default)]
+    pub boss: Option<BossDefinition>,
+    /// Scene to use as the model. A capsule matching the collider is used when missing.
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default = "default_model_scale")]
+    pub model_scale: f32,
+    /// `.clips.json` file with the animations of the model.
+    #[serde(default)]
+    pub animations: Option<String>,
+    /// Color of the capsule model.
+    #[serde(default = "default_color")]
+    pub color: [f32; 3],
+    /// Points awarded for killing the enemy.
+    pub score: u32,
+    /// Pickups the enemy may leave behind when it dies.
+    #[serde(default)]
+    pub drops: Vec<PickupDrop>,
+}
+
+#[derive(Deserialize)]
+pub enum EnemyAttackKind {
+    Melee,
+    Ranged { projectile_speed: f32 },
+}
+
+/// Upright capsule standing on the ground.
+#[derive(Deserialize)]
+pub struct EnemyCollider {
+    pub radius: f32,
+    pub height: f32,
+}
+
+#[derive(Deserialize)]
+pub struct WeakPointDefinition {
+    /// Center of the part, relative to the feet of the enemy facing +Z.
+    pub offset: [f32; 3],
+    pub radius: f32,
+    /// Multiplier of the damage of projectiles hitting the part.
+    pub damage_multiplier: f32,
+}
+
+fn default_model_scale() -> f32 {
+    1.0
+}
+
+fn default_windup_seconds() -> f32 {
+    0.4
+}
+
+fn default_strike_seconds() -> f32 {
+    0.15
+}
+
+fn default_stagger_seconds() -> f32 {
+    0.3
+}
+
+fn default_knockback() -> f32 {
+    3.0
+}
+
+fn default_field_of_view() -> f32 {
+    120.0
+}
+
+fn default_memory_seconds() -> f32 {
+    5.0
+}
+
+fn default_color() -> [f32; 3] {
+    [0.25, 0.25, 0.25]
+}
+
+impl EnemyArchetype {
+    pub fn collider_shape(&self) -> SharedShape {
+        let EnemyCollider { radius, height } = self.collider;
+
+        SharedShape::capsule(
+            point!(0.0, radius, 0.0),
+            point!(0.0, height.max(2.0 * radius) - radius, 0.0),
+            radius,
+        )
+    }
+
+    /// Local transform of the model, relative to the enemy body.
+    pub fn model_transform(&self) -> Transform {
+        match self.model {
+            Some(_) => Transform::from_scale(self.model_scale * Vec3::ONE),
+            // The shared capsule mesh has a radius of 0.5 and a height of 2
+            None => Transform {
+                translation: Vec3::new(0.0, self.collider.height / 2.0, 0.0),
+                scale: Vec3::new(
+                    self.collider.radius / 0.5,
+                    self.collider.height / 2.0,
+                    self.collider.radius / 0.5,
+                ),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// How close the enemy walks up to its target before stopping.
+    pub fn stop_distance(&self) -> f32 {
+        match self.attack {
+            EnemyAttackKind::Melee => self.attack_range - 0.1,
+            EnemyAttackKind::Ranged { .. } => self.attack_range * 0.8,
+        }
+    }
+}
+
+/// Names of the archetypes loaded at startup, as used by wave definitions.
+pub const ENEMY_ARCHETYPE_NAMES: &[&str] = &["default", "runner", "tank", "ranged", "boss"];
+
+/// Render handles prepared for an archetype once it has loaded.
+pub struct EnemyArchetypeVisuals {
+    pub material: Handle<StandardMaterial>,
+    pub model: Option<Handle<Scene>>,
+    pub animations: Option<Handle<EnemyAnimationSet>>,
+}
+
+pub struct EnemyArchetypes {
+    pub handles: HashMap<String, Handle<EnemyArchetype>>,
+    pub visuals: HashMap<Handle<EnemyArchetype>, EnemyArchetypeVisuals>,
+}
+
+pub(super) fn load_enemy_archetypes(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let handles = ENEMY_ARCHETYPE_NAMES
+        .iter()
+        .map(|&name| {
+            (
+                name.to_string(),
+                asset_server.load(format!("enemies/{}.enemy.json", name).as_str()),
+            )
+        })
+        .collect();
+
+    commands.insert_resource(EnemyArchetypes {
+        handles,
+        visuals: HashMap::new(),
+    });
+}
+
+pub(super) fn prepare_enemy_archetype_visuals(
+    asset_server: Res<AssetServer>,
+    archetypes: Res<Assets<EnemyArchetype>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut enemy_archetypes: ResMut<EnemyArchetypes>,
+    mut archetype_events: EventReader<AssetEvent<EnemyArchetype>>,
+) {
+    for event in archetype_events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                let archetype = match archetypes.get(handle) {
+                    Some(archetype) => archetype,
+                    None => continue,
+                };
+                let [r, g, b] = archetype.color;
+
+                enemy_archetypes.visuals.insert(
+                    handle.clone(),
+                    EnemyArchetypeVisuals {
+                        material: materials.add(Color::rgb(r, g, b).into()),
+                        model: archetype
+                            .model
+                            .as_ref()
+                            .map(|model| asset_server.load(model.as_str())),
+                        animations: archetype
+                            .animations
+                            .as_ref()
+                            .map(|animations| asset_server.load(animations.as_str())),
+                    },
+                );
+            }
+            AssetEvent::Removed { handle } => {
+                enemy_archetypes.visuals.remove(handle);
+            }
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_easings::{Ease, EaseFunction};
+use bevy_rapier3d::{prelude::*, rapier::parry::shape::Ball};
+
+use super::{
+    archetype::{EnemyArchetype, EnemyAttackKind},
+    behavior_tree::EnemyBrain,
+    Enemy, EnemyBehavior, EnemyModel, EnemyProjectile, EnemyProjectileBundle, EnemyResources,
+};
+use crate::{
+    damage::{DamageEvent, PlayerCharacter},
+    despawn::DespawnAfter,
+    resources::SimulationSettings,
+    PhysicsFlags, Tick,
+};
+
+/// Progress of an enemy through its current attack.
+///
+/// An attack winds up for `windup_seconds`, during which the target can step out of reach, then
+/// strikes for `strike_seconds` with a hitbox in front of the enemy. The next attack can start
+/// `attack_cooldown` seconds after the strike ends.
+pub enum EnemyAttackState {
+    Ready { from_tick: usize },
+    WindUp { target: Entity, strike_tick: usize },
+    Strike { until_tick: usize, landed: bool },
+}
+
+impl Default for EnemyAttackState {
+    fn default() -> Self {
+        EnemyAttackState::Ready { from_tick: 0 }
+    }
+}
+
+impl EnemyAttackState {
+    pub fn is_attacking(&self) -> bool {
+        !matches!(self, EnemyAttackState::Ready { .. })
+    }
+
+    /// Aborts a wind-up or strike, for example when the enemy is hit.
+    pub fn interrupt(&mut self, tick: usize) {
+        if self.is_attacking() {
+            *self = EnemyAttackState::Ready { from_tick: tick };
+        }
+    }
+}
+
+/// Moments of an enemy's attacks and hit reactions, for animations to follow.
+pub struct EnemyAnimationEvent {
+    pub enemy: Entity,
+    pub animation: EnemyAnimation,
+}
+
+pub enum EnemyAnimation {
+    WindUp { duration: f32 },
+    Strike { duration: f32 },
+    Hit { direction: Vec3 },
+}
+
+/// Height of the enemy melee hitbox and ranged muzzle relative to the collider height.
+const ATTACK_HEIGHT: f32 = 0.75;
+/// Height aimed at by ranged attacks.
+const CHARACTER_CHEST_HEIGHT: f32 = 1.0;
+/// Seconds an enemy projectile flies before vanishing.
+const ENEMY_PROJECTILE_LIFETIME: f32 = 3.0;
+
+pub(super) fn enemy_attack(
+    mut commands: Commands,
+    tick: Res<Tick>,
+    simulation_settings: Res<SimulationSettings>,
+    resources: Res<EnemyResources>,
+    archetypes: Res<Assets<EnemyArchetype>>,
+    query_pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    mut animation_events: EventWriter<EnemyAnimationEvent>,
+    mut damage_events: EventWriter<DamageEvent>,
+    character_query: Query<&Transform, With<PlayerCharacter>>,
+    mut enemy_query: Query<(
+        Entity,
+        &Enemy,
+        &EnemyBehavior,
+        &EnemyBrain,
+        &RigidBodyPosition,
+        &mut EnemyAttackState,
+    )>,
+) {
+    let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+
+    for (entity, enemy, behavior, brain, position, mut state) in enemy_query.iter_mut() {
+        let archetype = match archetypes.get(&enemy.archetype) {
+            Some(archetype) => archetype,
+            None => continue,
+        };
+
+        let target = match *behavior {
+            EnemyBehavior::Attack(target) if tick.0 >= brain.stagger_until_tick => target,
+            _ => {
+                state.interrupt(tick.0);
+                continue;
+            }
+        };
+
+        let translation: Vec3 = position.position.translation.into();
+        let forward: Vec3 = (position.position.rotation * Vector::z()).into();
+        let attack_origin = translation + Vec3::Y * archetype.collider.height * ATTACK_HEIGHT;
+
+        match *state {
+            EnemyAttackState::Ready { from_tick } => {
+                let character_transform = match character_query.get(target) {
+                    Ok(transform) => *transform,
+                    Err(_) => continue,
+                };
+
+                if tick.0 < from_tick
+                    || translation.distance(character_transform.translation)
+                        > archetype.attack_range
+                {
+                    continue;
+                }
+
+                *state = EnemyAttackState::WindUp {
+                    target,
+                    strike_tick: tick.0 + simulation_settings.ticks(archetype.windup_seconds),
+                };
+
+                animation_events.send(EnemyAnimationEvent {
+                    enemy: entity,
+                    animation: EnemyAnimation::WindUp {
+                        duration: archetype.windup_seconds,
+                    },
+                });
+            }
+            EnemyAttackState::WindUp {
+                target: windup_target,
+                strike_tick,
+            } => {
+                if windup_target != target {
+                    state.interrupt(tick.0);
+                    continue;
+                }
+
+                if tick.0 < strike_tick {
+                    continue;
+                }
+
+                *state = EnemyAttackState::Strike {
+                    until_tick: tick.0 + simulation_settings.ticks(archetype.strike_seconds),
+                    landed: false,
+                };
+
+                animation_events.send(EnemyAnimationEvent {
+                    enemy: entity,
+                    animation: EnemyAnimation::Strike {
+                        duration: archetype.strike_seconds,
+                    },
+                });
+
+                if let EnemyAttackKind::Ranged { projectile_speed } = archetype.attack {
+                    // Aim where the target is when the strike starts, not when the wind-up began
+                    let target_position = match character_query.get(target) {
+                        Ok(transform) => transform.translation,
+                        Err(_) => continue,
+                    };
+                    let direction = (target_position + Vec3::Y * CHARACTER_CHEST_HEIGHT
+                        - attack_origin)
+                        .normalize_or_zero();
+
+                    spawn_enemy_projectile(
+                        &mut commands,
+                        &resources,
+                        &simulation_settings,
+                        attack_origin,
+                        direction * projectile_speed,
+                        archetype.damage,
+                    );
+                }
+            }
+            EnemyAttackState::Strike { until_tick, landed } => {
+                if let (EnemyAttackKind::Melee, false) = (&archetype.attack, landed) {
+                    let hitbox_radius = archetype.attack_range / 2.0;
+                    let hitbox_center = attack_origin + forward * hitbox_radius;
+                    let hitbox_position =
+                        Isometry::translation(hitbox_center.x, hitbox_center.y, hitbox_center.z);
+                    let hitbox = Ball::new(hitbox_radius);
+                    let mut struck = Vec::new();
+
+                    query_pipeline.intersections_with_shape(
+                        &collider_set,
+                        &hitbox_position,
+                        &hitbox,
+                        InteractionGroups::new(u32::MAX, PhysicsFlags::PLAYER.bits()),
+                        None,
+                        |handle| {
+                            struck.push(handle.entity());
+                            true
+                        },
+                    );
+
+                    for character in struck {
+                        if character_query.get(character).is_ok() {
+                            damage_events.send(DamageEvent {
+                                target: character,
+                                attacker: None,
+                                amount: archetype.damage,
+                                position: hitbox_center.into(),
+                                direction: UnitVector::new_normalize(forward.into()),
+                            });
+
+                            *state = EnemyAttackState::Strike {
+                                until_tick,
+                                landed: true,
+                            };
+                        }
+                    }
+                }
+
+                if tick.0 >= until_tick {
+                    *state = EnemyAttackState::Ready {
+                        from_tick: tick.0 + simulation_settings.ticks(archetype.attack_cooldown),
+                    };
+                }
+            }
+        }
+    }
+}
+
+fn spawn_enemy_projectile(
+    commands: &mut Commands,
+    resources: &EnemyResources,
+    simulation_settings: &SimulationSettings,
+    origin: Vec3,
+    velocity: Vec3,
+    damage: f32,
+) {
+    commands.spawn_bundle(EnemyProjectileBundle {
+        projectile: EnemyProjectile { damage },
+        despawn_after: DespawnAfter(simulation_settings.ticks(ENEMY_PROJECTILE_LIFETIME)),
+        pbr: PbrBundle {
+            mesh: resources.projectile_mesh.clone(),
+            material: resources.projectile_material.clone(),
+            ..Default::default()
+        },
+        rigid_body: RigidBodyBundle {
+            body_type: RigidBodyType::Dynamic,
+            position: origin.into(),
+            velocity: RigidBodyVelocity {
+                linvel: velocity.into(),
+                ..Default::default()
+            },
+            forces: RigidBodyForces {
+                gravity_scale: 0.0,
+                ..Default::default()
+            },
+            ccd: RigidBodyCcd {
+                ccd_enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        collider: ColliderBundle {
+            collider_type: ColliderType::Sensor,
+            shape: resources.projectile_shape.clone(),
+            flags: ColliderFlags {
+                active_events: ActiveEvents::INTERSECTION_EVENTS,
+                collision_groups: InteractionGroups::new(
+                    PhysicsFlags::ENEMY_PROJECTILE.bits(),
+                    (PhysicsFlags::PLAYER | PhysicsFlags::ENVIRONMENT).bits(),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        rigid_body_position_sync: RigidBodyPositionSync::Discrete,
+    });
+}
+
+/// Cosmetic easing of the enemy model following attack and hit animation events.
+pub(super) fn animate_enemy_attacks(
+    mut commands: Commands,
+    mut animation_events: EventReader<EnemyAnimationEvent>,
+    enemy_query: Query<&Children, With<Enemy>>,
+    model_query: Query<&EnemyModel>,
+) {
+    for event in animation_events.iter() {
+        let model_entity = match enemy_query.get(event.enemy) {
+            Ok(children) => children[0],
+            Err(_) => continue,
+        };
+        let rest = match model_query.get(model_entity) {
+            Ok(model) => model.rest,
+            Err(_) => continue,
+        };
+
+        let easing = match event.animation {
+            EnemyAnimation::WindUp { duration } => {
+                let mut pulled_back = rest;
+                pulled_back.translation.z -= 0.15;
+
+                rest.ease_to(
+                    pulled_back,
+                    EaseFunction::QuadraticOut,
+                    bevy_easings::EasingType::Once {
+                        duration: Duration::from_secs_f32(duration.max(0.01)),
+                    },
+                )
+            }
+            EnemyAnimation::Strike { duration } => {
+                let mut lunge = rest;
+                lunge.translation.z += 0.2;
+
+                rest.ease_to(
+                    lunge,
+                    EaseFunction::BackIn,
+                    bevy_easings::EasingType::Once {
+                        duration: Duration::from_secs_f32(0.1),
+                    },
+                )
+                .ease_to(
+                    rest,
+                    EaseFunction::CubicOut,
+                    bevy_easings::EasingType::Once {
+                        duration: Duration::from_secs_f32(duration.max(0.1)),
+                    },
+                )
+            }
+            EnemyAnimation::Hit { .. } => {
+                let recoil = Transform {
+                    rotation: rest.rotation * Quat::from_rotation_x(-0.3),
+                    ..rest
+                };
+
+                rest.ease_to(
+                    recoil,
+                    EaseFunction::QuadraticOut,
+                    bevy_easings::EasingType::Once {
+                        duration: Duration::from_secs_f32(0.05),
+                    },
+                )
+                .ease_to(
+                    rest,
+                    EaseFunction::QuadraticIn,
+                    bevy_easings::EasingType::Once {
+                        duration: Duration::from_secs_f32(0.2),
+                    },
+                )
+            }
+        };
+
+        commands.entity(model_entity).insert(easing);
+    }
+}
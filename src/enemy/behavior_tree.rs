@@ -0,0 +1,351 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+use bevy_rapier3d::prelude::*;
+use rand::Rng;
+use rand_pcg::Pcg64Mcg;
+use serde::Deserialize;
+
+use super::{
+    archetype::EnemyArchetype,
+    navigation::{horizontal_distance, EnemyPath, NavigationGrid, NavigationGrids},
+    perception::EnemyPerception,
+    Enemy, EnemyBehavior,
+};
+use crate::{random::seeded_generator, Health, Tick};
+
+/// Node of an enemy behavior tree, as written in the `behavior` field of an archetype.
+///
+/// The tree is evaluated from the root on every AI tick. Leaves pick the `EnemyBehavior` that
+/// the movement and attack systems carry out until the next evaluation.
+#[derive(Debug, Deserialize)]
+pub enum BehaviorNode {
+    /// Runs children in order until one does not fail.
+    Selector(Vec<BehaviorNode>),
+    /// Runs children in order until one does not succeed.
+    Sequence(Vec<BehaviorNode>),
+    Condition(BehaviorCondition),
+    /// Attacks the current target while it is in sight.
+    Attack,
+    /// Walks to where the current target was last seen or heard.
+    MoveTo,
+    /// Walks between random destinations. Never finishes, but fails if no walkable destination is
+    /// found.
+    Wander,
+    /// Runs the given distance away from the current target.
+    Flee {
+        distance: f32,
+    },
+    /// Stands still while the enemy recovers from a hit.
+    Stagger,
+    Idle,
+}
+
+#[derive(Debug, Deserialize)]
+pub enum BehaviorCondition {
+    TargetVisible,
+    TargetRemembered,
+    /// Current target is closer than the given distance.
+    TargetCloserThan(f32),
+    /// Health is below the given fraction of the maximum.
+    HealthBelow(f32),
+    Staggered,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BehaviorStatus {
+    Success,
+    Failure,
+    Running,
+}
+
+impl BehaviorNode {
+    fn name(&self) -> &'static str {
+        match self {
+            BehaviorNode::Selector(_) => "Selector",
+            BehaviorNode::Sequence(_) => "Sequence",
+            BehaviorNode::Condition(BehaviorCondition::TargetVisible) => "TargetVisible?",
+            BehaviorNode::Condition(BehaviorCondition::TargetRemembered) => "TargetRemembered?",
+            BehaviorNode::Condition(BehaviorCondition::TargetCloserThan(_)) => "TargetCloserThan?",
+            BehaviorNode::Condition(BehaviorCondition::HealthBelow(_)) => "HealthBelow?",
+            BehaviorNode::Condition(BehaviorCondition::Staggered) => "Staggered?",
+            BehaviorNode::Attack => "Attack",
+            BehaviorNode::MoveTo => "MoveTo",
+            BehaviorNode::Wander => "Wander",
+            BehaviorNode::Flee { .. } => "Flee",
+            BehaviorNode::Stagger => "Stagger",
+            BehaviorNode::Idle => "Idle",
+        }
+    }
+}
+
+/// Tree used by archetypes that do not define their own.
+pub fn default_behavior_tree() -> BehaviorNode {
+    BehaviorNode::Selector(vec![
+        BehaviorNode::Sequence(vec![
+            BehaviorNode::Condition(BehaviorCondition::Staggered),
+            BehaviorNode::Stagger,
+        ]),
+        BehaviorNode::Sequence(vec![
+            BehaviorNode::Condition(BehaviorCondition::TargetVisible),
+            BehaviorNode::Attack,
+        ]),
+        BehaviorNode::Sequence(vec![
+            BehaviorNode::Condition(BehaviorCondition::TargetRemembered),
+            BehaviorNode::MoveTo,
+        ]),
+        BehaviorNode::Wander,
+    ])
+}
+
+/// Per-enemy state of the behavior tree.
+#[derive(Default)]
+pub struct EnemyBrain {
+    /// The enemy is staggered until this tick.
+    pub stagger_until_tick: usize,
+    /// Nodes visited during the last evaluation, for the debug window.
+    pub trace: Vec<BehaviorTraceEntry>,
+}
+
+pub struct BehaviorTraceEntry {
+    pub depth: usize,
+    pub name: &'static str,
+    pub status: BehaviorStatus,
+}
+
+/// Ticks between two evaluations of the behavior trees.
+pub const BEHAVIOR_TREE_INTERVAL_TICKS: usize = 6;
+
+/// Bounds of the random destinations picked by `Wander`.
+const WANDER_EXTENT: f32 = 24.5;
+
+/// Random destinations tried by `Wander` before giving up until the next evaluation.
+const WANDER_ATTEMPTS: usize = 16;
+
+struct BehaviorContext<'a> {
+    tick: usize,
+    position: Vec3,
+    health_fraction: f32,
+    perception: &'a EnemyPerception,
+    path: &'a EnemyPath,
+    navigation_grid: &'a NavigationGrid,
+    /// Seeded per enemy and tick, so draws do not depend on the order enemies are evaluated in.
+    generator: Pcg64Mcg,
+    behavior: &'a mut EnemyBehavior,
+    brain: &'a mut EnemyBrain,
+}
+
+impl BehaviorContext<'_> {
+    fn evaluate(&mut self, node: &BehaviorNode, depth: usize) -> BehaviorStatus {
+        let trace_index = self.brain.trace.len();
+        self.brain.trace.push(BehaviorTraceEntry {
+            depth,
+            name: node.name(),
+            status: BehaviorStatus::Running,
+        });
+
+        let status = match node {
+            BehaviorNode::Selector(children) => children
+                .iter()
+                .map(|child| self.evaluate(child, depth + 1))
+                .find(|&status| status != BehaviorStatus::Failure)
+                .unwrap_or(BehaviorStatus::Failure),
+            BehaviorNode::Sequence(children) => children
+                .iter()
+                .map(|child| self.evaluate(child, depth + 1))
+                .find(|&status| status != BehaviorStatus::Success)
+                .unwrap_or(BehaviorStatus::Success),
+            BehaviorNode::Condition(condition) => {
+                if self.check(condition) {
+                    BehaviorStatus::Success
+                } else {
+                    BehaviorStatus::Failure
+                }
+            }
+            BehaviorNode::Attack => match self.perception.target_memory() {
+                Some(memory) if memory.visible => {
+                    *self.behavior = EnemyBehavior::Attack(memory.entity);
+                    BehaviorStatus::Running
+                }
+                _ => BehaviorStatus::Failure,
+            },
+            BehaviorNode::MoveTo => match self.perception.target_memory() {
+                Some(memory) => {
+                    let destination = memory.last_known_position;
+
+                    if horizontal_distance(self.position, destination) <= EnemyPath::WAYPOINT_RADIUS
+                    {
+                        BehaviorStatus::Success
+                    } else {
+                        *self.behavior = EnemyBehavior::Investigate(destination);
+                        BehaviorStatus::Running
+                    }
+                }
+                None => BehaviorStatus::Failure,
+            },
+            BehaviorNode::Wander => self.wander(),
+            BehaviorNode::Flee { distance } => self.flee(*distance),
+            BehaviorNode::Stagger => {
+                if self.tick < self.brain.stagger_until_tick {
+                    *self.behavior = EnemyBehavior::Stagger;
+                    BehaviorStatus::Running
+                } else {
+                    BehaviorStatus::Success
+                }
+            }
+            BehaviorNode::Idle => {
+                *self.behavior = EnemyBehavior::Idle;
+                BehaviorStatus::Success
+            }
+        };
+
+        self.brain.trace[trace_index].status = status;
+        status
+    }
+
+    fn check(&self, condition: &BehaviorCondition) -> bool {
+        match *condition {
+            BehaviorCondition::TargetVisible => self
+                .perception
+                .target_memory()
+                .map_or(false, |memory| memory.visible),
+            BehaviorCondition::TargetRemembered => self.perception.target_memory().is_some(),
+            BehaviorCondition::TargetCloserThan(distance) => {
+                self.perception.target_memory().map_or(false, |memory| {
+                    horizontal_distance(self.position, memory.last_known_position) < distance
+                })
+            }
+            BehaviorCondition::HealthBelow(fraction) => self.health_fraction < fraction,
+            BehaviorCondition::Staggered => self.tick < self.brain.stagger_until_tick,
+        }
+    }
+
+    fn wander(&mut self) -> BehaviorStatus {
+        if let EnemyBehavior::Wander(destination) = *self.behavior {
+            if horizontal_distance(self.position, destination) > EnemyPath::WAYPOINT_RADIUS
+                && !self.path.is_unreachable()
+            {
+                return BehaviorStatus::Running;
+            }
+        }
+
+        let generator = &mut self.generator;
+        let navigation_grid = self.navigation_grid;
+        let destination = (0..WANDER_ATTEMPTS)
+            .map(|_| {
+                Vec3::new(
+                    generator.gen_range(-WANDER_EXTENT..WANDER_EXTENT),
+                    0.0,
+                    generator.gen_range(-WANDER_EXTENT..WANDER_EXTENT),
+                )
+            })
+            .find(|&destination| navigation_grid.is_walkable(destination));
+
+        match destination {
+            Some(destination) => {
+                *self.behavior = EnemyBehavior::Wander(destination);
+                BehaviorStatus::Running
+            }
+            None => BehaviorStatus::Failure,
+        }
+    }
+
+    fn flee(&mut self, distance: f32) -> BehaviorStatus {
+        let threat = match self.perception.target_memory() {
+            Some(memory) => memory.last_known_position,
+            None => return BehaviorStatus::Failure,
+        };
+
+        let away = Vec3::new(self.position.x - threat.x, 0.0, self.position.z - threat.z)
+            .try_normalize()
+            .unwrap_or(Vec3::X);
+
+        // Shorten the run until it ends somewhere walkable
+        let destination = (1..=4)
+            .rev()
+            .map(|step| self.position + away * distance * step as f32 / 4.0)
+            .find(|&destination| self.navigation_grid.is_walkable(destination));
+
+        match destination {
+            Some(destination) => {
+                *self.behavior = EnemyBehavior::Flee(destination);
+                BehaviorStatus::Running
+            }
+            None => BehaviorStatus::Failure,
+        }
+    }
+}
+
+/// Evaluates the behavior tree of every living enemy at a fixed tick interval.
+pub(super) fn enemy_behavior_tree(
+    tick: Res<Tick>,
+    navigation_grids: Res<NavigationGrids>,
+    archetypes: Res<Assets<EnemyArchetype>>,
+    mut enemy_query: Query<(
+        &Enemy,
+        &Health,
+        &mut EnemyBehavior,
+        &mut EnemyBrain,
+        &EnemyPerception,
+        &EnemyPath,
+        &RigidBodyPosition,
+    )>,
+) {
+    if tick.0 % BEHAVIOR_TREE_INTERVAL_TICKS != 0 {
+        return;
+    }
+
+    for (enemy, health, mut behavior, mut brain, perception, path, position) in
+        enemy_query.iter_mut()
+    {
+        if let EnemyBehavior::Death = *behavior {
+            continue;
+        }
+
+        let archetype = match archetypes.get(&enemy.archetype) {
+            Some(archetype) => archetype,
+            None => continue,
+        };
+
+        brain.trace.clear();
+
+        let mut context = BehaviorContext {
+            tick: tick.0,
+            position: position.position.translation.into(),
+            health_fraction: health.0 / enemy.max_health,
+            perception,
+            path,
+            navigation_grid: navigation_grids.for_radius(archetype.collider.radius),
+            generator: seeded_generator(tick.0, enemy.id as u64),
+            behavior: &mut *behavior,
+            brain: &mut *brain,
+        };
+
+        context.evaluate(&archetype.behavior, 0);
+    }
+}
+
+pub(super) fn enemy_behavior_tree_window(
+    egui_context: Res<EguiContext>,
+    enemy_query: Query<(Entity, &EnemyBehavior, &EnemyBrain)>,
+) {
+    egui::Window::new("Enemy AI")
+        .default_open(false)
+        .show(egui_context.ctx(), |ui| {
+            egui::ScrollArea::auto_sized().show(ui, |ui| {
+                for (entity, behavior, brain) in enemy_query.iter() {
+                    egui::CollapsingHeader::new(format!("{:?}: {:?}", entity, behavior))
+                        .id_source(entity)
+                        .show(ui, |ui| {
+                            for entry in brain.trace.iter() {
+                                ui.label(format!(
+                                    "{}{} {:?}",
+                                    "  ".repeat(entry.depth),
+                                    entry.name,
+                                    entry.status
+                                ));
+                            }
+                        });
+                }
+            });
+        });
+}
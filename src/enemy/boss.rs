@@ -0,0 +1,441 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_rapier3d::{na::RealField, prelude::*, rapier::parry::shape::Ball};
+use serde::Deserialize;
+
+use super::{
+    archetype::{EnemyArchetype, EnemyArchetypes},
+    attack::{EnemyAnimation, EnemyAnimationEvent, EnemyAttackState},
+    navigation::NavigationGrids,
+    perception::EnemyPerception,
+    Enemy, EnemyBehavior,
+};
+use crate::{
+    damage::{DamageEvent, PlayerCharacter},
+    resources::SimulationSettings,
+    waves::SpawnEnemyEvent,
+    Health, PhysicsFlags, Tick,
+};
+
+/// Boss part of an archetype, as written in its `boss` field.
+#[derive(Deserialize)]
+pub struct BossDefinition {
+    /// Shown above the boss health bar.
+    pub name: String,
+    /// Phases in order. The first one should start at full health.
+    pub phases: Vec<BossPhase>,
+}
+
+#[derive(Deserialize)]
+pub struct BossPhase {
+    /// The phase starts once health drops to this fraction of the maximum.
+    pub health_fraction: f32,
+    /// Seconds between the end of a special attack and the start of the next one.
+    pub special_cooldown: f32,
+    /// Special attacks used in turn during the phase.
+    pub attacks: Vec<BossAttack>,
+}
+
+#[derive(Deserialize)]
+pub enum BossAttack {
+    /// Winds up, then rushes in a straight line, hurting every character in the way once.
+    Charge {
+        windup_seconds: f32,
+        speed: f32,
+        duration_seconds: f32,
+        damage: f32,
+    },
+    /// Winds up, then hurts every character within `radius`.
+    GroundSlam {
+        windup_seconds: f32,
+        radius: f32,
+        damage: f32,
+    },
+    /// Calls `count` enemies of the given type, spread in a circle around the boss.
+    Summon {
+        enemy: String,
+        count: u32,
+        radius: f32,
+    },
+}
+
+/// State of a boss enemy on top of the regular enemy components.
+#[derive(Default)]
+pub struct Boss {
+    pub phase: usize,
+    /// Index of the next special attack of the phase.
+    pub next_attack: usize,
+    pub next_special_tick: usize,
+    pub special: BossSpecial,
+}
+
+pub enum BossSpecial {
+    None,
+    ChargeWindUp {
+        attack: usize,
+        charge_tick: usize,
+    },
+    Charge {
+        attack: usize,
+        direction: Vec3,
+        until_tick: usize,
+        struck: Vec<Entity>,
+    },
+    SlamWindUp {
+        attack: usize,
+        impact_tick: usize,
+    },
+}
+
+impl Default for BossSpecial {
+    fn default() -> Self {
+        BossSpecial::None
+    }
+}
+
+/// Sent when a boss enters a new phase. Phase 0 is the one it spawns in.
+pub struct BossPhaseEvent {
+    pub boss: Entity,
+    pub phase: usize,
+}
+
+/// Seconds between the spawn of a boss and its first special attack.
+const FIRST_SPECIAL_DELAY_SECONDS: f32 = 3.0;
+
+pub(super) fn boss_phases(
+    tick: Res<Tick>,
+    simulation_settings: Res<SimulationSettings>,
+    archetypes: Res<Assets<EnemyArchetype>>,
+    mut phase_events: EventWriter<BossPhaseEvent>,
+    mut boss_query: Query<(Entity, &Enemy, &EnemyBehavior, &Health, &mut Boss), Changed<Health>>,
+) {
+    for (entity, enemy, behavior, health, mut boss) in boss_query.iter_mut() {
+        if let EnemyBehavior::Death = *behavior {
+            continue;
+        }
+
+        let definition = match archetypes
+            .get(&enemy.archetype)
+            .and_then(|archetype| archetype.boss.as_ref())
+        {
+            Some(definition) => definition,
+            None => continue,
+        };
+
+        let health_fraction = health.0 / enemy.max_health;
+
+        // Phases only move forward, even if the boss were to heal
+        let phase = definition
+            .phases
+            .iter()
+            .rposition(|phase| health_fraction <= phase.health_fraction)
+            .unwrap_or(0)
+            .max(boss.phase);
+
+        if phase == boss.phase {
+            continue;
+        }
+
+        info!("{} enters phase {}", definition.name, phase + 1);
+
+        // Open the new phase with its first special attack
+        boss.phase = phase;
+        boss.next_attack = 0;
+        boss.special = BossSpecial::None;
+        boss.next_special_tick = tick.0 + simulation_settings.ticks(0.5);
+
+        phase_events.send(BossPhaseEvent {
+            boss: entity,
+            phase,
+        });
+    }
+}
+
+/// Sends the phase event of newly spawned bosses and delays their first special attack.
+pub(super) fn boss_spawned(
+    tick: Res<Tick>,
+    simulation_settings: Res<SimulationSettings>,
+    mut phase_events: EventWriter<BossPhaseEvent>,
+    mut boss_query: Query<(Entity, &mut Boss), Added<Boss>>,
+) {
+    for (entity, mut boss) in boss_query.iter_mut() {
+        boss.next_special_tick = tick.0 + simulation_settings.ticks(FIRST_SPECIAL_DELAY_SECONDS);
+
+        phase_events.send(BossPhaseEvent {
+            boss: entity,
+            phase: 0,
+        });
+    }
+}
+
+/// Runs the special attacks of bosses.
+///
+/// Runs after movement and steering, as a charging or winding up boss overrides its own velocity.
+/// Regular attacks are held off while a special attack is in progress.
+pub(super) fn boss_special_attacks(
+    tick: Res<Tick>,
+    simulation_settings: Res<SimulationSettings>,
+    enemy_archetypes: Res<EnemyArchetypes>,
+    archetypes: Res<Assets<EnemyArchetype>>,
+    navigation_grids: Res<NavigationGrids>,
+    query_pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    mut spawn_events: EventWriter<SpawnEnemyEvent>,
+    mut animation_events: EventWriter<EnemyAnimationEvent>,
+    mut damage_events: EventWriter<DamageEvent>,
+    character_query: Query<&Transform, With<PlayerCharacter>>,
+    mut boss_query: Query<(
+        Entity,
+        &Enemy,
+        &EnemyBehavior,
+        &EnemyPerception,
+        &mut Boss,
+        &mut EnemyAttackState,
+        &mut RigidBodyPosition,
+        &mut RigidBodyVelocity,
+    )>,
+) {
+    let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+    let characters = InteractionGroups::new(u32::MAX, PhysicsFlags::PLAYER.bits());
+
+    for (entity, enemy, behavior, perception, mut boss, mut attack, mut position, mut velocity) in
+        boss_query.iter_mut()
+    {
+        if let EnemyBehavior::Death = *behavior {
+            continue;
+        }
+
+        let archetype = match archetypes.get(&enemy.archetype) {
+            Some(archetype) => archetype,
+            None => continue,
+        };
+        let phase = match archetype
+            .boss
+            .as_ref()
+            .and_then(|definition| definition.phases.get(boss.phase))
+        {
+            Some(phase) => phase,
+            None => continue,
+        };
+
+        let translation: Vec3 = position.position.translation.into();
+        let cooldown_ticks = simulation_settings.ticks(phase.special_cooldown);
+
+        match boss.special {
+            BossSpecial::None => {
+                if tick.0 < boss.next_special_tick
+                    || attack.is_attacking()
+                    || phase.attacks.is_empty()
+                {
+                    continue;
+                }
+
+                let target = match perception.target_memory() {
+                    Some(memory) if memory.visible => memory.last_known_position,
+                    _ => continue,
+                };
+
+                let attack_index = boss.next_attack % phase.attacks.len();
+                boss.next_attack = attack_index + 1;
+
+                match &phase.attacks[attack_index] {
+                    BossAttack::Charge { windup_seconds, .. } => {
+                        face(&mut position, target - translation);
+
+                        boss.special = BossSpecial::ChargeWindUp {
+                            attack: attack_index,
+                            charge_tick: tick.0 + simulation_settings.ticks(*windup_seconds),
+                        };
+
+                        animation_events.send(EnemyAnimationEvent {
+                            enemy: entity,
+                            animation: EnemyAnimation::WindUp {
+                                duration: *windup_seconds,
+                            },
+                        });
+                    }
+                    BossAttack::GroundSlam { windup_seconds, .. } => {
+                        boss.special = BossSpecial::SlamWindUp {
+                            attack: attack_index,
+                            impact_tick: tick.0 + simulation_settings.ticks(*windup_seconds),
+                        };
+
+                        animation_events.send(EnemyAnimationEvent {
+                            enemy: entity,
+                            animation: EnemyAnimation::WindUp {
+                                duration: *windup_seconds,
+                            },
+                        });
+                    }
+                    BossAttack::Summon {
+                        enemy: minion,
+                        count,
+                        radius,
+                    } => {
+                        let minion_radius = enemy_archetypes
+                            .handles
+                            .get(minion)
+                            .and_then(|handle| archetypes.get(handle))
+                            .map_or(0.0, |archetype| archetype.collider.radius);
+                        let navigation_grid = navigation_grids.for_radius(minion_radius);
+
+                        for index in 0..*count {
+                            let angle = 2.0 * PI * index as f32 / *count as f32;
+                            let spawn_position =
+                                translation + Vec3::new(angle.cos(), 0.0, angle.sin()) * *radius;
+
+                            if navigation_grid.is_walkable(spawn_position) {
+                                spawn_events.send(SpawnEnemyEvent {
+                                    enemy: minion.clone(),
+                                    position: spawn_position,
+                                });
+                            }
+                        }
+
+                        boss.next_special_tick = tick.0 + cooldown_ticks;
+                    }
+                }
+            }
+            BossSpecial::ChargeWindUp {
+                attack: attack_index,
+                charge_tick,
+            } => {
+                if tick.0 < charge_tick {
+                    velocity.linvel = Vector::zeros();
+                    continue;
+                }
+
+                if let BossAttack::Charge {
+                    duration_seconds, ..
+                } = phase.attacks[attack_index]
+                {
+                    let direction: Vec3 = (position.position.rotation * Vector::z()).into();
+
+                    boss.special = BossSpecial::Charge {
+                        attack: attack_index,
+                        direction,
+                        until_tick: tick.0 + simulation_settings.ticks(duration_seconds),
+                        struck: Vec::new(),
+                    };
+                }
+            }
+            BossSpecial::Charge {
+                attack: attack_index,
+                direction,
+                until_tick,
+                ref mut struck,
+            } => {
+                let (speed, damage) = match phase.attacks[attack_index] {
+                    BossAttack::Charge { speed, damage, .. } => (speed, damage),
+                    _ => continue,
+                };
+
+                let hitbox_center =
+                    translation + Vec3::Y * archetype.collider.height / 2.0 + direction * 0.5;
+                let hitbox_position =
+                    Isometry::translation(hitbox_center.x, hitbox_center.y, hitbox_center.z);
+                let mut hit = Vec::new();
+
+                query_pipeline.intersections_with_shape(
+                    &collider_set,
+                    &hitbox_position,
+                    &Ball::new(archetype.collider.radius + 0.5),
+                    characters,
+                    None,
+                    |handle| {
+                        hit.push(handle.entity());
+                        true
+                    },
+                );
+
+                for character in hit {
+                    if struck.contains(&character) {
+                        continue;
+                    }
+
+                    if character_query.get(character).is_ok() {
+                        damage_events.send(DamageEvent {
+                            target: character,
+                            attacker: None,
+                            amount: damage,
+                            position: hitbox_center.into(),
+                            direction: UnitVector::new_normalize(direction.into()),
+                        });
+                        struck.push(character);
+                    }
+                }
+
+                if tick.0 >= until_tick {
+                    boss.special = BossSpecial::None;
+                    boss.next_special_tick = tick.0 + cooldown_ticks;
+                    velocity.linvel = Vector::zeros();
+                } else {
+                    velocity.linvel = (direction * speed).into();
+                }
+            }
+            BossSpecial::SlamWindUp {
+                attack: attack_index,
+                impact_tick,
+            } => {
+                velocity.linvel = Vector::zeros();
+
+                if tick.0 < impact_tick {
+                    continue;
+                }
+
+                if let BossAttack::GroundSlam { radius, damage, .. } = phase.attacks[attack_index] {
+                    let mut hit = Vec::new();
+
+                    query_pipeline.intersections_with_shape(
+                        &collider_set,
+                        &Isometry::translation(translation.x, translation.y, translation.z),
+                        &Ball::new(radius),
+                        characters,
+                        None,
+                        |handle| {
+                            hit.push(handle.entity());
+                            true
+                        },
+                    );
+
+                    for character in hit {
+                        if let Ok(transform) = character_query.get(character) {
+                            // Away from the boss, straight up for characters right on top of it
+                            let away: Vector<f32> = (transform.translation - translation).into();
+
+                            damage_events.send(DamageEvent {
+                                target: character,
+                                attacker: None,
+                                amount: damage,
+                                position: transform.translation.into(),
+                                direction: UnitVector::try_new(away, f32::EPSILON)
+                                    .unwrap_or_else(Vector::y_axis),
+                            });
+                        }
+                    }
+
+                    animation_events.send(EnemyAnimationEvent {
+                        enemy: entity,
+                        animation: EnemyAnimation::Strike { duration: 0.3 },
+                    });
+                }
+
+                boss.special = BossSpecial::None;
+                boss.next_special_tick = tick.0 + cooldown_ticks;
+            }
+        }
+
+        // No regular attack may start on top of a special one
+        if !matches!(boss.special, BossSpecial::None) {
+            attack.interrupt(tick.0);
+        }
+    }
+}
+
+fn face(position: &mut RigidBodyPosition, direction: Vec3) {
+    position.position.rotation = Rotation::from_axis_angle(
+        &UnitVector::new_unchecked(Vector::y()),
+        RealField::atan2(direction.x, direction.z),
+    );
+}
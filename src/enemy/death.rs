@@ -0,0 +1,312 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use super::{
+    animation::{EnemyAnimationSet, EnemyAnimator, EnemyClip},
+    archetype::{EnemyArchetype, EnemyArchetypes},
+    Enemy, EnemyResources,
+};
+use crate::{resources::SimulationSettings, PhysicsFlags, Tick};
+
+pub struct EnemyDeathEvent {
+    pub enemy: Entity,
+    pub position: Point<f32>,
+    pub direction: UnitVector<f32>,
+}
+
+/// Limits on the number of dead enemies kept around and how long they stay.
+pub struct CorpseSettings {
+    pub max_corpses: usize,
+    pub lifetime_seconds: f32,
+    pub fade_seconds: f32,
+}
+
+impl Default for CorpseSettings {
+    fn default() -> Self {
+        Self {
+            max_corpses: 16,
+            lifetime_seconds: 10.0,
+            fade_seconds: 1.5,
+        }
+    }
+}
+
+/// A dead enemy, along with the ragdoll bodies and joints attached to it.
+pub struct Corpse {
+    pub died_tick: usize,
+    pub parts: Vec<Entity>,
+    /// Tick the fade-out started, after which the corpse is despawned.
+    pub fade_start_tick: Option<usize>,
+}
+
+/// Impulse applied to ragdolls along the direction of the killing shot.
+const DEATH_IMPULSE: f32 = 20.0;
+
+/// Turns newly dead enemies into corpses.
+///
+/// Enemies with a death animation play it where they died. The others fall as a ragdoll: the body
+/// capsule with a head and two arms hanging from ball joints.
+pub(super) fn enemy_death(
+    mut commands: Commands,
+    tick: Res<Tick>,
+    resources: Res<EnemyResources>,
+    enemy_archetypes: Res<EnemyArchetypes>,
+    archetypes: Res<Assets<EnemyArchetype>>,
+    animation_sets: Res<Assets<EnemyAnimationSet>>,
+    mut death_events: EventReader<EnemyDeathEvent>,
+    mut enemy_query: Query<(
+        &Enemy,
+        &RigidBodyPosition,
+        &mut RigidBodyMassProps,
+        &mut RigidBodyVelocity,
+        &mut ColliderFlags,
+        Option<&EnemyAnimator>,
+    )>,
+) {
+    for death_event in death_events.iter() {
+        let (enemy, position, mut mass_props, mut velocity, mut collider_flags, animator) =
+            match enemy_query.get_mut(death_event.enemy) {
+                Ok(components) => components,
+                Err(_) => continue,
+            };
+
+        collider_flags.collision_groups.filter = PhysicsFlags::ENVIRONMENT.bits();
+
+        let mut parts = Vec::new();
+        let death_animation = animator
+            .and_then(|animator| animation_sets.get(&animator.animations))
+            .map_or(false, |animation_set| {
+                animation_set.clips.contains_key(&EnemyClip::Death)
+            });
+
+        if death_animation {
+            velocity.linvel = Vector::zeros();
+            velocity.angvel = Vector::zeros();
+        } else {
+            mass_props.flags = RigidBodyMassPropsFlags::empty();
+            velocity.apply_impulse_at_point(
+                &mass_props,
+                death_event.direction.scale(DEATH_IMPULSE),
+                death_event.position,
+            );
+
+            let archetype = archetypes.get(&enemy.archetype);
+            let visuals = enemy_archetypes.visuals.get(&enemy.archetype);
+
+            if let (Some(archetype), Some(visuals)) = (archetype, visuals) {
+                parts = spawn_ragdoll_limbs(
+                    &mut commands,
+                    &resources,
+                    &visuals.material,
+                    death_event.enemy,
+                    &position.position,
+                    archetype,
+                );
+            }
+        }
+
+        commands.entity(death_event.enemy).insert(Corpse {
+            died_tick: tick.0,
+            parts,
+            fade_start_tick: None,
+        });
+    }
+}
+
+fn spawn_ragdoll_limbs(
+    commands: &mut Commands,
+    resources: &EnemyResources,
+    material: &Handle<StandardMaterial>,
+    body: Entity,
+    body_position: &Isometry<f32>,
+    archetype: &EnemyArchetype,
+) -> Vec<Entity> {
+    let radius = archetype.collider.radius;
+    let height = archetype.collider.height;
+    let head_radius = 0.6 * radius;
+    let arm_radius = 0.3 * radius;
+    let arm_length = 0.35 * height;
+    let shoulder_height = 0.75 * height;
+
+    let mut parts = Vec::new();
+    let mut spawn_limb = |commands: &mut Commands,
+                          shape: SharedShape,
+                          mesh: Handle<Mesh>,
+                          mesh_scale: Vec3,
+                          body_anchor: Point<f32>,
+                          limb_anchor: Point<f32>| {
+        let limb_position = body_position
+            * Isometry::translation(
+                body_anchor.x - limb_anchor.x,
+                body_anchor.y - limb_anchor.y,
+                body_anchor.z - limb_anchor.z,
+            );
+
+        let limb = commands
+            .spawn_bundle(RigidBodyBundle {
+                body_type: RigidBodyType::Dynamic,
+                position: limb_position.into(),
+                damping: RigidBodyDamping {
+                    linear_damping: 1.0,
+                    angular_damping: 1.0,
+                },
+                ..Default::default()
+            })
+            .insert_bundle(ColliderBundle {
+                shape,
+                flags: ColliderFlags {
+                    collision_groups: InteractionGroups::new(
+                        PhysicsFlags::ENEMY.bits(),
+                        PhysicsFlags::ENVIRONMENT.bits(),
+                    ),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(Transform::default())
+            .insert(GlobalTransform::default())
+            .insert(RigidBodyPositionSync::Discrete)
+            .with_children(|parent| {
+                parent.spawn_bundle(PbrBundle {
+                    mesh,
+                    material: material.clone(),
+                    transform: Transform::from_scale(mesh_scale),
+                    ..Default::default()
+                });
+            })
+            .id();
+
+        let joint = commands
+            .spawn()
+            .insert(JointBuilderComponent::new(
+                BallJoint::new(body_anchor, limb_anchor),
+                body,
+                limb,
+            ))
+            .id();
+
+        parts.push(limb);
+        parts.push(joint);
+    };
+
+    spawn_limb(
+        commands,
+        SharedShape::ball(head_radius),
+        resources.ragdoll_head_mesh.clone(),
+        Vec3::ONE * head_radius,
+        point![0.0, height, 0.0],
+        point![0.0, -head_radius, 0.0],
+    );
+
+    for &side in [-1.0, 1.0].iter() {
+        let half_segment = arm_length / 2.0 - arm_radius;
+
+        spawn_limb(
+            commands,
+            SharedShape::capsule(
+                point![0.0, -half_segment, 0.0],
+                point![0.0, half_segment, 0.0],
+                arm_radius,
+            ),
+            resources.enemy_mesh.clone(),
+            // The enemy capsule mesh is 1 wide and 2 tall
+            Vec3::new(2.0 * arm_radius, arm_length / 2.0, 2.0 * arm_radius),
+            point![side * (radius + arm_radius), shoulder_height, 0.0],
+            point![0.0, arm_length / 2.0, 0.0],
+        );
+    }
+
+    parts
+}
+
+/// Starts fading corpses that outlived their lifetime, oldest first once there are too many.
+pub(super) fn limit_corpses(
+    tick: Res<Tick>,
+    settings: Res<CorpseSettings>,
+    simulation_settings: Res<SimulationSettings>,
+    mut corpse_query: Query<&mut Corpse>,
+) {
+    let lifetime_ticks = simulation_settings.ticks(settings.lifetime_seconds);
+
+    let mut remaining: Vec<_> = corpse_query
+        .iter_mut()
+        .filter(|corpse| corpse.fade_start_tick.is_none())
+        .collect();
+
+    remaining.sort_by_key(|corpse| corpse.died_tick);
+
+    let excess = remaining.len().saturating_sub(settings.max_corpses);
+
+    for (index, corpse) in remaining.iter_mut().enumerate() {
+        if index < excess || corpse.died_tick + lifetime_ticks <= tick.0 {
+            corpse.fade_start_tick = Some(tick.0);
+        }
+    }
+}
+
+/// Fades fading corpses out and despawns them along with their ragdoll parts.
+///
+/// Each corpse gets its own copies of its materials so fading one does not affect the others.
+pub(super) fn fade_corpses(
+    mut commands: Commands,
+    tick: Res<Tick>,
+    settings: Res<CorpseSettings>,
+    simulation_settings: Res<SimulationSettings>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    corpse_query: Query<(Entity, &Corpse)>,
+    children_query: Query<&Children>,
+    mut material_query: Query<(&mut Handle<StandardMaterial>, &mut Visible)>,
+    mut faded: Local<Vec<Entity>>,
+) {
+    let fade_ticks = simulation_settings.ticks(settings.fade_seconds).max(1);
+
+    for (entity, corpse) in corpse_query.iter() {
+        let fade_start_tick = match corpse.fade_start_tick {
+            Some(fade_start_tick) => fade_start_tick,
+            None => continue,
+        };
+
+        let mut roots = vec![entity];
+        roots.extend(corpse.parts.iter().copied());
+
+        if tick.0 >= fade_start_tick + fade_ticks {
+            for &root in roots.iter() {
+                commands.entity(root).despawn_recursive();
+            }
+
+            faded.retain(|&faded_entity| faded_entity != entity);
+            continue;
+        }
+
+        let alpha = 1.0 - (tick.0 - fade_start_tick) as f32 / fade_ticks as f32;
+        let first_frame = !faded.contains(&entity);
+
+        if first_frame {
+            faded.push(entity);
+        }
+
+        let mut stack = roots;
+
+        while let Some(node) = stack.pop() {
+            if let Ok(children) = children_query.get(node) {
+                stack.extend(children.iter().copied());
+            }
+
+            if let Ok((mut material_handle, mut visible)) = material_query.get_mut(node) {
+                if first_frame {
+                    let material = materials.get(&*material_handle).cloned();
+
+                    if let Some(material) = material {
+                        *material_handle = materials.add(material);
+                    }
+
+                    visible.is_transparent = true;
+                }
+
+                if let Some(material) = materials.get_mut(&*material_handle) {
+                    material.base_color.set_a(alpha);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,798 @@
+use std::f32::consts::PI;
+
+use bevy::{prelude::*, render::mesh::shape};
+use bevy_rapier3d::{na::RealField, prelude::*};
+use rand::Rng;
+
+use crate::{
+    damage::{DamageEvent, PlayerCharacter},
+    despawn::DespawnAfter,
+    json_asset::AddJsonAsset,
+    pool::{EntityPools, PoolKind, PoolSettings, PoolSlot, Pooled, PooledBodyBundle},
+    random::seeded_generator,
+    resources::{Score, SimulationSettings},
+    tick::{on_tick, tick_due, AddTickEvent},
+    weapons::Projectile,
+    AppState, Health, PhysicsFlags, Tick,
+};
+
+use self::{
+    animation::{
+        play_enemy_animations, select_enemy_clip, EnemyAnimationSet, EnemyAnimationSetLoader,
+        EnemyAnimator,
+    },
+    archetype::{
+        load_enemy_archetypes, prepare_enemy_archetype_visuals, EnemyArchetype, EnemyArchetypes,
+        WeakPointDefinition,
+    },
+    attack::{
+        animate_enemy_attacks, enemy_attack, EnemyAnimation, EnemyAnimationEvent, EnemyAttackState,
+    },
+    behavior_tree::{enemy_behavior_tree, enemy_behavior_tree_window, EnemyBrain},
+    boss::{boss_phases, boss_spawned, boss_special_attacks, Boss, BossPhaseEvent},
+    death::{enemy_death, fade_corpses, limit_corpses, CorpseSettings, EnemyDeathEvent},
+    navigation::{
+        enemy_navigation, horizontal_distance, init_navigation_grid, update_navigation_grid,
+        EnemyPath,
+    },
+    perception::{enemy_hearing, enemy_perception, EnemyPerception, NoiseEvent},
+    steering::{enemy_steering, CrowdSettings},
+};
+
+pub mod animation;
+pub mod archetype;
+pub mod attack;
+pub mod behavior_tree;
+pub mod boss;
+pub mod death;
+pub mod navigation;
+pub mod perception;
+pub mod steering;
+
+pub struct EnemyPlugin;
+
+impl Plugin for EnemyPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_tick_event::<EnemyHitEvent>()
+            .add_tick_event::<NoiseEvent>()
+            .add_tick_event::<EnemyAnimationEvent>()
+            .add_tick_event::<EnemyDeathEvent>()
+            .add_tick_event::<BossPhaseEvent>()
+            .insert_resource(CrowdSettings::default())
+            .insert_resource(EnemyIds::default())
+            .insert_resource(CorpseSettings::default())
+            .add_json_asset::<EnemyArchetype>(&["enemy.json"])
+            .add_asset::<EnemyAnimationSet>()
+            .init_asset_loader::<EnemyAnimationSetLoader>()
+            .add_startup_system(init_enemy_resources.system())
+            .add_startup_system(load_enemy_archetypes.system())
+            .add_startup_system(init_navigation_grid.system())
+            .add_system(prepare_enemy_archetype_visuals.system())
+            .add_system_set(
+                SystemSet::on_enter(AppState::InGame).with_system(reset_enemy_ids.system()),
+            )
+            .add_system(
+                update_navigation_grid
+                    .system()
+                    .label("update_navigation_grid"),
+            )
+            .add_system_set(
+                on_tick(AppState::InGame)
+                    .with_system(enemy_hearing.system().label("enemy_hearing"))
+                    .with_system(
+                        enemy_perception
+                            .system()
+                            .label("enemy_perception")
+                            .after("enemy_hearing"),
+                    )
+                    .with_system(
+                        enemy_behavior_tree
+                            .system()
+                            .label("enemy_behavior_tree")
+                            .after("enemy_perception"),
+                    )
+                    .with_system(
+                        enemy_navigation
+                            .system()
+                            .label("enemy_navigation")
+                            .after("enemy_behavior_tree")
+                            .after("update_navigation_grid"),
+                    )
+                    .with_system(
+                        enemy_movement
+                            .system()
+                            .label("enemy_movement")
+                            .after("enemy_navigation")
+                            .after("damage_enemy"),
+                    )
+                    .with_system(
+                        enemy_steering
+                            .system()
+                            .label("enemy_steering")
+                            .after("enemy_movement"),
+                    )
+                    .with_system(boss_spawned.system().label("boss_spawned"))
+                    .with_system(
+                        boss_phases
+                            .system()
+                            .label("boss_phases")
+                            .after("boss_spawned")
+                            .after("damage_enemy"),
+                    )
+                    .with_system(
+                        boss_special_attacks
+                            .system()
+                            .after("boss_phases")
+                            .after("enemy_steering"),
+                    )
+                    .with_system(enemy_projectile_hit.system())
+                    .with_system(enemy_hit.system().label("hit_enemy"))
+                    .with_system(
+                        damage_enemy
+                            .system()
+                            .label("damage_enemy")
+                            .after("hit_enemy"),
+                    )
+                    .with_system(
+                        enemy_death
+                            .system()
+                            .label("enemy_death")
+                            .after("damage_enemy"),
+                    )
+                    .with_system(
+                        limit_corpses
+                            .system()
+                            .label("limit_corpses")
+                            .after("enemy_death"),
+                    )
+                    .with_system(fade_corpses.system().after("limit_corpses"))
+                    .with_system(
+                        enemy_attack
+                            .system()
+                            .label("enemy_attack")
+                            .after("enemy_behavior_tree"),
+                    )
+                    .with_system(
+                        select_enemy_clip
+                            .system()
+                            .label("select_enemy_clip")
+                            .after("enemy_attack"),
+                    ),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::InGame)
+                    .with_system(enemy_behavior_tree_window.system())
+                    .with_system(animate_enemy_attacks.system().after("enemy_attack"))
+                    .with_system(play_enemy_animations.system().after("select_enemy_clip")),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                spawn_enemy_blood_splatters
+                    .system()
+                    .with_run_criteria(tick_due.system()),
+            );
+    }
+}
+
+#[derive(Bundle)]
+pub struct EnemyBundle {
+    pub enemy: Enemy,
+    pub behavior: EnemyBehavior,
+    pub path: EnemyPath,
+    pub perception: EnemyPerception,
+    pub brain: EnemyBrain,
+    pub attack: EnemyAttackState,
+    pub health: Health,
+    #[bundle]
+    pub rigid_body: RigidBodyBundle,
+    #[bundle]
+    pub collider: ColliderBundle,
+    pub rigid_body_position_sync: RigidBodyPositionSync,
+}
+
+#[derive(Bundle)]
+pub struct EnemyBloodSplatterBundle {
+    pub despawn_after: DespawnAfter,
+    #[bundle]
+    pub pbr: PbrBundle,
+    #[bundle]
+    pub rigid_body: RigidBodyBundle,
+    #[bundle]
+    pub collider: ColliderBundle,
+    pub rigid_body_position_sync: RigidBodyPositionSync,
+}
+
+pub struct Enemy {
+    pub archetype: Handle<EnemyArchetype>,
+    pub max_health: f32,
+    /// Spawn order of the enemy in the game. Unlike its `Entity`, it is the same on every peer.
+    pub id: u32,
+}
+
+/// Id given to the next enemy spawned.
+#[derive(Default)]
+pub struct EnemyIds {
+    next: u32,
+}
+
+/// Collider part of an enemy multiplying the damage of projectiles hitting it.
+pub struct WeakPoint {
+    pub enemy: Entity,
+    pub damage_multiplier: f32,
+}
+
+/// Model child of an enemy, with the transform it returns to after animations.
+pub struct EnemyModel {
+    pub rest: Transform,
+}
+
+pub struct EnemyProjectile {
+    pub damage: f32,
+}
+
+#[derive(Bundle)]
+pub struct EnemyProjectileBundle {
+    pub projectile: EnemyProjectile,
+    pub despawn_after: DespawnAfter,
+    #[bundle]
+    pub pbr: PbrBundle,
+    #[bundle]
+    pub rigid_body: RigidBodyBundle,
+    #[bundle]
+    pub collider: ColliderBundle,
+    pub rigid_body_position_sync: RigidBodyPositionSync,
+}
+
+#[derive(Debug)]
+pub enum EnemyBehavior {
+    Idle,
+    /// Walking to a destination on the floor.
+    Wander(Vec3),
+    /// Heading to where a character was last seen or heard.
+    Investigate(Vec3),
+    /// Running away to a destination on the floor.
+    Flee(Vec3),
+    Attack(Entity),
+    Stagger,
+    Death,
+}
+
+pub struct EnemyHitEvent {
+    pub enemy: Entity,
+    pub damage: f32,
+    pub position: Point<f32>,
+    pub direction: UnitVector<f32>,
+}
+
+pub struct EnemyResources {
+    enemy_mesh: Handle<Mesh>,
+    ragdoll_head_mesh: Handle<Mesh>,
+    projectile_mesh: Handle<Mesh>,
+    projectile_material: Handle<StandardMaterial>,
+    projectile_shape: SharedShape,
+    blood_mesh: Handle<Mesh>,
+    blood_material: Handle<StandardMaterial>,
+    blood_shape: SharedShape,
+}
+
+fn init_enemy_resources(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(EnemyResources {
+        enemy_mesh: meshes.add(Mesh::from(shape::Capsule {
+            radius: 0.5,
+            depth: 1.0,
+            ..Default::default()
+        })),
+        ragdoll_head_mesh: meshes.add(Mesh::from(shape::Icosphere {
+            radius: 1.0,
+            subdivisions: 2,
+        })),
+        projectile_mesh: meshes.add(Mesh::from(shape::Icosphere {
+            radius: 0.15,
+            subdivisions: 2,
+        })),
+        projectile_material: materials.add(Color::LIME_GREEN.into()),
+        projectile_shape: SharedShape::ball(0.15),
+        blood_mesh: meshes.add(Mesh::from(shape::Box::new(0.1, 0.1, 0.1))),
+        blood_material: materials.add(Color::RED.into()),
+        blood_shape: SharedShape::cuboid(0.1, 0.1, 0.1),
+    });
+}
+
+/// Turns projectile intersections into enemy hits.
+///
+/// A projectile touching both the body and a weak point of an enemy in the same frame hits it once,
+/// with the highest multiplier. Each projectile hits an enemy at most once and stops after going
+/// through as many enemies as its penetration allows.
+fn enemy_hit(
+    mut commands: Commands,
+    mut intersection_events: EventReader<IntersectionEvent>,
+    mut enemy_hit_events: EventWriter<EnemyHitEvent>,
+    mut projectile_query: Query<(&mut Projectile, &RigidBodyPosition, &RigidBodyVelocity)>,
+    enemy_query: Query<&EnemyBehavior>,
+    weak_point_query: Query<&WeakPoint>,
+) {
+    let mut hits: Vec<(Entity, Entity, f32)> = Vec::new();
+
+    for intersection_event in intersection_events.iter() {
+        if !intersection_event.intersecting {
+            continue;
+        }
+
+        let entity1 = intersection_event.collider1.entity();
+        let entity2 = intersection_event.collider2.entity();
+
+        let (projectile_entity, other_entity) = if projectile_query.get_mut(entity1).is_ok() {
+            (entity1, entity2)
+        } else if projectile_query.get_mut(entity2).is_ok() {
+            (entity2, entity1)
+        } else {
+            continue;
+        };
+
+        let (enemy_entity, damage_multiplier) = match weak_point_query.get(other_entity) {
+            Ok(weak_point) => (weak_point.enemy, weak_point.damage_multiplier),
+            Err(_) => (other_entity, 1.0),
+        };
+
+        match enemy_query.get(enemy_entity) {
+            Ok(EnemyBehavior::Death) | Err(_) => continue,
+            Ok(_) => {}
+        }
+
+        match hits.iter_mut().find(|(projectile, enemy, _)| {
+            *projectile == projectile_entity && *enemy == enemy_entity
+        }) {
+            Some(hit) => hit.2 = hit.2.max(damage_multiplier),
+            None => hits.push((projectile_entity, enemy_entity, damage_multiplier)),
+        }
+    }
+
+    for (projectile_entity, enemy_entity, damage_multiplier) in hits {
+        let (mut projectile, position, velocity) =
+            projectile_query.get_mut(projectile_entity).unwrap();
+
+        if projectile.hits.len() > projectile.penetration as usize
+            || projectile.hits.contains(&enemy_entity)
+        {
+            continue;
+        }
+
+        let translation: Vec3 = position.position.translation.into();
+        let falloff = projectile.falloff.map_or(1.0, |falloff| {
+            falloff.multiplier(translation.distance(projectile.origin))
+        });
+
+        enemy_hit_events.send(EnemyHitEvent {
+            enemy: enemy_entity,
+            damage: projectile.damage * damage_multiplier * falloff,
+            position: position.position.translation.vector.into(),
+            direction: UnitVector::new_normalize(velocity.linvel),
+        });
+
+        projectile.hits.push(enemy_entity);
+
+        if projectile.hits.len() > projectile.penetration as usize {
+            commands.entity(projectile_entity).insert(DespawnAfter(0));
+        }
+    }
+}
+
+fn reset_enemy_ids(mut enemy_ids: ResMut<EnemyIds>) {
+    *enemy_ids = EnemyIds::default();
+}
+
+/// Spawns an enemy of the given archetype, or returns `None` if it has not finished loading.
+pub fn spawn_enemy(
+    commands: &mut Commands,
+    resources: &EnemyResources,
+    enemy_ids: &mut EnemyIds,
+    enemy_archetypes: &EnemyArchetypes,
+    archetypes: &Assets<EnemyArchetype>,
+    archetype_handle: &Handle<EnemyArchetype>,
+    position: Vec3,
+    health_multiplier: f32,
+) -> Option<Entity> {
+    let archetype = archetypes.get(archetype_handle)?;
+    let visuals = enemy_archetypes.visuals.get(archetype_handle)?;
+    let model_transform = archetype.model_transform();
+    let max_health = archetype.health * health_multiplier;
+    let id = enemy_ids.next;
+    enemy_ids.next += 1;
+
+    let entity = commands
+        .spawn_bundle(EnemyBundle {
+            enemy: Enemy {
+                archetype: archetype_handle.clone(),
+                max_health,
+                id,
+            },
+            behavior: EnemyBehavior::Idle,
+            path: EnemyPath::default(),
+            perception: EnemyPerception::default(),
+            brain: EnemyBrain::default(),
+            attack: EnemyAttackState::default(),
+            health: Health(max_health),
+            rigid_body: RigidBodyBundle {
+                body_type: RigidBodyType::Dynamic,
+                position: position.into(),
+                mass_properties: RigidBodyMassPropsFlags::ROTATION_LOCKED.into(),
+                damping: RigidBodyDamping {
+                    linear_damping: 1.0,
+                    angular_damping: 1.0,
+                },
+                ..Default::default()
+            },
+            collider: ColliderBundle {
+                shape: archetype.collider_shape(),
+                material: ColliderMaterial {
+                    friction: 0.8,
+                    friction_combine_rule: CoefficientCombineRule::Max,
+                    restitution: 0.1,
+                    restitution_combine_rule: CoefficientCombineRule::Min,
+                },
+                flags: ColliderFlags {
+                    collision_groups: InteractionGroups::new(PhysicsFlags::ENEMY.bits(), u32::MAX),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            rigid_body_position_sync: RigidBodyPositionSync::Discrete,
+        })
+        .with_children(|parent| match &visuals.model {
+            Some(model) => {
+                parent
+                    .spawn_bundle((model_transform, GlobalTransform::identity()))
+                    .insert(EnemyModel {
+                        rest: model_transform,
+                    })
+                    .with_children(|parent| {
+                        parent.spawn_scene(model.clone());
+                    });
+            }
+            None => {
+                parent
+                    .spawn_bundle(PbrBundle {
+                        mesh: resources.enemy_mesh.clone(),
+                        material: visuals.material.clone(),
+                        transform: model_transform,
+                        ..Default::default()
+                    })
+                    .insert(EnemyModel {
+                        rest: model_transform,
+                    });
+            }
+        })
+        .id();
+
+    for weak_point in archetype.weak_points.iter() {
+        spawn_weak_point(commands, entity, position, weak_point);
+    }
+
+    if archetype.boss.is_some() {
+        commands.entity(entity).insert(Boss::default());
+    }
+
+    if let Some(animations) = &visuals.animations {
+        commands
+            .entity(entity)
+            .insert(EnemyAnimator::new(animations.clone()));
+    }
+
+    Some(entity)
+}
+
+/// Spawns a weak point as a child of the enemy, with a collider attached to the enemy body so that
+/// it follows the enemy around.
+pub fn spawn_weak_point(
+    commands: &mut Commands,
+    enemy: Entity,
+    enemy_position: Vec3,
+    definition: &WeakPointDefinition,
+) -> Entity {
+    let [x, y, z] = definition.offset;
+    let offset = Vec3::new(x, y, z);
+
+    let weak_point = commands
+        .spawn_bundle(ColliderBundle {
+            collider_type: ColliderType::Sensor,
+            shape: SharedShape::ball(definition.radius),
+            position: (enemy_position + offset).into(),
+            mass_properties: ColliderMassProps::Density(0.0),
+            flags: ColliderFlags {
+                collision_groups: InteractionGroups::new(
+                    PhysicsFlags::ENEMY.bits(),
+                    PhysicsFlags::PROJECTILE.bits(),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(ColliderParent {
+            handle: enemy.handle(),
+            pos_wrt_parent: offset.into(),
+        })
+        .insert(WeakPoint {
+            enemy,
+            damage_multiplier: definition.damage_multiplier,
+        })
+        .id();
+
+    commands.entity(enemy).push_children(&[weak_point]);
+
+    weak_point
+}
+
+/// Steers enemies along their paths, facing the next waypoint.
+fn enemy_movement(
+    tick: Res<Tick>,
+    archetypes: Res<Assets<EnemyArchetype>>,
+    character_query: Query<&Transform, With<PlayerCharacter>>,
+    mut enemy_query: Query<(
+        &Enemy,
+        &EnemyBehavior,
+        &EnemyAttackState,
+        &EnemyBrain,
+        &mut EnemyPath,
+        &mut RigidBodyPosition,
+        &mut RigidBodyVelocity,
+    )>,
+) {
+    for (enemy, behavior, attack, brain, mut path, mut position, mut velocity) in
+        enemy_query.iter_mut()
+    {
+        // Left to the knockback of the hit that staggered it
+        if tick.0 < brain.stagger_until_tick {
+            continue;
+        }
+
+        let archetype = match archetypes.get(&enemy.archetype) {
+            Some(archetype) => archetype,
+            None => continue,
+        };
+        let translation: Vec3 = position.position.translation.into();
+
+        // Planted while winding up and striking, which is what gives players time to dodge
+        if attack.is_attacking() {
+            velocity.linvel.x = 0.0;
+            velocity.linvel.z = 0.0;
+            continue;
+        }
+
+        let (waypoint, speed) = match *behavior {
+            EnemyBehavior::Wander(_) | EnemyBehavior::Investigate(_) => {
+                (path.current_waypoint(translation), archetype.wander_speed)
+            }
+            EnemyBehavior::Flee(_) => (path.current_waypoint(translation), archetype.chase_speed),
+            EnemyBehavior::Attack(character) => {
+                let character_translation = match character_query.get(character) {
+                    Ok(transform) => transform.translation,
+                    Err(_) => continue,
+                };
+
+                if horizontal_distance(translation, character_translation)
+                    <= archetype.stop_distance()
+                {
+                    (Some(character_translation), 0.0)
+                } else {
+                    (path.current_waypoint(translation), archetype.chase_speed)
+                }
+            }
+            _ => continue,
+        };
+
+        let direction = match waypoint {
+            Some(waypoint) => waypoint - translation,
+            None => {
+                velocity.linvel = Vector::zeros();
+                continue;
+            }
+        };
+
+        position.position.rotation = Rotation::from_axis_angle(
+            &UnitVector::new_unchecked(Vector::y()),
+            RealField::atan2(direction.x, direction.z),
+        );
+        velocity.linvel = position.position.rotation * vector!(0.0, 0.0, speed);
+    }
+}
+
+fn enemy_projectile_hit(
+    mut commands: Commands,
+    mut intersection_events: EventReader<IntersectionEvent>,
+    mut damage_events: EventWriter<DamageEvent>,
+    projectile_query: Query<(&EnemyProjectile, &RigidBodyPosition, &RigidBodyVelocity)>,
+    character_query: Query<(), With<PlayerCharacter>>,
+) {
+    for intersection_event in intersection_events.iter() {
+        if !intersection_event.intersecting {
+            continue;
+        }
+
+        let entity1 = intersection_event.collider1.entity();
+        let entity2 = intersection_event.collider2.entity();
+
+        let (projectile_entity, other_entity) = if projectile_query.get(entity1).is_ok() {
+            (entity1, entity2)
+        } else if projectile_query.get(entity2).is_ok() {
+            (entity2, entity1)
+        } else {
+            continue;
+        };
+
+        if character_query.get(other_entity).is_ok() {
+            let (projectile, position, velocity) = projectile_query.get(projectile_entity).unwrap();
+
+            damage_events.send(DamageEvent {
+                target: other_entity,
+                attacker: None,
+                amount: projectile.damage,
+                position: position.position.translation.vector.into(),
+                direction: UnitVector::new_normalize(velocity.linvel),
+            });
+        }
+
+        commands.entity(projectile_entity).insert(DespawnAfter(0));
+    }
+}
+
+fn damage_enemy(
+    tick: Res<Tick>,
+    simulation_settings: Res<SimulationSettings>,
+    mut score: ResMut<Score>,
+    archetypes: Res<Assets<EnemyArchetype>>,
+    mut enemy_hit_events: EventReader<EnemyHitEvent>,
+    mut animation_events: EventWriter<EnemyAnimationEvent>,
+    mut death_events: EventWriter<EnemyDeathEvent>,
+    mut query: Query<(
+        &Enemy,
+        &mut EnemyBehavior,
+        &mut EnemyBrain,
+        &mut EnemyAttackState,
+        &mut Health,
+        &RigidBodyMassProps,
+        &mut RigidBodyVelocity,
+    )>,
+) {
+    for enemy_hit_event in enemy_hit_events.iter() {
+        let (
+            enemy,
+            mut behavior,
+            mut brain,
+            mut attack,
+            mut health,
+            body_mass_props,
+            mut body_velocity,
+        ) = match query.get_mut(enemy_hit_event.enemy) {
+            Ok(components) => components,
+            Err(_) => continue,
+        };
+
+        if let EnemyBehavior::Death = *behavior {
+            continue;
+        }
+
+        let archetype = match archetypes.get(&enemy.archetype) {
+            Some(archetype) => archetype,
+            None => continue,
+        };
+
+        health.0 -= enemy_hit_event.damage;
+
+        if health.0 <= 0.0 {
+            score.0 += archetype.score;
+            *behavior = EnemyBehavior::Death;
+            attack.interrupt(tick.0);
+
+            death_events.send(EnemyDeathEvent {
+                enemy: enemy_hit_event.enemy,
+                position: enemy_hit_event.position,
+                direction: enemy_hit_event.direction,
+            });
+        } else {
+            let direction = Vec3::new(
+                enemy_hit_event.direction.x,
+                0.0,
+                enemy_hit_event.direction.z,
+            )
+            .normalize_or_zero();
+
+            body_velocity.apply_impulse(body_mass_props, (direction * archetype.knockback).into());
+            brain.stagger_until_tick = brain
+                .stagger_until_tick
+                .max(tick.0 + simulation_settings.ticks(archetype.stagger_seconds));
+            attack.interrupt(tick.0);
+
+            animation_events.send(EnemyAnimationEvent {
+                enemy: enemy_hit_event.enemy,
+                animation: EnemyAnimation::Hit { direction },
+            });
+        }
+    }
+}
+
+/// Key of the generator scattering blood splatters, "blood" in ASCII.
+const BLOOD_RANDOM_KEY: u64 = 0x62_6c6f_6f64;
+
+/// Sprays blood splatters out of hit enemies, each living for one to two seconds.
+fn spawn_enemy_blood_splatters(
+    mut commands: Commands,
+    tick: Res<Tick>,
+    simulation_settings: Res<SimulationSettings>,
+    enemy_resources: Res<EnemyResources>,
+    pool_settings: Res<PoolSettings>,
+    mut pools: ResMut<EntityPools>,
+    mut enemy_hit_events: EventReader<EnemyHitEvent>,
+) {
+    let mut generator = seeded_generator(tick.0, BLOOD_RANDOM_KEY);
+    let lifetime = simulation_settings.ticks(1.0)..=simulation_settings.ticks(2.0);
+
+    for enemy_hit_event in enemy_hit_events.iter() {
+        for _ in 0..32 {
+            let rotation = Rotation::from_euler_angles(
+                generator.gen_range(-PI..=PI),
+                0.0,
+                generator.gen_range(-PI..=PI),
+            );
+            let direction = rotation * Vector::z();
+
+            let blood_splatter = EnemyBloodSplatterBundle {
+                despawn_after: DespawnAfter(generator.gen_range(lifetime.clone())),
+                pbr: PbrBundle {
+                    mesh: enemy_resources.blood_mesh.clone(),
+                    material: enemy_resources.blood_material.clone(),
+                    ..Default::default()
+                },
+                rigid_body: RigidBodyBundle {
+                    body_type: RigidBodyType::Dynamic,
+                    position: enemy_hit_event.position.into(),
+                    velocity: RigidBodyVelocity {
+                        linvel: direction * 5.0,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                collider: ColliderBundle {
+                    shape: enemy_resources.blood_shape.clone(),
+                    flags: ColliderFlags {
+                        collision_groups: InteractionGroups::new(
+                            PhysicsFlags::EFFECT.bits(),
+                            PhysicsFlags::ENVIRONMENT.bits(),
+                        ),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                rigid_body_position_sync: RigidBodyPositionSync::Discrete,
+            };
+
+            match pools
+                .blood_splatters
+                .acquire(pool_settings.blood_splatters, |_| true)
+            {
+                PoolSlot::Reused(entity) => {
+                    commands
+                        .entity(entity)
+                        .insert_bundle(PooledBodyBundle::new(
+                            PoolKind::BloodSplatter,
+                            blood_splatter.rigid_body,
+                            blood_splatter.collider,
+                        ))
+                        .insert(blood_splatter.despawn_after)
+                        .insert(Visible::default());
+                }
+                PoolSlot::New => {
+                    commands
+                        .spawn_bundle(blood_splatter)
+                        .insert(Pooled::new(PoolKind::BloodSplatter));
+                }
+                PoolSlot::Overflow => {
+                    commands.spawn_bundle(blood_splatter);
+                }
+            }
+        }
+    }
+}
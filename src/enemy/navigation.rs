@@ -0,0 +1,473 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use bevy::prelude::*;
+use bevy_rapier3d::{
+    prelude::*,
+    rapier::parry::{query, shape::Cuboid},
+};
+
+use super::{archetype::EnemyArchetype, Enemy, EnemyBehavior, WeakPoint};
+use crate::{damage::PlayerCharacter, Tick};
+
+/// Walkability grid over the arena floor for agents of one radius class, updated whenever static
+/// colliders are added.
+///
+/// Cells are blocked when an upright box the size of an agent standing at the cell center
+/// intersects a static collider. Building and querying only depend on collider data and the
+/// query positions, so every peer ends up with the same grid and the same paths.
+pub struct NavigationGrid {
+    min: Vec2,
+    cell_size: f32,
+    width: usize,
+    height: usize,
+    agent_radius: f32,
+    agent_height: f32,
+    walkable: Vec<bool>,
+}
+
+/// One navigation grid per radius class, from the smallest class to the largest.
+pub struct NavigationGrids {
+    grids: Vec<NavigationGrid>,
+}
+
+impl NavigationGrids {
+    /// Grid of the smallest class fitting an agent of the given radius, or of the largest class
+    /// for agents bigger than every class.
+    pub fn for_radius(&self, radius: f32) -> &NavigationGrid {
+        self.grids
+            .iter()
+            .find(|grid| grid.agent_radius >= radius)
+            .unwrap_or_else(|| self.grids.last().unwrap())
+    }
+}
+
+/// Area covered by the navigation grids and the size of the agents walking them.
+pub struct NavigationSettings {
+    pub min: Vec2,
+    pub max: Vec2,
+    pub cell_size: f32,
+    /// Radius classes of the agents, in increasing order. Each class gets its own grid.
+    pub agent_radii: Vec<f32>,
+    pub agent_height: f32,
+}
+
+impl Default for NavigationSettings {
+    fn default() -> Self {
+        Self {
+            min: Vec2::new(-25.0, -25.0),
+            max: Vec2::new(25.0, 25.0),
+            cell_size: 0.5,
+            agent_radii: vec![0.5, 0.8, 1.2],
+            agent_height: 2.0,
+        }
+    }
+}
+
+/// Path an enemy is following towards its current goal.
+#[derive(Default)]
+pub struct EnemyPath {
+    pub waypoints: Vec<Vec3>,
+    pub next_waypoint: usize,
+    goal: Option<Vec3>,
+    repath_tick: usize,
+}
+
+impl EnemyPath {
+    /// Ticks after which a path is recomputed even if the goal did not move.
+    pub const REPATH_INTERVAL_TICKS: usize = 60;
+    /// Distance the goal has to move before the path is recomputed.
+    pub const REPATH_DISTANCE: f32 = 1.0;
+    /// Distance at which a waypoint counts as reached.
+    pub const WAYPOINT_RADIUS: f32 = 0.3;
+
+    pub fn clear(&mut self) {
+        self.waypoints.clear();
+        self.next_waypoint = 0;
+        self.goal = None;
+    }
+
+    /// Waypoint to steer towards, skipping the ones already reached.
+    pub fn current_waypoint(&mut self, position: Vec3) -> Option<Vec3> {
+        while let Some(&waypoint) = self.waypoints.get(self.next_waypoint) {
+            if horizontal_distance(position, waypoint) > Self::WAYPOINT_RADIUS {
+                return Some(waypoint);
+            }
+
+            self.next_waypoint += 1;
+        }
+
+        None
+    }
+
+    /// Whether the last path query for the current goal found no way there.
+    pub fn is_unreachable(&self) -> bool {
+        self.goal.is_some() && self.waypoints.is_empty()
+    }
+
+    fn needs_repath(&self, goal: Vec3, tick: usize) -> bool {
+        match self.goal {
+            Some(previous_goal) => {
+                tick >= self.repath_tick
+                    || horizontal_distance(previous_goal, goal) > Self::REPATH_DISTANCE
+            }
+            None => true,
+        }
+    }
+}
+
+pub fn horizontal_distance(a: Vec3, b: Vec3) -> f32 {
+    Vec2::new(a.x - b.x, a.z - b.z).length()
+}
+
+/// Movement costs of straight and diagonal steps, as integers so ordering is exact.
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+
+const NEIGHBORS: [(isize, isize, u32); 8] = [
+    (1, 0, STRAIGHT_COST),
+    (-1, 0, STRAIGHT_COST),
+    (0, 1, STRAIGHT_COST),
+    (0, -1, STRAIGHT_COST),
+    (1, 1, DIAGONAL_COST),
+    (1, -1, DIAGONAL_COST),
+    (-1, 1, DIAGONAL_COST),
+    (-1, -1, DIAGONAL_COST),
+];
+
+impl NavigationGrid {
+    pub fn new(settings: &NavigationSettings, agent_radius: f32) -> Self {
+        let size = (settings.max - settings.min) / settings.cell_size;
+        let width = size.x.ceil() as usize;
+        let height = size.y.ceil() as usize;
+
+        Self {
+            min: settings.min,
+            cell_size: settings.cell_size,
+            width,
+            height,
+            agent_radius,
+            agent_height: settings.agent_height,
+            walkable: vec![true; width * height],
+        }
+    }
+
+    /// Marks the cells blocked by a new collider, only testing the ones around its bounding box.
+    pub fn block(&mut self, shape: &ColliderShape, position: &ColliderPosition) {
+        // Lift the probe off the floor so the ground itself never blocks a cell
+        let clearance = 0.1;
+        let half_height = (self.agent_height - clearance) / 2.0;
+        let probe = Cuboid::new(vector![self.agent_radius, half_height, self.agent_radius]);
+
+        let aabb = shape.compute_aabb(&position.0);
+        let (min_x, min_z) = self.cell(Vec3::new(
+            aabb.mins.x - self.agent_radius,
+            0.0,
+            aabb.mins.z - self.agent_radius,
+        ));
+        let (max_x, max_z) = self.cell(Vec3::new(
+            aabb.maxs.x + self.agent_radius,
+            0.0,
+            aabb.maxs.z + self.agent_radius,
+        ));
+
+        for z in min_z..=max_z {
+            for x in min_x..=max_x {
+                let index = self.index((x, z));
+                let center = self.cell_center(index);
+                let probe_position =
+                    Isometry::translation(center.x, clearance + half_height, center.z);
+
+                if query::intersection_test(&probe_position, &probe, &position.0, &**shape)
+                    .unwrap_or(false)
+                {
+                    self.walkable[index] = false;
+                }
+            }
+        }
+    }
+
+    fn cell(&self, position: Vec3) -> (usize, usize) {
+        let x = ((position.x - self.min.x) / self.cell_size).floor();
+        let z = ((position.z - self.min.y) / self.cell_size).floor();
+
+        (
+            (x.max(0.0) as usize).min(self.width - 1),
+            (z.max(0.0) as usize).min(self.height - 1),
+        )
+    }
+
+    fn index(&self, (x, z): (usize, usize)) -> usize {
+        z * self.width + x
+    }
+
+    fn cell_center(&self, index: usize) -> Vec3 {
+        let x = (index % self.width) as f32 + 0.5;
+        let z = (index / self.width) as f32 + 0.5;
+
+        Vec3::new(
+            self.min.x + x * self.cell_size,
+            0.0,
+            self.min.y + z * self.cell_size,
+        )
+    }
+
+    fn neighbor(&self, index: usize, dx: isize, dz: isize) -> Option<usize> {
+        let x = (index % self.width) as isize + dx;
+        let z = (index / self.width) as isize + dz;
+
+        if x < 0 || z < 0 || x >= self.width as isize || z >= self.height as isize {
+            return None;
+        }
+
+        Some(z as usize * self.width + x as usize)
+    }
+
+    pub fn is_walkable(&self, position: Vec3) -> bool {
+        self.walkable[self.index(self.cell(position))]
+    }
+
+    /// Closest walkable cell to the given one, scanning rings of growing size.
+    fn nearest_walkable(&self, index: usize) -> Option<usize> {
+        if self.walkable[index] {
+            return Some(index);
+        }
+
+        let max_radius = self.width.max(self.height) as isize;
+
+        for radius in 1..max_radius {
+            for dz in -radius..=radius {
+                for dx in -radius..=radius {
+                    if dx.abs() != radius && dz.abs() != radius {
+                        continue;
+                    }
+
+                    if let Some(neighbor) = self.neighbor(index, dx, dz) {
+                        if self.walkable[neighbor] {
+                            return Some(neighbor);
+                        }
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Whether an agent can walk in a straight line between two points.
+    pub fn line_of_sight(&self, from: Vec3, to: Vec3) -> bool {
+        let distance = horizontal_distance(from, to);
+        let steps = (distance / (0.25 * self.cell_size)).ceil() as usize;
+
+        (0..=steps).all(|step| {
+            let t = if steps == 0 {
+                0.0
+            } else {
+                step as f32 / steps as f32
+            };
+
+            self.is_walkable(from.lerp(to, t))
+        })
+    }
+
+    fn heuristic(&self, from: usize, to: usize) -> u32 {
+        let dx = ((from % self.width) as isize - (to % self.width) as isize).abs() as u32;
+        let dz = ((from / self.width) as isize - (to / self.width) as isize).abs() as u32;
+
+        STRAIGHT_COST * dx.max(dz) + (DIAGONAL_COST - STRAIGHT_COST) * dx.min(dz)
+    }
+
+    /// Finds a path from `start` to `goal` with A*, returning the waypoints after `start`.
+    ///
+    /// Ties in the open set are broken by cell index, so the result only depends on the grid
+    /// and the two positions.
+    pub fn find_path(&self, start: Vec3, goal: Vec3) -> Option<Vec<Vec3>> {
+        let start_index = self.nearest_walkable(self.index(self.cell(start)))?;
+        let goal_index = self.nearest_walkable(self.index(self.cell(goal)))?;
+        let goal_position = if self.walkable[self.index(self.cell(goal))] {
+            Vec3::new(goal.x, 0.0, goal.z)
+        } else {
+            self.cell_center(goal_index)
+        };
+
+        if self.line_of_sight(start, goal_position) {
+            return Some(vec![goal_position]);
+        }
+
+        let mut costs = vec![u32::MAX; self.walkable.len()];
+        let mut came_from = vec![usize::MAX; self.walkable.len()];
+        let mut open = BinaryHeap::new();
+
+        costs[start_index] = 0;
+        open.push(Reverse((
+            self.heuristic(start_index, goal_index),
+            0,
+            start_index,
+        )));
+
+        while let Some(Reverse((_, cost, index))) = open.pop() {
+            if index == goal_index {
+                break;
+            }
+
+            if cost > costs[index] {
+                continue;
+            }
+
+            for &(dx, dz, step_cost) in NEIGHBORS.iter() {
+                let neighbor = match self.neighbor(index, dx, dz) {
+                    Some(neighbor) if self.walkable[neighbor] => neighbor,
+                    _ => continue,
+                };
+
+                // Don't cut corners around blocked cells
+                if dx != 0 && dz != 0 {
+                    let side_x = self.neighbor(index, dx, 0);
+                    let side_z = self.neighbor(index, 0, dz);
+
+                    if !side_x.map_or(false, |side| self.walkable[side])
+                        || !side_z.map_or(false, |side| self.walkable[side])
+                    {
+                        continue;
+                    }
+                }
+
+                let neighbor_cost = cost + step_cost;
+
+                if neighbor_cost < costs[neighbor] {
+                    costs[neighbor] = neighbor_cost;
+                    came_from[neighbor] = index;
+                    open.push(Reverse((
+                        neighbor_cost + self.heuristic(neighbor, goal_index),
+                        neighbor_cost,
+                        neighbor,
+                    )));
+                }
+            }
+        }
+
+        if costs[goal_index] == u32::MAX {
+            return None;
+        }
+
+        let mut cells = vec![goal_index];
+
+        while let Some(&index) = cells.last() {
+            let previous = came_from[index];
+
+            if previous == usize::MAX || previous == start_index {
+                break;
+            }
+
+            cells.push(previous);
+        }
+
+        cells.reverse();
+
+        let mut waypoints: Vec<_> = cells
+            .into_iter()
+            .map(|index| self.cell_center(index))
+            .collect();
+        *waypoints.last_mut().unwrap() = goal_position;
+
+        Some(self.smooth_path(start, waypoints))
+    }
+
+    /// Drops waypoints that can be skipped by walking straight to a later one.
+    fn smooth_path(&self, start: Vec3, waypoints: Vec<Vec3>) -> Vec<Vec3> {
+        let mut smoothed = Vec::new();
+        let mut from = start;
+        let mut index = 0;
+
+        while index < waypoints.len() {
+            let farthest = (index..waypoints.len())
+                .rev()
+                .find(|&candidate| self.line_of_sight(from, waypoints[candidate]))
+                .unwrap_or(index);
+
+            from = waypoints[farthest];
+            smoothed.push(from);
+            index = farthest + 1;
+        }
+
+        smoothed
+    }
+}
+
+pub(super) fn init_navigation_grid(mut commands: Commands) {
+    let settings = NavigationSettings::default();
+
+    commands.insert_resource(NavigationGrids {
+        grids: settings
+            .agent_radii
+            .iter()
+            .map(|&agent_radius| NavigationGrid::new(&settings, agent_radius))
+            .collect(),
+    });
+    commands.insert_resource(settings);
+}
+
+/// Blocks the cells of every grid around static colliders, those without a rigid body, as they are
+/// spawned.
+///
+/// Weak points are attached to an enemy body from a child entity, so they are left out.
+pub(super) fn update_navigation_grid(
+    mut grids: ResMut<NavigationGrids>,
+    added_query: Query<
+        (&ColliderShape, &ColliderPosition, &ColliderType),
+        (
+            Added<ColliderShape>,
+            Without<RigidBodyType>,
+            Without<WeakPoint>,
+        ),
+    >,
+) {
+    for (shape, position, collider_type) in added_query.iter() {
+        if !matches!(collider_type, ColliderType::Solid) {
+            continue;
+        }
+
+        for grid in grids.grids.iter_mut() {
+            grid.block(shape, position);
+        }
+    }
+}
+
+/// Keeps each enemy's path up to date with the goal of its behavior.
+pub(super) fn enemy_navigation(
+    tick: Res<Tick>,
+    grids: Res<NavigationGrids>,
+    archetypes: Res<Assets<EnemyArchetype>>,
+    character_query: Query<&Transform, With<PlayerCharacter>>,
+    mut enemy_query: Query<(&Enemy, &EnemyBehavior, &RigidBodyPosition, &mut EnemyPath)>,
+) {
+    for (enemy, behavior, position, mut path) in enemy_query.iter_mut() {
+        let goal = match *behavior {
+            EnemyBehavior::Wander(destination)
+            | EnemyBehavior::Investigate(destination)
+            | EnemyBehavior::Flee(destination) => destination,
+            EnemyBehavior::Attack(character) => match character_query.get(character) {
+                Ok(transform) => transform.translation,
+                Err(_) => continue,
+            },
+            _ => {
+                path.clear();
+                continue;
+            }
+        };
+
+        if !path.needs_repath(goal, tick.0) {
+            continue;
+        }
+
+        let grid = match archetypes.get(&enemy.archetype) {
+            Some(archetype) => grids.for_radius(archetype.collider.radius),
+            None => continue,
+        };
+        let start: Vec3 = position.position.translation.into();
+
+        path.waypoints = grid.find_path(start, goal).unwrap_or_default();
+        path.next_waypoint = 0;
+        path.goal = Some(goal);
+        path.repath_tick = tick.0 + EnemyPath::REPATH_INTERVAL_TICKS;
+    }
+}
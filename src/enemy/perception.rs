@@ -0,0 +1,212 @@
+use std::cmp::Ordering;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use super::{archetype::EnemyArchetype, Enemy, EnemyBehavior};
+use crate::{damage::PlayerCharacter, resources::SimulationSettings, PhysicsFlags, Tick};
+
+/// A loud sound, such as gunfire, that enemies within `radius` hear regardless of walls.
+pub struct NoiseEvent {
+    pub source: Entity,
+    pub position: Vec3,
+    pub radius: f32,
+}
+
+/// What an enemy knows about the characters around it.
+#[derive(Default)]
+pub struct EnemyPerception {
+    pub memories: Vec<TargetMemory>,
+    /// Character the enemy currently goes after, if any.
+    pub target: Option<Entity>,
+}
+
+pub struct TargetMemory {
+    pub entity: Entity,
+    pub last_known_position: Vec3,
+    pub last_sensed_tick: usize,
+    /// Whether the character was in sight on the last perception update.
+    pub visible: bool,
+    /// Grows when the character makes noise near the enemy and decays over time.
+    pub threat: f32,
+}
+
+impl EnemyPerception {
+    pub fn target_memory(&self) -> Option<&TargetMemory> {
+        let target = self.target?;
+
+        self.memories.iter().find(|memory| memory.entity == target)
+    }
+
+    fn remember(&mut self, entity: Entity, position: Vec3, tick: usize) -> &mut TargetMemory {
+        let index = match self
+            .memories
+            .iter()
+            .position(|memory| memory.entity == entity)
+        {
+            Some(index) => index,
+            None => {
+                self.memories.push(TargetMemory {
+                    entity,
+                    last_known_position: position,
+                    last_sensed_tick: tick,
+                    visible: false,
+                    threat: 0.0,
+                });
+                self.memories.len() - 1
+            }
+        };
+
+        let memory = &mut self.memories[index];
+        memory.last_known_position = position;
+        memory.last_sensed_tick = tick;
+        memory
+    }
+}
+
+/// Ticks between two sight updates. Hearing is processed every tick.
+const PERCEPTION_INTERVAL_TICKS: usize = 6;
+/// Characters this close are noticed even outside the field of view.
+const PROXIMITY_RADIUS: f32 = 2.0;
+/// Threat added for every noise a character makes within earshot.
+const NOISE_THREAT: f32 = 1.0;
+/// Threat lost per second.
+const THREAT_DECAY: f32 = 0.5;
+
+/// Height of the enemy eyes relative to the collider height.
+const EYE_HEIGHT: f32 = 0.9;
+/// Height aimed at when checking the line of sight to a character.
+const CHARACTER_CHEST_HEIGHT: f32 = 1.0;
+
+pub(super) fn enemy_hearing(
+    tick: Res<Tick>,
+    mut noise_events: EventReader<NoiseEvent>,
+    mut enemy_query: Query<(&EnemyBehavior, &RigidBodyPosition, &mut EnemyPerception)>,
+) {
+    for noise in noise_events.iter() {
+        for (behavior, position, mut perception) in enemy_query.iter_mut() {
+            if let EnemyBehavior::Death = *behavior {
+                continue;
+            }
+
+            let position: Vec3 = position.position.translation.into();
+
+            if position.distance(noise.position) > noise.radius {
+                continue;
+            }
+
+            perception
+                .remember(noise.source, noise.position, tick.0)
+                .threat += NOISE_THREAT;
+        }
+    }
+}
+
+/// Updates what enemies see and which character they target.
+///
+/// Sight is a cone of `field_of_view` degrees up to `aggro_range`, confirmed by a ray cast
+/// against the environment. Characters stay remembered at their last known position for
+/// `memory_seconds` after they were last sensed.
+pub(super) fn enemy_perception(
+    tick: Res<Tick>,
+    simulation_settings: Res<SimulationSettings>,
+    archetypes: Res<Assets<EnemyArchetype>>,
+    query_pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    character_query: Query<(Entity, &Transform), With<PlayerCharacter>>,
+    mut enemy_query: Query<(
+        &Enemy,
+        &EnemyBehavior,
+        &RigidBodyPosition,
+        &mut EnemyPerception,
+    )>,
+) {
+    if tick.0 % PERCEPTION_INTERVAL_TICKS != 0 {
+        return;
+    }
+
+    let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+    let environment = InteractionGroups::new(u32::MAX, PhysicsFlags::ENVIRONMENT.bits());
+    let interval = PERCEPTION_INTERVAL_TICKS as f32 * simulation_settings.tick_duration();
+
+    for (enemy, behavior, position, mut perception) in enemy_query.iter_mut() {
+        if let EnemyBehavior::Death = *behavior {
+            perception.target = None;
+            continue;
+        }
+
+        let archetype = match archetypes.get(&enemy.archetype) {
+            Some(archetype) => archetype,
+            None => continue,
+        };
+
+        let translation: Vec3 = position.position.translation.into();
+        let eye = translation + Vec3::Y * archetype.collider.height * EYE_HEIGHT;
+        let forward: Vec3 = (position.position.rotation * Vector::z()).into();
+        let half_field_of_view = archetype.field_of_view.to_radians() / 2.0;
+
+        for memory in perception.memories.iter_mut() {
+            memory.visible = false;
+            memory.threat = (memory.threat - THREAT_DECAY * interval).max(0.0);
+        }
+
+        for (character, character_transform) in character_query.iter() {
+            let target = character_transform.translation + Vec3::Y * CHARACTER_CHEST_HEIGHT;
+            let offset = target - eye;
+            let distance = offset.length();
+
+            if distance > archetype.aggro_range {
+                continue;
+            }
+
+            let horizontal_offset = Vec3::new(offset.x, 0.0, offset.z);
+
+            if distance > PROXIMITY_RADIUS
+                && forward.angle_between(horizontal_offset) > half_field_of_view
+            {
+                continue;
+            }
+
+            if let Some(direction) = offset.try_normalize() {
+                let ray = Ray::new(eye.into(), direction.into());
+
+                if query_pipeline
+                    .cast_ray(&collider_set, &ray, distance, true, environment, None)
+                    .is_some()
+                {
+                    continue;
+                }
+            }
+
+            perception
+                .remember(character, character_transform.translation, tick.0)
+                .visible = true;
+        }
+
+        let memory_ticks = simulation_settings.ticks(archetype.memory_seconds);
+
+        perception.memories.retain(|memory| {
+            character_query.get(memory.entity).is_ok()
+                && memory.last_sensed_tick + memory_ticks >= tick.0
+        });
+
+        // Most threatening first, nearest among equals
+        perception.target = perception
+            .memories
+            .iter()
+            .min_by(|a, b| {
+                b.threat
+                    .partial_cmp(&a.threat)
+                    .unwrap_or(Ordering::Equal)
+                    .then_with(|| {
+                        let distance_a = translation.distance(a.last_known_position);
+                        let distance_b = translation.distance(b.last_known_position);
+
+                        distance_a
+                            .partial_cmp(&distance_b)
+                            .unwrap_or(Ordering::Equal)
+                    })
+            })
+            .map(|memory| memory.entity);
+    }
+}
@@ -0,0 +1,214 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use super::{archetype::EnemyArchetype, behavior_tree::EnemyBrain, Enemy, EnemyBehavior};
+use crate::{damage::PlayerCharacter, Tick};
+
+/// Tuning of the separation and alignment steering applied on top of path following.
+pub struct CrowdSettings {
+    /// Size of the spatial hash cells, and the furthest an enemy looks for neighbors.
+    pub neighbor_radius: f32,
+    /// Extra space kept between the colliders of two enemies.
+    pub separation_margin: f32,
+    pub separation_weight: f32,
+    pub alignment_weight: f32,
+    /// Radius assumed for characters when keeping enemies off them.
+    pub character_radius: f32,
+    pub character_separation_weight: f32,
+}
+
+impl Default for CrowdSettings {
+    fn default() -> Self {
+        Self {
+            neighbor_radius: 2.0,
+            separation_margin: 0.2,
+            separation_weight: 3.0,
+            alignment_weight: 0.3,
+            character_radius: 0.5,
+            character_separation_weight: 1.5,
+        }
+    }
+}
+
+struct CrowdAgent {
+    entity: Entity,
+    position: Vec2,
+    velocity: Vec2,
+    radius: f32,
+    max_speed: f32,
+    /// Staggered enemies still push their neighbors away, but keep their knockback.
+    staggered: bool,
+}
+
+/// Total order on positions, used to visit neighbors in the same order on every peer.
+fn compare_positions(a: Vec2, b: Vec2) -> Ordering {
+    a.x.partial_cmp(&b.x)
+        .unwrap_or(Ordering::Equal)
+        .then(a.y.partial_cmp(&b.y).unwrap_or(Ordering::Equal))
+}
+
+fn horizontal(vector: Vector<Real>) -> Vec2 {
+    Vec2::new(vector.x, vector.z)
+}
+
+/// Push away from whatever overlaps the given radius, stronger the deeper the overlap.
+fn separation(position: Vec2, other: Vec2, distance: f32) -> Vec2 {
+    let offset = position - other;
+    let length = offset.length();
+
+    if length >= distance {
+        return Vec2::ZERO;
+    }
+
+    // Agents on the exact same spot split along the axis given by their order
+    let direction = if length > 1e-4 {
+        offset / length
+    } else if compare_positions(position, other) == Ordering::Less {
+        -Vec2::X
+    } else {
+        Vec2::X
+    };
+
+    direction * (distance - length) / distance
+}
+
+/// Adjusts the velocities chosen by `enemy_movement` so enemies spread around each other and
+/// around characters instead of relying on collisions to push them apart.
+///
+/// Neighbors come from a spatial hash and are visited sorted by position, so the floating point
+/// sums are identical on every peer regardless of entity ids or query order.
+pub(super) fn enemy_steering(
+    tick: Res<Tick>,
+    settings: Res<CrowdSettings>,
+    archetypes: Res<Assets<EnemyArchetype>>,
+    character_query: Query<&Transform, With<PlayerCharacter>>,
+    mut enemy_query: Query<(
+        Entity,
+        &Enemy,
+        &EnemyBehavior,
+        &EnemyBrain,
+        &RigidBodyPosition,
+        &mut RigidBodyVelocity,
+    )>,
+) {
+    let mut agents: Vec<_> = enemy_query
+        .iter_mut()
+        .filter(|(_, _, behavior, _, _, _)| !matches!(behavior, EnemyBehavior::Death))
+        .filter_map(|(entity, enemy, _, brain, position, velocity)| {
+            let archetype = archetypes.get(&enemy.archetype)?;
+
+            Some(CrowdAgent {
+                entity,
+                position: horizontal(position.position.translation.vector),
+                velocity: horizontal(velocity.linvel),
+                radius: archetype.collider.radius,
+                max_speed: archetype.chase_speed,
+                staggered: tick.0 < brain.stagger_until_tick,
+            })
+        })
+        .collect();
+
+    agents.sort_by(|a, b| compare_positions(a.position, b.position));
+
+    let cell_of = |position: Vec2| {
+        (
+            (position.x / settings.neighbor_radius).floor() as i32,
+            (position.y / settings.neighbor_radius).floor() as i32,
+        )
+    };
+
+    let mut cells: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+
+    for (index, agent) in agents.iter().enumerate() {
+        cells
+            .entry(cell_of(agent.position))
+            .or_default()
+            .push(index);
+    }
+
+    let mut characters: Vec<_> = character_query
+        .iter()
+        .map(|transform| Vec2::new(transform.translation.x, transform.translation.z))
+        .collect();
+    characters.sort_by(|&a, &b| compare_positions(a, b));
+
+    let mut neighbors = Vec::new();
+
+    for (index, agent) in agents.iter().enumerate() {
+        if agent.staggered {
+            continue;
+        }
+
+        let (cell_x, cell_z) = cell_of(agent.position);
+
+        neighbors.clear();
+
+        for dz in -1..=1 {
+            for dx in -1..=1 {
+                if let Some(cell) = cells.get(&(cell_x + dx, cell_z + dz)) {
+                    neighbors.extend(cell.iter().copied().filter(|&other| other != index));
+                }
+            }
+        }
+
+        // Indices follow the position order of `agents`
+        neighbors.sort_unstable();
+
+        let mut separation_force = Vec2::ZERO;
+        let mut average_velocity = Vec2::ZERO;
+        let mut moving_neighbors = 0;
+
+        for &other_index in neighbors.iter() {
+            let other = &agents[other_index];
+
+            if agent.position.distance(other.position) > settings.neighbor_radius {
+                continue;
+            }
+
+            separation_force += separation(
+                agent.position,
+                other.position,
+                agent.radius + other.radius + settings.separation_margin,
+            );
+
+            if other.velocity.length_squared() > 1e-4 {
+                average_velocity += other.velocity;
+                moving_neighbors += 1;
+            }
+        }
+
+        let mut character_force = Vec2::ZERO;
+
+        for &character in characters.iter() {
+            character_force += separation(
+                agent.position,
+                character,
+                agent.radius + settings.character_radius + settings.separation_margin,
+            );
+        }
+
+        let mut steering = settings.separation_weight * separation_force
+            + settings.character_separation_weight * character_force;
+
+        if moving_neighbors > 0 && agent.velocity.length_squared() > 1e-4 {
+            steering += settings.alignment_weight
+                * (average_velocity / moving_neighbors as f32 - agent.velocity);
+        }
+
+        if steering == Vec2::ZERO {
+            continue;
+        }
+
+        let mut steered = agent.velocity + steering;
+
+        if steered.length() > agent.max_speed {
+            steered = steered.normalize() * agent.max_speed;
+        }
+
+        let (_, _, _, _, _, mut velocity) = enemy_query.get_mut(agent.entity).unwrap();
+        velocity.linvel.x = steered.x;
+        velocity.linvel.z = steered.y;
+    }
+}
@@ -0,0 +1,57 @@
+use std::marker::PhantomData;
+
+use bevy::{
+    asset::{Asset, AssetLoader, BoxedFuture, LoadContext, LoadedAsset},
+    prelude::*,
+};
+use serde::de::DeserializeOwned;
+
+/// Loads any deserializable asset from a JSON file with one of the given extensions.
+pub struct JsonAssetLoader<T> {
+    extensions: &'static [&'static str],
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> JsonAssetLoader<T> {
+    pub fn new(extensions: &'static [&'static str]) -> Self {
+        Self {
+            extensions,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Asset + DeserializeOwned> AssetLoader for JsonAssetLoader<T> {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let asset = serde_json::from_slice::<'_, T>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(asset));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        self.extensions
+    }
+}
+
+pub trait AddJsonAsset {
+    fn add_json_asset<T: Asset + DeserializeOwned>(
+        &mut self,
+        extensions: &'static [&'static str],
+    ) -> &mut Self;
+}
+
+impl AddJsonAsset for AppBuilder {
+    fn add_json_asset<T: Asset + DeserializeOwned>(
+        &mut self,
+        extensions: &'static [&'static str],
+    ) -> &mut Self {
+        self.add_asset::<T>()
+            .add_asset_loader(JsonAssetLoader::<T>::new(extensions))
+    }
+}
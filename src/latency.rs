@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::IntegrationParameters;
+use libp2p::gossipsub::GossipsubEvent;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    network::{NetworkBehaviourEvent, NetworkEvent, NetworkManager, NetworkTopic},
+    party::Party,
+    player::PlayerId,
+    resources::{SimulationSettings, Tick},
+};
+
+pub struct LatencyPlugin;
+
+impl Plugin for LatencyPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(PeerLatencies::default())
+            .add_system_set(
+                SystemSet::on_enter(AppState::InLobby)
+                    .with_system(subscribe_latency_topics.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::InLobby)
+                    .with_system(send_latency_pings.system())
+                    .with_system(handle_latency_messages.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::InGame)
+                    .with_system(send_latency_pings.system())
+                    .with_system(handle_latency_messages.system())
+                    .with_system(adjust_simulation_settings.system())
+                    .with_system(apply_simulation_settings_change.system()),
+            );
+    }
+}
+
+/// Round trip times from the host to every other peer, in seconds.
+#[derive(Default)]
+pub struct PeerLatencies {
+    pub round_trip_times: HashMap<PlayerId, f32>,
+    pending_pings: HashMap<u64, f64>,
+    next_nonce: u64,
+}
+
+impl PeerLatencies {
+    const PING_INTERVAL: f64 = 1.0;
+    const PING_TIMEOUT: f64 = 5.0;
+    const SMOOTHING: f32 = 0.2;
+    pub const MAX_INPUT_DELAY: u32 = 12;
+
+    pub fn max_round_trip_time(&self) -> f32 {
+        self.round_trip_times.values().copied().fold(0.0, f32::max)
+    }
+
+    /// Picks the tick rate and the smallest input delay that covers the slowest peer's one-way
+    /// latency, with one tick of margin for jitter.
+    pub fn simulation_settings(&self) -> SimulationSettings {
+        let max_round_trip_time = self.max_round_trip_time();
+        let tick_rate = if max_round_trip_time < 0.15 { 60 } else { 30 };
+        let one_way_ticks = (max_round_trip_time / 2.0 * tick_rate as f32).ceil() as u32;
+
+        SimulationSettings {
+            tick_rate,
+            input_delay: (one_way_ticks + 1).clamp(1, Self::MAX_INPUT_DELAY),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LatencyPing {
+    nonce: u64,
+}
+
+/// Settings that take effect on every peer at the same tick.
+#[derive(Serialize, Deserialize)]
+pub struct SimulationSettingsChange {
+    pub tick: usize,
+    pub settings: SimulationSettings,
+}
+
+/// Pending change received from the host or decided locally by the host.
+struct PendingSimulationSettingsChange(SimulationSettingsChange);
+
+fn subscribe_latency_topics(mut network_manager: ResMut<NetworkManager>) {
+    network_manager.subscribe(NetworkTopic::new("ping"));
+    network_manager.subscribe(NetworkTopic::new("pong"));
+    network_manager.subscribe(NetworkTopic::new("simulation_settings"));
+}
+
+fn send_latency_pings(
+    time: Res<Time>,
+    party: Res<Party>,
+    mut latencies: ResMut<PeerLatencies>,
+    mut network_manager: ResMut<NetworkManager>,
+    mut last_ping_time: Local<f64>,
+) {
+    if party.host_id != network_manager.local_peer_id().into() {
+        return;
+    }
+
+    let now = time.seconds_since_startup();
+
+    if now - *last_ping_time < PeerLatencies::PING_INTERVAL {
+        return;
+    }
+
+    *last_ping_time = now;
+
+    latencies
+        .pending_pings
+        .retain(|_, sent_time| now - *sent_time < PeerLatencies::PING_TIMEOUT);
+
+    let nonce = latencies.next_nonce;
+    latencies.next_nonce += 1;
+    latencies.pending_pings.insert(nonce, now);
+
+    let data = serde_json::to_vec(&LatencyPing { nonce }).unwrap();
+    network_manager.publish(NetworkTopic::new("ping"), data);
+}
+
+fn handle_latency_messages(
+    mut commands: Commands,
+    time: Res<Time>,
+    party: Res<Party>,
+    mut latencies: ResMut<PeerLatencies>,
+    mut network_manager: ResMut<NetworkManager>,
+    mut network_events: EventReader<NetworkEvent>,
+) {
+    let is_host = party.host_id == network_manager.local_peer_id().into();
+
+    for event in network_events.iter() {
+        if let NetworkEvent::Behaviour(NetworkBehaviourEvent::Gossipsub(
+            GossipsubEvent::Message { message, .. },
+        )) = event
+        {
+            if NetworkTopic::new("ping").hash() == message.topic && !is_host {
+                network_manager.publish(NetworkTopic::new("pong"), message.data.clone());
+            } else if NetworkTopic::new("pong").hash() == message.topic && is_host {
+                let ping = match serde_json::from_slice::<'_, LatencyPing>(&message.data) {
+                    Ok(ping) => ping,
+                    Err(error) => {
+                        error!("Cannot parse LatencyPing from pong message: {:?}", error);
+                        continue;
+                    }
+                };
+                let (source, sent_time) = match (
+                    message.source,
+                    latencies.pending_pings.get(&ping.nonce).copied(),
+                ) {
+                    (Some(source), Some(sent_time)) => (source, sent_time),
+                    _ => continue,
+                };
+                let round_trip_time = (time.seconds_since_startup() - sent_time) as f32;
+
+                latencies
+                    .round_trip_times
+                    .entry(source.into())
+                    .and_modify(|smoothed| {
+                        *smoothed += PeerLatencies::SMOOTHING * (round_trip_time - *smoothed)
+                    })
+                    .or_insert(round_trip_time);
+            } else if NetworkTopic::new("simulation_settings").hash() == message.topic
+                && message.source == Some(party.host_id.into())
+            {
+                match serde_json::from_slice::<'_, SimulationSettingsChange>(&message.data) {
+                    Ok(change) => {
+                        commands.insert_resource(PendingSimulationSettingsChange(change));
+                    }
+                    Err(error) => {
+                        error!(
+                            "Cannot parse SimulationSettingsChange from simulation_settings message: {:?}",
+                            error
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Re-negotiates the settings when the slowest peer's latency has drifted by two ticks or more.
+fn adjust_simulation_settings(
+    mut commands: Commands,
+    tick: Res<Tick>,
+    party: Res<Party>,
+    settings: Res<SimulationSettings>,
+    latencies: Res<PeerLatencies>,
+    pending_change: Option<Res<PendingSimulationSettingsChange>>,
+    mut network_manager: ResMut<NetworkManager>,
+) {
+    if party.host_id != network_manager.local_peer_id().into()
+        || pending_change.is_some()
+        || !latencies.is_changed()
+    {
+        return;
+    }
+
+    let new_settings = latencies.simulation_settings();
+
+    if new_settings.tick_rate == settings.tick_rate
+        && (new_settings.input_delay as i32 - settings.input_delay as i32).abs() < 2
+    {
+        return;
+    }
+
+    // Give the message enough ticks to reach every peer before it takes effect.
+    let change = SimulationSettingsChange {
+        tick: tick.0 + 2 * settings.input_delay.max(new_settings.input_delay) as usize,
+        settings: new_settings,
+    };
+
+    info!(
+        "Changing simulation settings at tick {}: {:?}",
+        change.tick, change.settings
+    );
+
+    let data = serde_json::to_vec(&change).unwrap();
+    network_manager.publish(NetworkTopic::new("simulation_settings"), data);
+    commands.insert_resource(PendingSimulationSettingsChange(change));
+}
+
+fn apply_simulation_settings_change(
+    mut commands: Commands,
+    tick: Res<Tick>,
+    pending_change: Option<Res<PendingSimulationSettingsChange>>,
+    mut settings: ResMut<SimulationSettings>,
+    mut integration_parameters: ResMut<IntegrationParameters>,
+) {
+    let change = match pending_change {
+        Some(pending_change) => pending_change,
+        None => return,
+    };
+
+    if tick.0 < change.0.tick {
+        return;
+    }
+
+    *settings = change.0.settings;
+    integration_parameters.dt = settings.tick_duration();
+    commands.remove_resource::<PendingSimulationSettingsChange>();
+}
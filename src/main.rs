@@ -1,6 +1,7 @@
-use std::{f32::consts::PI, time::Duration};
+use std::time::Duration;
 
 use bevy::{
+    input::mouse::MouseWheel,
     math::Vec4Swizzles,
     prelude::*,
     reflect::TypeRegistry,
@@ -19,39 +20,76 @@ extern crate bitflags;
 
 mod app_state;
 mod cleanup;
+mod damage;
 mod debug;
 mod despawn;
 mod enemy;
+mod json_asset;
+mod latency;
 mod main_menu;
 mod network;
+mod network_diagnostics;
 mod party;
 mod physics;
 mod player;
+mod player_input;
+mod pool;
 mod random;
 mod resources;
 mod sound;
+mod spectator;
+mod tick;
 mod ui;
+mod waves;
 mod weapons;
 
+#[cfg(test)]
+mod tests;
+
 use app_state::{AppState, InitAppStatePlugin};
 use cleanup::{CleanupConfig, CleanupPlugin};
+use damage::{DamagePlugin, PlayerCharacter};
 use debug::{DebugPlugin, DebugRigidBodyIndex, DebugSimulationStateEvent};
 use despawn::DespawnPlugin;
 use enemy::EnemyPlugin;
+use latency::LatencyPlugin;
 use libp2p::Multiaddr;
 use main_menu::MainMenuPlugin;
+use network_diagnostics::NetworkDiagnosticsPlugin;
 use physics::PhysicsPlugin;
-use random::{Random, RandomPlugin};
+use player::PlayerId;
+use player_input::PlayerInputPlugin;
+use pool::PoolPlugin;
+use random::RandomPlugin;
 use resources::{
-    GameReplay, InitResourcesPlugin, MainCharacterInput, PbrResources, Tick, UIResources,
+    DelayedInputs, GameReplay, InitResourcesPlugin, MainCharacterInput, PbrResources, Score,
+    SimulationSettings, Tick, UIResources,
 };
+use spectator::SpectatorPlugin;
+use tick::{on_tick, tick_due, TickPlugin};
 use ui::UIPlugin;
+use waves::WavePlugin;
 use weapons::WeaponsPlugin;
 
-use crate::{network::NetworkPlugin, sound::InitSoundPlugin, weapons::{
-        Weapon, WeaponAmmoCount, WeaponBundle, WeaponCooldownTime, WeaponEnabled, WeaponFireMode,
-        WeaponReloadTime, WeaponTrigger,
-    }};
+use crate::{
+    enemy::{
+        archetype::EnemyArchetype,
+        boss::{Boss, BossPhaseEvent},
+        Enemy, EnemyBehavior,
+    },
+    network::{NetworkManager, NetworkPlugin},
+    party::Party,
+    sound::InitSoundPlugin,
+    weapons::{
+        ammo::AmmoReserve,
+        definition::{WeaponDefinition, WeaponDefinitions},
+        explosive::{GrenadeCount, STARTING_GRENADES},
+        held_weapon_transform,
+        inventory::{WeaponInventory, WeaponSwitch},
+        melee::MeleeCooldown,
+        Weapon, WeaponAmmoCount, WeaponBundle, WeaponEnabled, WeaponInput, WeaponReload,
+    },
+};
 
 bitflags! {
     struct PhysicsFlags: u32 {
@@ -59,11 +97,20 @@ bitflags! {
         const PLAYER =      0b0000_0000_0000_0000_0000_0000_0000_0001;
         const ENEMY =       0b0000_0000_0000_0000_0000_0000_0000_0010;
         const PROJECTILE =  0b0000_0000_0000_0000_0000_0000_0000_0100;
+        const ENEMY_PROJECTILE = 0b0000_0000_0000_0000_0000_0000_0000_1000;
+        const PICKUP =      0b0000_0000_0000_0000_0000_0000_0001_0000;
         const EFFECT =      0b0100_0000_0000_0000_0000_0000_0000_0000;
         const ENVIRONMENT = 0b1000_0000_0000_0000_0000_0000_0000_0000;
     }
 }
 
+const MAIN_CHARACTER_WALK_SPEED: f32 = 2.0;
+const MAIN_CHARACTER_RUN_SPEED: f32 = 4.0;
+/// Multiplier applied to the movement speed while aiming.
+const MAIN_CHARACTER_AIM_SPEED_MULTIPLIER: f32 = 0.5;
+/// Weapon definition characters spawn with.
+const STARTING_WEAPON: &str = "pistol_2";
+
 fn main() {
     App::build()
         .add_plugins(DefaultPlugins)
@@ -75,22 +122,40 @@ fn main() {
             wait_duration: Duration::from_secs(60),
             filter: Some(vec![
                 bevy::diagnostic::FrameTimeDiagnosticsPlugin::FRAME_TIME,
+                NetworkDiagnosticsPlugin::BYTES_IN,
+                NetworkDiagnosticsPlugin::BYTES_OUT,
+                NetworkDiagnosticsPlugin::MESSAGES_IN,
+                NetworkDiagnosticsPlugin::MESSAGES_OUT,
+                NetworkDiagnosticsPlugin::DUPLICATES,
+                PoolPlugin::PROJECTILES_ACTIVE,
+                PoolPlugin::PROJECTILES_OVERFLOW,
+                PoolPlugin::BLOOD_SPLATTERS_ACTIVE,
+                PoolPlugin::BLOOD_SPLATTERS_OVERFLOW,
             ]),
             ..Default::default()
         })
         .add_plugin(bevy::diagnostic::FrameTimeDiagnosticsPlugin::default())
         .add_plugin(NetworkPlugin)
+        .add_plugin(NetworkDiagnosticsPlugin)
+        .add_plugin(LatencyPlugin)
         .add_plugin(InitAppStatePlugin(AppState::MainMenu))
         .add_plugin(InitResourcesPlugin)
+        .add_plugin(TickPlugin)
         .add_plugin(InitSoundPlugin)
         .add_plugin(RandomPlugin)
         .add_plugin(DespawnPlugin)
+        .add_plugin(PoolPlugin)
         .add_plugin(CleanupPlugin)
+        .add_plugin(DamagePlugin)
         .add_plugin(DebugPlugin)
         .add_plugin(MainMenuPlugin)
         .add_plugin(UIPlugin)
         .add_plugin(WeaponsPlugin)
         .add_plugin(EnemyPlugin)
+        .add_plugin(WavePlugin)
+        .add_plugin(SpectatorPlugin)
+        .add_plugin(PlayerInputPlugin)
+        .add_plugin(SimulationPlugin)
         .add_startup_system(setup.system())
         // Update
         // In-game
@@ -99,26 +164,23 @@ fn main() {
                 .with_system(game_setup.system())
                 .with_system(game_setup_replay.system())
                 .with_system(game_setup_ui.system())
-                .with_system(game_setup_main_character.system())
                 .with_system(game_setup_environment.system()),
         )
         .add_system_set(
             SystemSet::on_update(AppState::InGame)
-                .with_system(game_main_character_input.system().label("character_input"))
-                .with_system(
-                    game_main_character_input_record
-                        .system()
-                        .after("character_input"),
-                )
+                .with_system(game_main_character_input.system().label("sample_input"))
                 .with_system(game_ui.system().after("character_input"))
                 .with_system(
-                    game_main_character_movement
+                    main_character_rotation
                         .system()
-                        .after("character_input"),
+                        .label("character_rotation")
+                        .before("sample_input"),
                 )
-                .with_system(main_character_rotation.system().label("character_rotation"))
                 // .with_system(main_character_shoot.system().after("character_rotation"))
                 .with_system(main_character_health.system())
+                .with_system(game_score_text.system())
+                .with_system(game_ammo_text.system())
+                .with_system(game_boss_health_bar.system())
                 .with_system(game_save.exclusive_system()),
         )
         .add_system_set(SystemSet::on_exit(AppState::InGame).with_system(game_cleanup.system()))
@@ -128,22 +190,22 @@ fn main() {
                 .with_system(replay_setup.system())
                 .with_system(game_setup.system())
                 .with_system(game_setup_ui.system())
-                .with_system(game_setup_main_character.system())
+                .with_system(replay_setup_main_character.system())
                 .with_system(game_setup_environment.system()),
         )
         .add_system_set(
-            SystemSet::on_update(AppState::Replay)
+            on_tick(AppState::Replay)
                 .with_system(
                     game_main_character_input_replay
                         .system()
                         .label("character_input"),
                 )
-                .with_system(game_ui.system().after("character_input"))
-                .with_system(
-                    game_main_character_movement
-                        .system()
-                        .after("character_input"),
-                ),
+                .with_system(game_character_movement.system().after("character_input"))
+                .with_system(game_character_rotation.system().after("character_input")),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::Replay)
+                .with_system(game_ui.system().after("character_input")),
         )
         // GameOver
         .add_system_set(
@@ -152,13 +214,48 @@ fn main() {
         .add_system_set(SystemSet::on_update(AppState::GameOver).with_system(game_ui.system()))
         // PostUpdate
         .add_system_to_stage(CoreStage::PostUpdate, game_camera_movement.system())
-        // Last
-        .add_system_to_stage(CoreStage::Last, game_increment_tick.system())
         .run();
 }
 
+/// Spawns the characters of the party when the game starts, and moves them from their inputs and
+/// advances the tick on every tick.
+///
+/// Rendering and the UI are left to `main`, so that headless apps run the same simulation.
+struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_enter(AppState::InGame).with_system(game_setup_characters.system()),
+        )
+        .add_system_set(
+            on_tick(AppState::InGame)
+                .with_system(
+                    game_main_character_input_apply
+                        .system()
+                        .label("character_input")
+                        .after("sample_input"),
+                )
+                .with_system(
+                    game_main_character_input_record
+                        .system()
+                        .after("character_input"),
+                )
+                .with_system(game_character_movement.system().after("character_input"))
+                .with_system(game_character_rotation.system().after("character_input")),
+        )
+        .add_system_to_stage(
+            CoreStage::Last,
+            game_increment_tick
+                .system()
+                .with_run_criteria(tick_due.system()),
+        );
+    }
+}
+
 struct InitialEnvironment {
     boundaries: Vec<Boundary>,
+    obstacles: Vec<Obstacle>,
     ball_template: BallTemplate,
 }
 
@@ -179,8 +276,23 @@ struct Boundary {
     collider_position: ColliderPosition,
 }
 
+struct Obstacle {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+    transform: Transform,
+    collider_shape: SharedShape,
+}
+
 struct TickText;
 
+struct ScoreText;
+
+struct AmmoText;
+
+struct BossHealthBarFrame;
+
+struct BossNameText;
+
 fn setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
@@ -224,6 +336,28 @@ fn setup(
         })
         .collect();
 
+    let pillar_half_extents = Vec3::new(1.5, 2.0, 1.5);
+    let pillar_mesh = meshes.add(Mesh::from(shape::Box::new(
+        2.0 * pillar_half_extents.x,
+        2.0 * pillar_half_extents.y,
+        2.0 * pillar_half_extents.z,
+    )));
+    let pillar_material = materials.add(Color::DARK_GRAY.into());
+
+    let obstacles = [(-10.0, -10.0), (10.0, -10.0), (-10.0, 10.0), (10.0, 10.0)]
+        .iter()
+        .map(|&(x, z)| Obstacle {
+            mesh: pillar_mesh.clone(),
+            material: pillar_material.clone(),
+            transform: Transform::from_xyz(x, pillar_half_extents.y, z),
+            collider_shape: ColliderShape::cuboid(
+                pillar_half_extents.x,
+                pillar_half_extents.y,
+                pillar_half_extents.z,
+            ),
+        })
+        .collect();
+
     let mut rigid_body_positions = Vec::new();
 
     for i in -5..=5 {
@@ -237,6 +371,7 @@ fn setup(
 
     commands.insert_resource(InitialEnvironment {
         boundaries,
+        obstacles,
         ball_template: BallTemplate {
             mesh: meshes.add(Mesh::from(shape::Icosphere {
                 radius: 1.0,
@@ -265,12 +400,18 @@ fn setup(
 fn game_setup(
     mut commands: Commands,
     mut tick: ResMut<Tick>,
+    mut score: ResMut<Score>,
+    mut delayed_inputs: ResMut<DelayedInputs>,
+    simulation_settings: Res<SimulationSettings>,
     mut rapier_config: ResMut<RapierConfiguration>,
-    asset_server: Res<AssetServer>,
+    mut integration_parameters: ResMut<IntegrationParameters>,
 ) {
     tick.0 = 0;
+    score.0 = 0;
+    delayed_inputs.0.clear();
 
     rapier_config.physics_pipeline_active = true;
+    integration_parameters.dt = simulation_settings.tick_duration();
 
     commands.spawn_bundle(LightBundle {
         light: Light {
@@ -281,16 +422,6 @@ fn game_setup(
         transform: Transform::from_xyz(0.0, 200.0, 400.0),
         ..Default::default()
     });
-
-    commands
-        .spawn_bundle((
-            Transform::from_scale(5.0 * Vec3::ONE),
-            GlobalTransform::identity(),
-            Name::new("Zombie"),
-        ))
-        .with_children(|parent| {
-            parent.spawn_scene(asset_server.load("models/low_poly_zombie/scene.gltf#Scene0"));
-        });
 }
 
 fn game_setup_replay(mut game_replay: ResMut<GameReplay>) {
@@ -356,6 +487,39 @@ fn game_setup_ui(mut commands: Commands, ui_resources: Res<UIResources>) {
                 })
                 .insert(TickText);
 
+            parent
+                .spawn_bundle(TextBundle {
+                    style: Style {
+                        margin: Rect::all(Val::Px(5.0)),
+                        ..Default::default()
+                    },
+                    text: Text {
+                        sections: vec![
+                            TextSection {
+                                value: String::from("Score: "),
+                                style: TextStyle {
+                                    font: ui_resources.font.clone(),
+                                    font_size: 24.0,
+                                    color: Color::GREEN,
+                                },
+                                ..Default::default()
+                            },
+                            TextSection {
+                                value: String::from("0"),
+                                style: TextStyle {
+                                    font: ui_resources.font.clone(),
+                                    font_size: 24.0,
+                                    color: Color::GREEN,
+                                },
+                                ..Default::default()
+                            },
+                        ],
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(ScoreText);
+
             parent
                 .spawn_bundle(button_bundle.clone())
                 .insert(ButtonType::RestartGame)
@@ -443,36 +607,213 @@ fn game_setup_ui(mut commands: Commands, ui_resources: Res<UIResources>) {
                         })
                         .insert(HealthBar);
                 });
+
+            parent
+                .spawn_bundle(TextBundle {
+                    style: Style {
+                        margin: Rect {
+                            left: Val::Px(10.0),
+                            bottom: Val::Px(5.0),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    },
+                    text: Text {
+                        sections: vec![
+                            TextSection {
+                                value: String::from("Ammo: "),
+                                style: TextStyle {
+                                    font: ui_resources.font.clone(),
+                                    font_size: 24.0,
+                                    color: Color::GREEN,
+                                },
+                                ..Default::default()
+                            },
+                            TextSection {
+                                value: String::new(),
+                                style: TextStyle {
+                                    font: ui_resources.font.clone(),
+                                    font_size: 24.0,
+                                    color: Color::GREEN,
+                                },
+                                ..Default::default()
+                            },
+                        ],
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(AmmoText);
+        });
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                display: Display::None,
+                position_type: PositionType::Absolute,
+                position: Rect::all(Val::Px(0.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::FlexStart,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: ui_resources.transparent.clone(),
+            ..Default::default()
+        })
+        .insert(BossHealthBarFrame)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    style: Style {
+                        margin: Rect::all(Val::Px(5.0)),
+                        ..Default::default()
+                    },
+                    text: Text {
+                        sections: vec![
+                            TextSection {
+                                value: String::new(),
+                                style: TextStyle {
+                                    font: ui_resources.font.clone(),
+                                    font_size: 28.0,
+                                    color: Color::RED,
+                                },
+                                ..Default::default()
+                            },
+                            TextSection {
+                                value: String::new(),
+                                style: TextStyle {
+                                    font: ui_resources.font.clone(),
+                                    font_size: 20.0,
+                                    color: Color::WHITE,
+                                },
+                                ..Default::default()
+                            },
+                        ],
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(BossNameText);
+
+            // Boss health bar
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(600.0), Val::Px(24.0)),
+                        ..Default::default()
+                    },
+                    material: ui_resources.white.clone(),
+                    ..Default::default()
+                })
+                .with_children(|parent| {
+                    parent
+                        .spawn_bundle(NodeBundle {
+                            style: Style {
+                                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                                min_size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+                                ..Default::default()
+                            },
+                            material: ui_resources.red.clone(),
+                            ..Default::default()
+                        })
+                        .insert(BossHealthBar);
+                });
         });
 }
 
-fn game_setup_main_character(
+/// Spawns a character for every player of the party that is not a spectator, so that every peer
+/// simulates all of them. The character of the local player becomes the main character.
+fn game_setup_characters(
     mut commands: Commands,
     pbr_resources: Res<PbrResources>,
-    asset_server: Res<AssetServer>,
+    weapon_definitions: Res<WeaponDefinitions>,
+    party: Option<Res<Party>>,
+    network_manager: Res<NetworkManager>,
 ) {
-    let control_camera = commands
-        .spawn_bundle(PerspectiveCameraBundle {
-            transform: Transform::from_xyz(10.0, 10.0, 10.0).looking_at(Vec3::ZERO, Vec3::Y),
-            ..Default::default()
-        })
-        .insert(MainCamera)
-        .id();
+    let local_player_id = PlayerId::new(network_manager.local_peer_id());
+    let player_ids = party.map_or_else(|| vec![local_player_id], |party| party.character_players());
+
+    for (index, &player_id) in player_ids.iter().enumerate() {
+        let character = spawn_character(
+            &mut commands,
+            &pbr_resources,
+            &weapon_definitions,
+            player_id,
+            character_spawn_position(index),
+        );
 
-    commands
+        if player_id == local_player_id {
+            insert_main_character(&mut commands, character, character_spawn_position(index));
+        }
+    }
+}
+
+/// Replays only have the inputs of the main character, so the other characters are left out.
+fn replay_setup_main_character(
+    mut commands: Commands,
+    pbr_resources: Res<PbrResources>,
+    weapon_definitions: Res<WeaponDefinitions>,
+    party: Option<Res<Party>>,
+    network_manager: Res<NetworkManager>,
+) {
+    let local_player_id = PlayerId::new(network_manager.local_peer_id());
+    let index = match party {
+        Some(party) => match party
+            .character_players()
+            .iter()
+            .position(|&player_id| player_id == local_player_id)
+        {
+            Some(index) => index,
+            None => return,
+        },
+        None => 0,
+    };
+
+    let character = spawn_character(
+        &mut commands,
+        &pbr_resources,
+        &weapon_definitions,
+        local_player_id,
+        character_spawn_position(index),
+    );
+    insert_main_character(&mut commands, character, character_spawn_position(index));
+}
+
+/// Characters are lined up in the order of `Party::character_players`.
+fn character_spawn_position(index: usize) -> Vec3 {
+    Vec3::new(2.0 * index as f32, 0.0, 0.0)
+}
+
+/// Offset of the control camera from the character it follows. The camera always looks at the
+/// character from this direction.
+fn control_camera_offset() -> Vec3 {
+    Vec3::new(10.0, 10.0, 10.0)
+}
+
+fn spawn_character(
+    commands: &mut Commands,
+    pbr_resources: &PbrResources,
+    weapon_definitions: &WeaponDefinitions,
+    player_id: PlayerId,
+    position: Vec3,
+) -> Entity {
+    let character = commands
         .spawn()
-        .insert(MainCharacter {
-            control_camera,
-            control_camera_offset: Vec3::new(10.0, 10.0, 10.0),
-        })
+        .insert(PlayerCharacter(player_id))
         .insert(MainCharacterMovement {
             want_to_move: Vec2::ZERO,
-            walk_speed: 2.0,
-            run_speed: 4.0,
+            want_to_face: 0.0,
+            walk_speed: MAIN_CHARACTER_WALK_SPEED,
+            run_speed: MAIN_CHARACTER_RUN_SPEED,
         })
         .insert(Health(100.0))
+        .insert(GrenadeCount(STARTING_GRENADES))
+        .insert(AmmoReserve::starting())
+        .insert(WeaponInput::default())
+        .insert(MeleeCooldown::default())
         .insert_bundle(RigidBodyBundle {
             body_type: RigidBodyType::Dynamic,
+            position: position.into(),
             mass_properties: RigidBodyMassPropsFlags::ROTATION_LOCKED.into(),
             ..Default::default()
         })
@@ -486,60 +827,50 @@ fn game_setup_main_character(
         })
         .insert(RigidBodyPositionSync::Discrete)
         .with_children(|parent| {
-            // Main character model
+            // Character model
             parent.spawn_bundle(PbrBundle {
                 mesh: pbr_resources.main_character_mesh.clone(),
                 material: pbr_resources.main_character_material.clone(),
                 transform: Transform::from_xyz(0.0, 1.0, 0.0),
                 ..Default::default()
             });
+        })
+        .id();
 
-            // Weapon model
-            parent
-                .spawn_bundle(WeaponBundle {
-                    weapon: Weapon {
-                        ammo_capacity: 30,
-                        rate_of_file: 10.0,
-                        reload_time: 1.0,
-                        projectile_scene: asset_server
-                            .load("models/weapons/pistol_bullet/scene.gltf#Scene0"),
-                        projectile_shape: SharedShape::capsule(
-                            point!(0.0, 0.0, -0.1),
-                            point!(0.0, 0.0, 0.1),
-                            0.1,
-                        ),
-                        shoot_sound: asset_server.load("sounds/pistol_shoot.mp3"),
-                        reload_sound: asset_server.load("sounds/pistol_reload.mp3"),
-                    },
-                    fire_mode: WeaponFireMode::Semi,
-                    trigger: WeaponTrigger {
-                        release_required: true,
-                    },
-                    ammo_count: WeaponAmmoCount(20),
-                    cooldown_time: WeaponCooldownTime(0.0),
-                    reload_time: WeaponReloadTime(0.0),
-                    transform: Transform::from_xyz(0.0, 1.5, 0.7),
-                    global_transform: GlobalTransform::identity(),
-                })
-                .insert(WeaponEnabled)
-                .with_children(|parent| {
-                    parent
-                        .spawn_bundle((
-                            Transform::from_xyz(0.0, -0.2, 0.0)
-                                * Transform::from_rotation(Quat::from_axis_angle(
-                                    Vec3::Y,
-                                    -PI / 2.0,
-                                ))
-                                * Transform::from_scale(0.2 * Vec3::ONE),
-                            GlobalTransform::identity(),
-                        ))
-                        .with_children(|parent| {
-                            parent.spawn_scene(
-                                asset_server.load("models/weapons/pistol_2/scene.gltf#Scene0"),
-                            );
-                        });
-                });
-        });
+    if let Some(definition) = weapon_definitions.handles.get(STARTING_WEAPON) {
+        let weapon = commands
+            .spawn_bundle(WeaponBundle::new(
+                definition.clone(),
+                held_weapon_transform(),
+            ))
+            .insert(WeaponEnabled)
+            .id();
+
+        commands
+            .entity(character)
+            .push_children(&[weapon])
+            .insert(WeaponInventory::new(weapon));
+    }
+
+    character
+}
+
+/// Makes a character the one controlled by the local player, followed by the control camera.
+fn insert_main_character(commands: &mut Commands, character: Entity, position: Vec3) {
+    let control_camera = commands
+        .spawn_bundle(PerspectiveCameraBundle {
+            transform: Transform::from_translation(position + control_camera_offset())
+                .looking_at(position, Vec3::Y),
+            ..Default::default()
+        })
+        .insert(MainCamera)
+        .id();
+
+    commands.entity(character).insert(MainCharacter {
+        control_camera,
+        control_camera_offset: control_camera_offset(),
+        aim_facing: 0.0,
+    });
 }
 
 fn game_setup_environment(
@@ -563,6 +894,28 @@ fn game_setup_environment(
             });
     }
 
+    for obstacle in &initial_environment.obstacles {
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: obstacle.mesh.clone(),
+                material: obstacle.material.clone(),
+                transform: obstacle.transform,
+                ..Default::default()
+            })
+            .insert_bundle(ColliderBundle {
+                shape: obstacle.collider_shape.clone(),
+                position: obstacle.transform.translation.into(),
+                flags: ColliderFlags {
+                    collision_groups: InteractionGroups::new(
+                        PhysicsFlags::ENVIRONMENT.bits(),
+                        u32::MAX,
+                    ),
+                    ..Default::default()
+                },
+                ..Default::default()
+            });
+    }
+
     return;
 
     let mut count = 0;
@@ -618,11 +971,39 @@ fn game_ui(
     }
 }
 
+/// Samples the input of the main character, to be applied `input_delay` ticks later so that it
+/// has time to reach the other peers. Inputs are only queued, and sent as events, when they change.
 fn game_main_character_input(
+    tick: Res<Tick>,
+    simulation_settings: Res<SimulationSettings>,
     input: Res<Input<KeyCode>>,
-    mut query: Query<&mut MainCharacterMovement>,
+    mouse_input: Res<Input<MouseButton>>,
+    mut mouse_wheel_events: EventReader<MouseWheel>,
+    mut delayed_inputs: ResMut<DelayedInputs>,
+    mut input_events: EventWriter<MainCharacterInput>,
+    mut asked: Local<WeaponInput>,
+    query: Query<(&MainCharacter, &MainCharacterMovement)>,
 ) {
-    for mut movement in query.iter_mut() {
+    let scroll: f32 = mouse_wheel_events.iter().map(|event| event.y).sum();
+
+    // Switches and throws are only asked for on one frame, so they are kept until the next sample
+    asked.switch = WeaponSwitch::from_input(&input, scroll).or(asked.switch);
+    asked.throw |= input.just_pressed(KeyCode::G);
+
+    // Every tick gets exactly one input, as the other peers wait for it. When the input delay
+    // shrinks, sampling waits for the ticks to catch up with the queued inputs, and when it grows,
+    // the ticks in between get the same input.
+    let apply_tick = tick.0 + simulation_settings.input_delay as usize;
+    let first_tick = delayed_inputs
+        .0
+        .back()
+        .map_or(tick.0, |latest| latest.tick.0 + 1);
+
+    if first_tick > apply_tick {
+        return;
+    }
+
+    for (character, movement) in query.iter() {
         let mut want_to_move = Vec2::ZERO;
 
         if input.pressed(KeyCode::W) {
@@ -641,16 +1022,70 @@ fn game_main_character_input(
             want_to_move.x += 1.0;
         }
 
-        let speed = if input.pressed(KeyCode::LShift) {
+        let aim = mouse_input.pressed(MouseButton::Right);
+
+        let mut speed = if input.pressed(KeyCode::LShift) {
             movement.run_speed
         } else {
             movement.walk_speed
         };
 
+        if aim {
+            speed *= MAIN_CHARACTER_AIM_SPEED_MULTIPLIER;
+        }
+
         want_to_move = speed * want_to_move.normalize_or_zero();
 
-        if movement.want_to_move != want_to_move {
-            movement.want_to_move = want_to_move;
+        let mut weapon_input = WeaponInput {
+            fire: mouse_input.pressed(MouseButton::Left),
+            aim,
+            melee: input.pressed(KeyCode::V),
+            switch: asked.switch,
+            throw: asked.throw,
+            reload: input.pressed(KeyCode::R),
+        };
+
+        for input_tick in first_tick..=apply_tick {
+            let sampled = MainCharacterInput {
+                tick: Tick(input_tick),
+                movement: want_to_move,
+                facing: character.aim_facing,
+                weapon_input,
+            };
+
+            delayed_inputs.0.push_back(sampled);
+            input_events.send(sampled);
+
+            weapon_input.switch = None;
+            weapon_input.throw = false;
+        }
+
+        *asked = WeaponInput::default();
+    }
+}
+
+/// Applies the sampled inputs of the main character that are due this tick.
+fn game_main_character_input_apply(
+    tick: Res<Tick>,
+    mut delayed_inputs: ResMut<DelayedInputs>,
+    mut query: Query<(&mut MainCharacterMovement, &mut WeaponInput), With<MainCharacter>>,
+) {
+    while delayed_inputs
+        .0
+        .front()
+        .map_or(false, |input| input.tick.0 <= tick.0)
+    {
+        let input = delayed_inputs.0.pop_front().unwrap();
+
+        for (mut movement, mut weapon_input) in query.iter_mut() {
+            if movement.want_to_move != input.movement || movement.want_to_face != input.facing {
+                movement.want_to_move = input.movement;
+                movement.want_to_face = input.facing;
+            }
+
+            if *weapon_input != input.weapon_input {
+                *weapon_input = input.weapon_input;
+            }
         }
     }
 }
@@ -658,11 +1093,17 @@ fn game_main_character_input(
 fn game_main_character_input_record(
     tick: Res<Tick>,
     mut game_replay: ResMut<GameReplay>,
-    query: Query<&MainCharacterMovement, Changed<MainCharacterMovement>>,
+    query: Query<
+        (&MainCharacterMovement, &WeaponInput),
+        (
+            With<MainCharacter>,
+            Or<(Changed<MainCharacterMovement>, Changed<WeaponInput>)>,
+        ),
+    >,
 ) {
     game_replay.tick = *tick;
 
-    for movement in query.iter() {
+    for (movement, weapon_input) in query.iter() {
         // info!(
         //     "Tick {} recorded movement {}",
         //     tick.0, movement.want_to_move
@@ -670,6 +1111,8 @@ fn game_main_character_input_record(
         game_replay.main_character_inputs.push(MainCharacterInput {
             tick: *tick,
             movement: movement.want_to_move,
+            facing: movement.want_to_face,
+            weapon_input: *weapon_input,
         });
     }
 }
@@ -679,7 +1122,7 @@ fn game_main_character_input_replay(
     mut debug_events: EventWriter<DebugSimulationStateEvent>,
     mut game_replay: ResMut<GameReplay>,
     mut rapier_config: ResMut<RapierConfiguration>,
-    mut query: Query<&mut MainCharacterMovement>,
+    mut query: Query<(&mut MainCharacterMovement, &mut WeaponInput), With<MainCharacter>>,
 ) {
     if tick.0 >= game_replay.tick.0 {
         if rapier_config.physics_pipeline_active {
@@ -698,8 +1141,10 @@ fn game_main_character_input_replay(
         let input = &game_replay.main_character_inputs[game_replay.main_character_inputs_index];
 
         if input.tick.0 == tick.0 {
-            let mut movement = query.single_mut().unwrap();
+            let (mut movement, mut weapon_input) = query.single_mut().unwrap();
             movement.want_to_move = input.movement;
+            movement.want_to_face = input.facing;
+            *weapon_input = input.weapon_input;
             game_replay.main_character_inputs_index += 1;
             return;
         } else if input.tick.0 > tick.0 {
@@ -710,26 +1155,24 @@ fn game_main_character_input_replay(
     }
 }
 
-fn game_main_character_movement(
-    camera_query: Query<&Transform, With<Camera>>,
-    mut character_query: Query<(
-        &MainCharacter,
-        &MainCharacterMovement,
-        &mut RigidBodyVelocity,
-    )>,
+/// Moves characters relative to the view of the control camera. As it always looks at characters
+/// from the same direction, remote characters move the same as on the peer that controls them.
+fn game_character_movement(
+    mut character_query: Query<(&MainCharacterMovement, &mut RigidBodyVelocity)>,
 ) {
-    for (character, character_movement, mut body_velocity) in character_query.iter_mut() {
-        let camera_transform = camera_query.get(character.control_camera).unwrap();
-
-        let mut velocity = character_movement.want_to_move.x * camera_transform.local_x();
+    let camera_transform =
+        Transform::from_translation(control_camera_offset()).looking_at(Vec3::ZERO, Vec3::Y);
 
-        let mut forward = camera_transform.local_z();
+    let mut forward = camera_transform.local_z();
+    forward.y = 0.0;
+    forward = forward.try_normalize().unwrap_or_else(|| {
+        let mut forward = camera_transform.local_y();
         forward.y = 0.0;
-        forward = forward.try_normalize().unwrap_or_else(|| {
-            let mut forward = camera_transform.local_y();
-            forward.y = 0.0;
-            forward.try_normalize().unwrap() // Should not be zero at this point
-        });
+        forward.try_normalize().unwrap() // Should not be zero at this point
+    });
+
+    for (character_movement, mut body_velocity) in character_query.iter_mut() {
+        let mut velocity = character_movement.want_to_move.x * camera_transform.local_x();
 
         velocity += character_movement.want_to_move.y * forward;
         velocity.y = body_velocity.linvel.y;
@@ -738,10 +1181,26 @@ fn game_main_character_movement(
     }
 }
 
+/// Turns characters to the facing of their input.
+fn game_character_rotation(
+    mut character_query: Query<
+        (&MainCharacterMovement, &mut RigidBodyPosition),
+        Changed<MainCharacterMovement>,
+    >,
+) {
+    for (character_movement, mut position) in character_query.iter_mut() {
+        position.position.rotation = Rotation::from_axis_angle(
+            &UnitVector::new_unchecked(Vector::y()),
+            character_movement.want_to_face,
+        );
+    }
+}
+
+/// Aims the main character at the cursor, to be sampled with the rest of its input.
 fn main_character_rotation(
     windows: Res<Windows>,
     camera_query: Query<(&Transform, &PerspectiveProjection), With<MainCamera>>,
-    mut character_query: Query<&mut RigidBodyPosition, With<MainCharacter>>,
+    mut character_query: Query<(&mut MainCharacter, &RigidBodyPosition)>,
 ) {
     for (camera_transform, camera_projection) in camera_query.iter() {
         let window = windows.get_primary().unwrap();
@@ -763,7 +1222,7 @@ fn main_character_rotation(
         let mut cursor_direction = cursor_world_position.xyz() - camera_transform.translation;
         cursor_direction = cursor_direction.try_normalize().unwrap();
 
-        for mut character_position in character_query.iter_mut() {
+        for (mut character, character_position) in character_query.iter_mut() {
             let mut cursor_plane_position = camera_transform.translation
                 + cursor_direction
                     * (camera_transform.translation.y
@@ -772,14 +1231,15 @@ fn main_character_rotation(
                     / -cursor_direction.y;
             cursor_plane_position.y = character_position.position.translation.y;
 
-            let forward = Vec3::normalize(
-                cursor_plane_position - character_position.position.translation.into(),
-            );
-            let right = Vec3::Y.cross(forward).normalize();
-            let up = forward.cross(right);
+            let character_translation: Vec3 = character_position.position.translation.into();
+            let forward = cursor_plane_position - character_translation;
 
-            character_position.position.rotation =
-                Quat::from_rotation_mat3(&Mat3::from_cols(right, up, forward)).into();
+            // The cursor is right on the character
+            if forward.x == 0.0 && forward.z == 0.0 {
+                continue;
+            }
+
+            character.aim_facing = forward.x.atan2(forward.z);
         }
     }
 }
@@ -836,7 +1296,7 @@ fn game_save(world: &mut World) {
 fn game_cleanup(
     mut commands: Commands,
     mut debug_events: EventWriter<DebugSimulationStateEvent>,
-    query: Query<Entity, With<MainCharacter>>,
+    query: Query<Entity, With<PlayerCharacter>>,
 ) {
     debug_events.send(DebugSimulationStateEvent::Record);
 
@@ -861,6 +1321,104 @@ fn game_increment_tick(
     }
 }
 
+/// Waits until the next tick is due, so that the simulation runs at the tick rate whatever the
+/// frame rate. Late frames are not made up for, the simulation slows down instead.
+fn game_score_text(score: Res<Score>, mut query: Query<&mut Text, With<ScoreText>>) {
+    if !score.is_changed() {
+        return;
+    }
+
+    for mut text in query.iter_mut() {
+        text.sections[1].value = format!("{}", score.0);
+    }
+}
+
+/// Shows the magazine and reserve ammo of the active weapon of the main character.
+fn game_ammo_text(
+    definitions: Res<Assets<WeaponDefinition>>,
+    character_query: Query<(&WeaponInventory, Option<&AmmoReserve>), With<MainCharacter>>,
+    weapon_query: Query<(&Weapon, &WeaponAmmoCount, &WeaponReload)>,
+    mut text_query: Query<&mut Text, With<AmmoText>>,
+) {
+    let (inventory, reserve) = match character_query.single() {
+        Ok(components) => components,
+        Err(_) => return,
+    };
+
+    let value = match inventory
+        .active_weapon()
+        .and_then(|weapon| weapon_query.get(weapon).ok())
+    {
+        Some((weapon, ammo_count, reload)) => {
+            let mut value = format!("{}", ammo_count.0);
+
+            if let (Some(reserve), Some(definition)) =
+                (reserve, definitions.get(&weapon.definition))
+            {
+                value += &format!(" / {}", reserve.count(definition.ammo_type));
+            }
+
+            if reload.done_tick.is_some() {
+                value += " (reloading)";
+            }
+
+            value
+        }
+        None => String::from("-"),
+    };
+
+    for mut text in text_query.iter_mut() {
+        if text.sections[1].value != value {
+            text.sections[1].value = value.clone();
+        }
+    }
+}
+
+/// Shows the health of the first living boss, hiding the bar while there is none.
+fn game_boss_health_bar(
+    archetypes: Res<Assets<EnemyArchetype>>,
+    mut phase_events: EventReader<BossPhaseEvent>,
+    boss_query: Query<(&Enemy, &EnemyBehavior, &Health), With<Boss>>,
+    mut frame_query: Query<&mut Style, With<BossHealthBarFrame>>,
+    mut bar_query: Query<&mut Style, (With<BossHealthBar>, Without<BossHealthBarFrame>)>,
+    mut text_query: Query<&mut Text, With<BossNameText>>,
+) {
+    for phase_event in phase_events.iter() {
+        let name = match boss_query
+            .get(phase_event.boss)
+            .ok()
+            .and_then(|(enemy, _, _)| archetypes.get(&enemy.archetype))
+            .and_then(|archetype| archetype.boss.as_ref())
+        {
+            Some(definition) => definition.name.clone(),
+            None => continue,
+        };
+
+        for mut text in text_query.iter_mut() {
+            text.sections[0].value = name.clone();
+            text.sections[1].value = format!("  Phase {}", phase_event.phase + 1);
+        }
+    }
+
+    let boss = boss_query
+        .iter()
+        .find(|(_, behavior, _)| !matches!(behavior, EnemyBehavior::Death));
+
+    for mut style in frame_query.iter_mut() {
+        style.display = if boss.is_some() {
+            Display::Flex
+        } else {
+            Display::None
+        };
+    }
+
+    if let Some((enemy, _, health)) = boss {
+        for mut style in bar_query.iter_mut() {
+            style.size.width = Val::Percent(100.0 * health.0.max(0.0) / enemy.max_health);
+        }
+    }
+}
+
 fn replay_setup(mut tick: ResMut<Tick>, mut game_replay: ResMut<GameReplay>) {
     tick.0 = 0;
 
@@ -977,10 +1535,14 @@ struct MainCamera;
 struct MainCharacter {
     control_camera: Entity,
     control_camera_offset: Vec3,
+    /// Facing towards the cursor, in radians around the up axis.
+    aim_facing: f32,
 }
 
 struct MainCharacterMovement {
     want_to_move: Vec2,
+    /// Facing in radians around the up axis, from the forward axis.
+    want_to_face: f32,
     walk_speed: f32,
     run_speed: f32,
 }
@@ -989,6 +1551,8 @@ pub struct Health(f32);
 
 pub struct HealthBar;
 
+pub struct BossHealthBar;
+
 enum ButtonType {
     CreateLobby,
     JoinLobby(Multiaddr),
@@ -7,7 +7,7 @@ use crate::{
     cleanup::CleanupConfig,
     network::{NetworkAddress, NetworkManager, NetworkTopic},
     party::Party,
-    player::{Player, PlayerId},
+    player::{Player, PlayerId, PlayerRole},
     resources::UIResources,
 };
 
@@ -241,13 +241,13 @@ fn create_lobby(
     let player = Player {
         id: PlayerId::new(network_manager.local_peer_id()),
         name: player_name,
+        role: PlayerRole::Player,
     };
 
     commands.insert_resource(Party::new(player));
 
-    network_manager
-        .listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap())
-        .unwrap();
+    let listen_address = network_manager.any_address();
+    network_manager.listen_on(listen_address).unwrap();
     network_manager.subscribe(NetworkTopic::new("join_request"));
 
     cleanup_config.next_state_after_cleanup = Some(AppState::InLobby);
@@ -265,13 +265,13 @@ fn join_lobby(
     let player = Player {
         id: PlayerId::new(network_manager.local_peer_id()),
         name: player_name,
+        role: PlayerRole::Player,
     };
 
     commands.insert_resource(Party::new(player));
 
-    network_manager
-        .listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap())
-        .unwrap();
+    let listen_address = network_manager.any_address();
+    network_manager.listen_on(listen_address).unwrap();
     network_manager.subscribe(NetworkTopic::new("join_accepted"));
     network_manager.subscribe(NetworkTopic::new("join_rejected"));
     network_manager.dial_addr(address);
@@ -1,7 +1,7 @@
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
     sync::mpsc as sync_mpsc,
-    sync::Mutex,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
 };
 
@@ -12,17 +12,25 @@ use futures::{
 };
 pub use libp2p::{core::connection::ListenerId, PeerId, TransportError};
 use libp2p::{
+    core::{
+        connection::ConnectionId,
+        muxing::StreamMuxerBox,
+        transport::{Boxed, MemoryTransport},
+        upgrade, ConnectedPoint,
+    },
     development_transport,
     gossipsub::{
-        error::PublishError, Gossipsub, GossipsubConfigBuilder, GossipsubEvent, IdentTopic,
-        MessageAuthenticity,
+        error::PublishError, time_cache::DuplicateCache, Gossipsub, GossipsubConfig,
+        GossipsubConfigBuilder, GossipsubEvent, GossipsubMessage, IdentTopic, MessageAuthenticity,
+        MessageId, TopicHash,
     },
     identity::Keypair,
+    mplex, noise,
     swarm::{
-        IntoProtocolsHandler, NetworkBehaviourEventProcess, PollParameters, ProtocolsHandler,
-        SwarmEvent,
+        IntoProtocolsHandler, NetworkBehaviour as _, NetworkBehaviourEventProcess, PollParameters,
+        ProtocolsHandler, SwarmEvent,
     },
-    Multiaddr, Swarm,
+    Multiaddr, Swarm, Transport,
 };
 
 pub struct NetworkPlugin;
@@ -38,7 +46,7 @@ impl Plugin for NetworkPlugin {
 #[derive(libp2p::NetworkBehaviour)]
 #[behaviour(out_event = "NetworkBehaviourEvent", poll_method = "poll")]
 pub struct NetworkBehaviour {
-    pub gossipsub: Gossipsub,
+    pub gossipsub: CountingGossipsub,
     #[behaviour(ignore)]
     events: VecDeque<NetworkBehaviourAction>,
 }
@@ -70,17 +78,369 @@ pub enum NetworkBehaviourEvent {
     Gossipsub(GossipsubEvent),
 }
 
+/// Gossipsub behaviour that counts the traffic the application never sees: duplicate messages,
+/// which gossipsub drops, and the messages sent to each peer, which gossipsub picks.
+///
+/// Duplicates are detected like gossipsub does, by message id within the duplicate cache time.
+pub struct CountingGossipsub {
+    gossipsub: Gossipsub,
+    config: GossipsubConfig,
+    seen: DuplicateCache<MessageId>,
+    stats: Arc<Mutex<NetworkStats>>,
+}
+
+impl CountingGossipsub {
+    fn new(gossipsub: Gossipsub, config: GossipsubConfig, stats: Arc<Mutex<NetworkStats>>) -> Self {
+        Self {
+            gossipsub,
+            seen: DuplicateCache::new(config.duplicate_cache_time()),
+            config,
+            stats,
+        }
+    }
+
+    /// Publishes a message, remembering its id so that copies relayed back count as duplicates.
+    pub fn publish(
+        &mut self,
+        topic: NetworkTopic,
+        data: impl Into<Vec<u8>>,
+    ) -> Result<MessageId, PublishError> {
+        let message_id = self.gossipsub.publish(topic, data)?;
+
+        self.seen.insert(message_id.clone());
+
+        Ok(message_id)
+    }
+}
+
+impl std::ops::Deref for CountingGossipsub {
+    type Target = Gossipsub;
+
+    fn deref(&self) -> &Gossipsub {
+        &self.gossipsub
+    }
+}
+
+impl std::ops::DerefMut for CountingGossipsub {
+    fn deref_mut(&mut self) -> &mut Gossipsub {
+        &mut self.gossipsub
+    }
+}
+
+impl libp2p::swarm::NetworkBehaviour for CountingGossipsub {
+    type ProtocolsHandler = <Gossipsub as libp2p::swarm::NetworkBehaviour>::ProtocolsHandler;
+    type OutEvent = GossipsubEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        self.gossipsub.new_handler()
+    }
+
+    fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        self.gossipsub.addresses_of_peer(peer_id)
+    }
+
+    fn inject_connected(&mut self, peer_id: &PeerId) {
+        self.gossipsub.inject_connected(peer_id)
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId) {
+        self.gossipsub.inject_disconnected(peer_id)
+    }
+
+    fn inject_connection_established(
+        &mut self,
+        peer_id: &PeerId,
+        connection: &ConnectionId,
+        endpoint: &ConnectedPoint,
+    ) {
+        self.gossipsub
+            .inject_connection_established(peer_id, connection, endpoint)
+    }
+
+    fn inject_connection_closed(
+        &mut self,
+        peer_id: &PeerId,
+        connection: &ConnectionId,
+        endpoint: &ConnectedPoint,
+    ) {
+        self.gossipsub
+            .inject_connection_closed(peer_id, connection, endpoint)
+    }
+
+    fn inject_address_change(
+        &mut self,
+        peer_id: &PeerId,
+        connection: &ConnectionId,
+        old: &ConnectedPoint,
+        new: &ConnectedPoint,
+    ) {
+        self.gossipsub
+            .inject_address_change(peer_id, connection, old, new)
+    }
+
+    fn inject_event(
+        &mut self,
+        peer_id: PeerId,
+        connection: ConnectionId,
+        event: GossipsubHandlerEvent,
+    ) {
+        if let GossipsubHandlerEvent::Message { rpc, .. } = &event {
+            for message in rpc.messages.iter() {
+                let message_id = self.config.message_id(&GossipsubMessage {
+                    source: message.source,
+                    data: message.data.clone(),
+                    sequence_number: message.sequence_number,
+                    topic: message.topic.clone(),
+                });
+
+                if !self.seen.insert(message_id) {
+                    self.stats
+                        .lock()
+                        .unwrap()
+                        .record_duplicate(&message.topic, peer_id);
+                }
+            }
+        }
+
+        self.gossipsub.inject_event(peer_id, connection, event)
+    }
+
+    fn inject_addr_reach_failure(
+        &mut self,
+        peer_id: Option<&PeerId>,
+        addr: &Multiaddr,
+        error: &dyn std::error::Error,
+    ) {
+        self.gossipsub
+            .inject_addr_reach_failure(peer_id, addr, error)
+    }
+
+    fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+        self.gossipsub.inject_dial_failure(peer_id)
+    }
+
+    fn inject_new_listener(&mut self, id: ListenerId) {
+        self.gossipsub.inject_new_listener(id)
+    }
+
+    fn inject_new_listen_addr(&mut self, id: ListenerId, addr: &Multiaddr) {
+        self.gossipsub.inject_new_listen_addr(id, addr)
+    }
+
+    fn inject_expired_listen_addr(&mut self, id: ListenerId, addr: &Multiaddr) {
+        self.gossipsub.inject_expired_listen_addr(id, addr)
+    }
+
+    fn inject_listener_error(&mut self, id: ListenerId, error: &(dyn std::error::Error + 'static)) {
+        self.gossipsub.inject_listener_error(id, error)
+    }
+
+    fn inject_listener_closed(&mut self, id: ListenerId, reason: Result<(), &std::io::Error>) {
+        self.gossipsub.inject_listener_closed(id, reason)
+    }
+
+    fn inject_new_external_addr(&mut self, addr: &Multiaddr) {
+        self.gossipsub.inject_new_external_addr(addr)
+    }
+
+    fn inject_expired_external_addr(&mut self, addr: &Multiaddr) {
+        self.gossipsub.inject_expired_external_addr(addr)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+        params: &mut impl PollParameters,
+    ) -> Poll<GossipsubAction> {
+        let action = match self.gossipsub.poll(cx, params) {
+            Poll::Ready(action) => action,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        if let GossipsubAction::NotifyHandler { peer_id, event, .. } = &action {
+            if !event.publish.is_empty() {
+                let bytes = event
+                    .publish
+                    .iter()
+                    .map(|message| message.data.as_ref().map_or(0, Vec::len))
+                    .sum();
+
+                self.stats
+                    .lock()
+                    .unwrap()
+                    .record_sent(*peer_id, event.publish.len(), bytes);
+            }
+        }
+
+        Poll::Ready(action)
+    }
+}
+
+/// Selects the transport the swarm is built on.
+///
+/// Insert this as a resource before adding `NetworkPlugin` to override the default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkTransport {
+    /// TCP/DNS/WebSocket transport used by the game.
+    Development,
+    /// In-process transport, used to connect several headless `App`s in the same process.
+    Memory,
+}
+
+impl Default for NetworkTransport {
+    fn default() -> Self {
+        Self::Development
+    }
+}
+
+/// Message and byte counters for one topic or one peer.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NetworkCounters {
+    pub messages_in: u64,
+    pub messages_out: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub duplicates: u64,
+}
+
+impl std::ops::AddAssign for NetworkCounters {
+    fn add_assign(&mut self, other: Self) {
+        self.messages_in += other.messages_in;
+        self.messages_out += other.messages_out;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.duplicates += other.duplicates;
+    }
+}
+
+/// Gossipsub traffic, counted since the manager was created.
+///
+/// Topics count the messages the application publishes and receives. Peers count the messages
+/// received from them, and every message sent to them, including the ones relayed for other peers.
+/// Both count the duplicates gossipsub drops.
+#[derive(Default)]
+pub struct NetworkStats {
+    pub topics: HashMap<TopicHash, NetworkCounters>,
+    pub peers: HashMap<PeerId, NetworkCounters>,
+}
+
+impl NetworkStats {
+    pub fn total(&self) -> NetworkCounters {
+        let mut total = NetworkCounters::default();
+
+        for &counters in self.topics.values() {
+            total += counters;
+        }
+
+        total
+    }
+
+    fn record_outgoing(&mut self, topic: TopicHash, bytes: usize) {
+        let counters = self.topics.entry(topic).or_default();
+        counters.messages_out += 1;
+        counters.bytes_out += bytes as u64;
+    }
+
+    fn record_incoming(&mut self, topic: &TopicHash, peer_id: PeerId, bytes: usize) {
+        let record = |counters: &mut NetworkCounters| {
+            counters.messages_in += 1;
+            counters.bytes_in += bytes as u64;
+        };
+
+        record(self.topics.entry(topic.clone()).or_default());
+        record(self.peers.entry(peer_id).or_default());
+    }
+
+    fn record_duplicate(&mut self, topic: &TopicHash, peer_id: PeerId) {
+        self.topics.entry(topic.clone()).or_default().duplicates += 1;
+        self.peers.entry(peer_id).or_default().duplicates += 1;
+    }
+
+    fn record_sent(&mut self, peer_id: PeerId, messages: usize, bytes: usize) {
+        let counters = self.peers.entry(peer_id).or_default();
+        counters.messages_out += messages as u64;
+        counters.bytes_out += bytes as u64;
+    }
+
+    fn merge(&mut self, other: NetworkStats) {
+        for (topic, counters) in other.topics {
+            *self.topics.entry(topic).or_default() += counters;
+        }
+
+        for (peer_id, counters) in other.peers {
+            *self.peers.entry(peer_id).or_default() += counters;
+        }
+    }
+}
+
 pub struct NetworkManager {
     local_peer_id: PeerId,
+    transport: NetworkTransport,
+    stats: NetworkStats,
+    /// Counted by the swarm task, merged into `stats` every frame.
+    swarm_stats: Arc<Mutex<NetworkStats>>,
     command_tx: async_mpsc::UnboundedSender<NetworkCommand>,
     event_rx: Mutex<sync_mpsc::Receiver<NetworkEvent>>,
 }
 
 impl NetworkManager {
+    pub fn new(io_task_pool: &IoTaskPool, transport: NetworkTransport) -> Self {
+        let local_key = Keypair::generate_ed25519();
+        let local_peer_id = PeerId::from_public_key(local_key.public());
+        let (command_tx, mut command_rx) = async_mpsc::unbounded::<NetworkCommand>();
+        let (event_tx, event_rx) = sync_mpsc::channel::<NetworkEvent>();
+        let event_rx = Mutex::new(event_rx);
+        let swarm_stats = Arc::new(Mutex::new(NetworkStats::default()));
+        let task_stats = swarm_stats.clone();
+
+        io_task_pool
+            .spawn(async move {
+                let mut swarm = create_network_swarm(local_key, local_peer_id, transport, task_stats);
+
+                loop {
+                    select! {
+                        command = command_rx.select_next_some() => handle_network_command(&mut swarm, command),
+                        event = swarm.next_event().fuse() => {
+                            // handle_network_event(&mut swarm, &event);
+                            event_tx.send(event).unwrap();
+                        }
+                    }
+                }
+            })
+            .detach();
+
+        block_on(poll_fn(|context| command_tx.poll_ready(context))).unwrap();
+
+        Self {
+            local_peer_id,
+            transport,
+            stats: NetworkStats::default(),
+            swarm_stats,
+            command_tx,
+            event_rx,
+        }
+    }
+
     pub fn local_peer_id(&self) -> PeerId {
         self.local_peer_id
     }
 
+    pub fn transport(&self) -> NetworkTransport {
+        self.transport
+    }
+
+    pub fn stats(&self) -> &NetworkStats {
+        &self.stats
+    }
+
+    /// Address that listens on any interface with an OS-assigned port for the current transport.
+    pub fn any_address(&self) -> NetworkAddress {
+        match self.transport {
+            NetworkTransport::Development => "/ip4/0.0.0.0/tcp/0".parse().unwrap(),
+            NetworkTransport::Memory => "/memory/0".parse().unwrap(),
+        }
+    }
+
     pub fn listen_on(
         &mut self,
         addr: NetworkAddress,
@@ -125,44 +485,24 @@ impl NetworkManager {
     }
 
     pub fn publish(&mut self, topic: NetworkTopic, data: impl Into<Vec<u8>>) {
+        let data = data.into();
+
+        self.stats.record_outgoing(topic.hash(), data.len());
         self.command_tx
-            .unbounded_send(NetworkCommand::Publish(topic, data.into()))
+            .unbounded_send(NetworkCommand::Publish(topic, data))
             .unwrap();
     }
 }
 
 impl FromWorld for NetworkManager {
     fn from_world(world: &mut World) -> Self {
-        let local_key = Keypair::generate_ed25519();
-        let local_peer_id = PeerId::from_public_key(local_key.public());
-        let (command_tx, mut command_rx) = async_mpsc::unbounded::<NetworkCommand>();
-        let (event_tx, event_rx) = sync_mpsc::channel::<NetworkEvent>();
-        let event_rx = Mutex::new(event_rx);
-
+        let transport = world
+            .get_resource::<NetworkTransport>()
+            .copied()
+            .unwrap_or_default();
         let io_task_pool = world.get_resource::<IoTaskPool>().unwrap();
-        io_task_pool
-            .spawn(async move {
-                let mut swarm = create_network_swarm(local_key, local_peer_id);
-
-                loop {
-                    select! {
-                        command = command_rx.select_next_some() => handle_network_command(&mut swarm, command),
-                        event = swarm.next_event().fuse() => {
-                            // handle_network_event(&mut swarm, &event);
-                            event_tx.send(event).unwrap();
-                        }
-                    }
-                }
-            })
-            .detach();
-
-        block_on(poll_fn(|context| command_tx.poll_ready(context))).unwrap();
 
-        Self {
-            local_peer_id,
-            command_tx,
-            event_rx,
-        }
+        Self::new(io_task_pool, transport)
     }
 }
 
@@ -173,6 +513,13 @@ pub type NetworkEvent =
         <<<NetworkBehaviour as libp2p::swarm::NetworkBehaviour>::ProtocolsHandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::Error
     >;
 pub type NetworkTopic = IdentTopic;
+type GossipsubHandler =
+    <<Gossipsub as libp2p::swarm::NetworkBehaviour>::ProtocolsHandler as IntoProtocolsHandler>::Handler;
+type GossipsubHandlerEvent = <GossipsubHandler as ProtocolsHandler>::OutEvent;
+type GossipsubAction = libp2p::swarm::NetworkBehaviourAction<
+    <GossipsubHandler as ProtocolsHandler>::InEvent,
+    GossipsubEvent,
+>;
 type NetworkBehaviourAction = libp2p::swarm::NetworkBehaviourAction<
     <<<NetworkBehaviour as libp2p::swarm::NetworkBehaviour>::ProtocolsHandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InEvent,
     <NetworkBehaviour as libp2p::swarm::NetworkBehaviour>::OutEvent
@@ -191,19 +538,44 @@ enum NetworkCommand {
     Publish(NetworkTopic, Vec<u8>),
 }
 
-fn create_network_swarm(local_key: Keypair, local_peer_id: PeerId) -> Swarm<NetworkBehaviour> {
-    let transport = block_on(development_transport(local_key.clone())).unwrap();
+fn create_network_swarm(
+    local_key: Keypair,
+    local_peer_id: PeerId,
+    transport: NetworkTransport,
+    stats: Arc<Mutex<NetworkStats>>,
+) -> Swarm<NetworkBehaviour> {
+    let transport = match transport {
+        NetworkTransport::Development => {
+            block_on(development_transport(local_key.clone())).unwrap()
+        }
+        NetworkTransport::Memory => memory_transport(&local_key),
+    };
     let gossipsub_config = GossipsubConfigBuilder::default().build().unwrap();
-    let gossipsub: Gossipsub =
-        Gossipsub::new(MessageAuthenticity::Signed(local_key), gossipsub_config).unwrap();
+    let gossipsub: Gossipsub = Gossipsub::new(
+        MessageAuthenticity::Signed(local_key),
+        gossipsub_config.clone(),
+    )
+    .unwrap();
     let behaviour = NetworkBehaviour {
-        gossipsub,
+        gossipsub: CountingGossipsub::new(gossipsub, gossipsub_config, stats),
         events: VecDeque::new(),
     };
 
     Swarm::new(transport, behaviour, local_peer_id)
 }
 
+fn memory_transport(local_key: &Keypair) -> Boxed<(PeerId, StreamMuxerBox)> {
+    let noise_keys = noise::Keypair::<noise::X25519Spec>::new()
+        .into_authentic(local_key)
+        .unwrap();
+
+    MemoryTransport::default()
+        .upgrade(upgrade::Version::V1)
+        .authenticate(noise::NoiseConfig::xx(noise_keys).into_authenticated())
+        .multiplex(mplex::MplexConfig::default())
+        .boxed()
+}
+
 fn handle_network_command(swarm: &mut Swarm<NetworkBehaviour>, command: NetworkCommand) {
     match command {
         NetworkCommand::ListenOn(addr, sender) => sender.send(swarm.listen_on(addr)).unwrap(),
@@ -233,9 +605,31 @@ fn handle_network_command(swarm: &mut Swarm<NetworkBehaviour>, command: NetworkC
     };
 }
 
-fn update_network(manager: Res<NetworkManager>, mut event_writer: EventWriter<NetworkEvent>) {
+fn update_network(
+    mut manager: ResMut<NetworkManager>,
+    mut event_writer: EventWriter<NetworkEvent>,
+) {
+    let manager = &mut *manager;
+    let swarm_stats = std::mem::take(&mut *manager.swarm_stats.lock().unwrap());
+
+    manager.stats.merge(swarm_stats);
+
     for event in manager.event_rx.lock().unwrap().try_iter() {
         info!("{:?}", event);
+
+        if let NetworkEvent::Behaviour(NetworkBehaviourEvent::Gossipsub(
+            GossipsubEvent::Message {
+                propagation_source,
+                message,
+                ..
+            },
+        )) = &event
+        {
+            manager
+                .stats
+                .record_incoming(&message.topic, *propagation_source, message.data.len());
+        }
+
         event_writer.send(event);
     }
 }
@@ -0,0 +1,209 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId, Diagnostics},
+    prelude::*,
+};
+use bevy_egui::{egui, EguiContext};
+use libp2p::gossipsub::TopicHash;
+
+use crate::{
+    app_state::AppState,
+    network::{NetworkCounters, NetworkManager},
+};
+
+/// Adds gossipsub traffic diagnostics: total bytes and messages per second in both directions,
+/// duplicate messages per second and bytes per second for every topic seen so far.
+pub struct NetworkDiagnosticsPlugin;
+
+impl Plugin for NetworkDiagnosticsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(NetworkDiagnosticsState::default())
+            .add_startup_system(Self::setup_system.system())
+            .add_system_to_stage(CoreStage::PreUpdate, Self::diagnostic_system.system())
+            .add_system_set(
+                SystemSet::on_update(AppState::InLobby)
+                    .with_system(network_diagnostics_window.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::InGame)
+                    .with_system(network_diagnostics_window.system()),
+            );
+    }
+}
+
+#[derive(Default)]
+pub struct NetworkDiagnosticsState {
+    total: NetworkCounters,
+    topics: HashMap<TopicHash, (NetworkCounters, DiagnosticId, DiagnosticId)>,
+}
+
+impl NetworkDiagnosticsPlugin {
+    pub const BYTES_IN: DiagnosticId =
+        DiagnosticId::from_u128(200553296585215049337095826484385838651);
+    pub const BYTES_OUT: DiagnosticId =
+        DiagnosticId::from_u128(24939126733213393744367834052406815523);
+    pub const MESSAGES_IN: DiagnosticId =
+        DiagnosticId::from_u128(137289358735209561306624812591474186069);
+    pub const MESSAGES_OUT: DiagnosticId =
+        DiagnosticId::from_u128(320146931598604123934389137590961287145);
+    pub const DUPLICATES: DiagnosticId =
+        DiagnosticId::from_u128(97155617207736093390233618446950211433);
+
+    const TOPIC_BYTES_IN: u128 = 0x6b1e_4ad0_1c3e_4f7d_0000_0000_0000_0000;
+    const TOPIC_BYTES_OUT: u128 = 0x2f90_77c5_a8d4_4b61_0000_0000_0000_0000;
+
+    pub fn setup_system(mut diagnostics: ResMut<Diagnostics>) {
+        diagnostics.add(Diagnostic::new(Self::BYTES_IN, "net_bytes_in", 20).with_suffix("B/s"));
+        diagnostics.add(Diagnostic::new(Self::BYTES_OUT, "net_bytes_out", 20).with_suffix("B/s"));
+        diagnostics
+            .add(Diagnostic::new(Self::MESSAGES_IN, "net_messages_in", 20).with_suffix("/s"));
+        diagnostics
+            .add(Diagnostic::new(Self::MESSAGES_OUT, "net_messages_out", 20).with_suffix("/s"));
+        diagnostics.add(Diagnostic::new(Self::DUPLICATES, "net_duplicates", 20).with_suffix("/s"));
+    }
+
+    pub fn diagnostic_system(
+        mut diagnostics: ResMut<Diagnostics>,
+        time: Res<Time>,
+        network_manager: Res<NetworkManager>,
+        mut state: ResMut<NetworkDiagnosticsState>,
+    ) {
+        let delta_seconds = time.delta_seconds_f64();
+
+        if delta_seconds == 0.0 {
+            return;
+        }
+
+        let stats = network_manager.stats();
+        let total = stats.total();
+        let rate = |current: u64, previous: u64| (current - previous) as f64 / delta_seconds;
+
+        diagnostics.add_measurement(Self::BYTES_IN, rate(total.bytes_in, state.total.bytes_in));
+        diagnostics.add_measurement(
+            Self::BYTES_OUT,
+            rate(total.bytes_out, state.total.bytes_out),
+        );
+        diagnostics.add_measurement(
+            Self::MESSAGES_IN,
+            rate(total.messages_in, state.total.messages_in),
+        );
+        diagnostics.add_measurement(
+            Self::MESSAGES_OUT,
+            rate(total.messages_out, state.total.messages_out),
+        );
+        diagnostics.add_measurement(
+            Self::DUPLICATES,
+            rate(total.duplicates, state.total.duplicates),
+        );
+        state.total = total;
+
+        for (topic, &counters) in stats.topics.iter() {
+            let (previous, bytes_in_id, bytes_out_id) =
+                state.topics.entry(topic.clone()).or_insert_with(|| {
+                    let mut hasher = DefaultHasher::new();
+                    topic.hash(&mut hasher);
+                    let hash = hasher.finish() as u128;
+
+                    let bytes_in_id = DiagnosticId::from_u128(Self::TOPIC_BYTES_IN | hash);
+                    let bytes_out_id = DiagnosticId::from_u128(Self::TOPIC_BYTES_OUT | hash);
+
+                    diagnostics.add(
+                        Diagnostic::new(bytes_in_id, format!("net_in/{}", topic.as_str()), 20)
+                            .with_suffix("B/s"),
+                    );
+                    diagnostics.add(
+                        Diagnostic::new(bytes_out_id, format!("net_out/{}", topic.as_str()), 20)
+                            .with_suffix("B/s"),
+                    );
+
+                    (NetworkCounters::default(), bytes_in_id, bytes_out_id)
+                });
+
+            diagnostics.add_measurement(*bytes_in_id, rate(counters.bytes_in, previous.bytes_in));
+            diagnostics
+                .add_measurement(*bytes_out_id, rate(counters.bytes_out, previous.bytes_out));
+            *previous = counters;
+        }
+    }
+}
+
+fn network_diagnostics_window(
+    egui_context: Res<EguiContext>,
+    diagnostics: Res<Diagnostics>,
+    network_manager: Res<NetworkManager>,
+) {
+    let stats = network_manager.stats();
+    let average = |id| {
+        diagnostics
+            .get(id)
+            .and_then(|diagnostic| diagnostic.average())
+            .unwrap_or(0.0)
+    };
+
+    egui::Window::new("Network")
+        .default_open(false)
+        .show(egui_context.ctx(), |ui| {
+            ui.label(format!(
+                "In: {:.0} B/s, {:.1} msg/s",
+                average(NetworkDiagnosticsPlugin::BYTES_IN),
+                average(NetworkDiagnosticsPlugin::MESSAGES_IN)
+            ));
+            ui.label(format!(
+                "Out: {:.0} B/s, {:.1} msg/s",
+                average(NetworkDiagnosticsPlugin::BYTES_OUT),
+                average(NetworkDiagnosticsPlugin::MESSAGES_OUT)
+            ));
+            ui.label(format!(
+                "Duplicates: {:.1} msg/s",
+                average(NetworkDiagnosticsPlugin::DUPLICATES)
+            ));
+
+            ui.separator();
+            ui.heading("Topics");
+
+            egui::Grid::new("network_topics").show(ui, |ui| {
+                ui.label("Topic");
+                ui.label("Msg in/out");
+                ui.label("Bytes in/out");
+                ui.label("Dup");
+                ui.end_row();
+
+                for (topic, counters) in stats.topics.iter() {
+                    ui.label(topic.as_str());
+                    ui.label(format!(
+                        "{}/{}",
+                        counters.messages_in, counters.messages_out
+                    ));
+                    ui.label(format!("{}/{}", counters.bytes_in, counters.bytes_out));
+                    ui.label(format!("{}", counters.duplicates));
+                    ui.end_row();
+                }
+            });
+
+            ui.separator();
+            ui.heading("Peers");
+
+            egui::Grid::new("network_peers").show(ui, |ui| {
+                ui.label("Peer");
+                ui.label("Msg in/out");
+                ui.label("Bytes in/out");
+                ui.label("Dup");
+                ui.end_row();
+
+                for (peer_id, counters) in stats.peers.iter() {
+                    ui.label(peer_id.to_base58());
+                    ui.label(format!(
+                        "{}/{}",
+                        counters.messages_in, counters.messages_out
+                    ));
+                    ui.label(format!("{}/{}", counters.bytes_in, counters.bytes_out));
+                    ui.label(format!("{}", counters.duplicates));
+                    ui.end_row();
+                }
+            });
+        });
+}
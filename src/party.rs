@@ -1,8 +1,9 @@
 use std::collections::HashMap;
 
+use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
 
-use crate::player::{Player, PlayerId};
+use crate::player::{Player, PlayerId, PlayerRole};
 
 #[derive(Serialize, Deserialize)]
 pub struct Party {
@@ -18,4 +19,24 @@ impl Party {
 
         Self { players, host_id }
     }
+
+    pub fn is_spectator(&self, player_id: PlayerId) -> bool {
+        self.players
+            .get(&player_id)
+            .map_or(false, |player| player.role == PlayerRole::Spectator)
+    }
+
+    /// Players that get a character, in the same order on every peer.
+    pub fn character_players(&self) -> Vec<PlayerId> {
+        let mut player_ids: Vec<_> = self
+            .players
+            .values()
+            .filter(|player| player.role != PlayerRole::Spectator)
+            .map(|player| player.id)
+            .collect();
+
+        player_ids.sort_by_cached_key(|&player_id| Into::<PeerId>::into(player_id).to_base58());
+
+        player_ids
+    }
 }
@@ -14,6 +14,8 @@ use bevy_rapier3d::rapier::pipeline::PhysicsPipeline;
 use bevy_rapier3d::rapier::pipeline::QueryPipeline;
 use std::marker::PhantomData;
 
+use crate::tick::tick_due;
+
 /// A plugin responsible for setting up a full Rapier physics simulation pipeline and resources.
 ///
 /// This will automatically setup all the resources needed to run a Rapier physics simulation including:
@@ -112,6 +114,7 @@ impl<UserData: 'static + WorldQuery + Send + Sync> Plugin for PhysicsPlugin<User
             PhysicsStages::Update,
             physics::step_world_system::<UserData>
                 .system()
+                .with_run_criteria(tick_due.system())
                 .label(PhysicsSystems::StepWorld),
         )
         .add_system_to_stage(
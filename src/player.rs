@@ -7,9 +7,24 @@ use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 pub struct Player {
     pub id: PlayerId,
     pub name: String,
+    #[serde(default)]
+    pub role: PlayerRole,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+/// Whether a player controls a character or only watches the simulation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlayerRole {
+    Player,
+    Spectator,
+}
+
+impl Default for PlayerRole {
+    fn default() -> Self {
+        Self::Player
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct PlayerId(PeerId);
 
 impl PlayerId {
@@ -0,0 +1,459 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bevy::prelude::*;
+use libp2p::gossipsub::GossipsubEvent;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    cleanup::CleanupConfig,
+    damage::PlayerCharacter,
+    latency::PeerLatencies,
+    network::{NetworkBehaviourEvent, NetworkEvent, NetworkManager, NetworkTopic},
+    party::Party,
+    player::PlayerId,
+    resources::{DelayedInputs, MainCharacterInput, Tick},
+    tick::{on_tick, TickClock},
+    weapons::WeaponInput,
+    Health, MainCharacter, MainCharacterMovement, MAIN_CHARACTER_RUN_SPEED,
+};
+
+pub struct PlayerInputPlugin;
+
+impl Plugin for PlayerInputPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(RemotePlayerInputs::default())
+            .insert_resource(InputViolations::default())
+            .insert_resource(PendingKicks::default())
+            .add_system_to_stage(
+                CoreStage::First,
+                wait_for_player_inputs.system().before("tick_clock"),
+            )
+            .add_system_set(
+                SystemSet::on_enter(AppState::InGame)
+                    .with_system(subscribe_player_input_topics.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::InGame)
+                    .with_system(publish_local_input.system().after("sample_input"))
+                    .with_system(
+                        receive_player_inputs
+                            .system()
+                            .label("receive_player_inputs"),
+                    )
+                    .with_system(
+                        kick_offenders
+                            .system()
+                            .label("kick_offenders")
+                            .after("receive_player_inputs"),
+                    )
+                    .with_system(
+                        handle_kicks
+                            .system()
+                            .label("handle_kicks")
+                            .after("kick_offenders"),
+                    ),
+            )
+            .add_system_set(
+                on_tick(AppState::InGame)
+                    .with_system(
+                        apply_remote_inputs
+                            .system()
+                            .label("character_input")
+                            .after("receive_player_inputs"),
+                    )
+                    .with_system(apply_kicks.system().after("handle_kicks")),
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::InGame)
+                    .with_system(unsubscribe_player_input_topics.system())
+                    .with_system(clear_remote_inputs.system()),
+            );
+    }
+}
+
+/// Input of one player, applied on `tick`, as sent over the network.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PlayerInput {
+    pub player_id: PlayerId,
+    pub tick: usize,
+    pub movement: Vec2,
+    /// Facing in radians around the up axis.
+    pub facing: f32,
+    pub weapon_input: WeaponInput,
+}
+
+/// Inputs received from other peers that passed validation and are not applied yet, by player
+/// and tick.
+#[derive(Default)]
+pub struct RemotePlayerInputs(pub HashMap<PlayerId, BTreeMap<usize, PlayerInput>>);
+
+impl RemotePlayerInputs {
+    pub fn contains(&self, player_id: PlayerId, tick: usize) -> bool {
+        self.0
+            .get(&player_id)
+            .map_or(false, |inputs| inputs.contains_key(&tick))
+    }
+}
+
+#[derive(Default)]
+struct InputViolations(HashMap<PlayerId, u32>);
+
+impl InputViolations {
+    const KICK_THRESHOLD: u32 = 3;
+}
+
+/// Removes a player from the party on `tick`, on every peer.
+///
+/// `tick` is the first tick the host has no input from the player for. The player's inputs before
+/// it are applied everywhere, and no peer waits for the ones after it, so a player who stopped
+/// sending valid inputs does not stall the simulation.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Kick {
+    player_id: PlayerId,
+    tick: usize,
+}
+
+/// Kicks announced by the host that have not taken effect yet, as the tick they take effect on by
+/// player.
+#[derive(Default)]
+struct PendingKicks(HashMap<PlayerId, usize>);
+
+impl PendingKicks {
+    /// Whether the player is kicked by the given tick.
+    fn is_kicked(&self, player_id: PlayerId, tick: usize) -> bool {
+        self.0
+            .get(&player_id)
+            .map_or(false, |&kick_tick| kick_tick <= tick)
+    }
+}
+
+#[derive(Debug)]
+enum InputViolation {
+    UnknownSource,
+    ImpersonatedPlayer(PlayerId),
+    Spectator,
+    InvalidMovement(Vec2),
+    InvalidFacing(f32),
+    Malformed,
+    /// The tick was simulated already, which the peer knows as it waits for the local inputs.
+    Late(usize),
+    TooEarly(usize),
+    Repeated(usize),
+}
+
+/// Ticks an input may be ahead of the local tick. Peers cannot simulate more than the input delay
+/// ahead, as they wait for the local inputs, and sample theirs up to the input delay ahead.
+const MAX_INPUT_TICKS_AHEAD: usize = 2 * PeerLatencies::MAX_INPUT_DELAY as usize + 1;
+
+fn subscribe_player_input_topics(mut network_manager: ResMut<NetworkManager>) {
+    network_manager.subscribe(NetworkTopic::new("input"));
+    network_manager.subscribe(NetworkTopic::new("kicked"));
+}
+
+fn unsubscribe_player_input_topics(mut network_manager: ResMut<NetworkManager>) {
+    network_manager.unsubscribe(NetworkTopic::new("input"));
+    network_manager.unsubscribe(NetworkTopic::new("kicked"));
+}
+
+fn clear_remote_inputs(
+    mut remote_inputs: ResMut<RemotePlayerInputs>,
+    mut pending_kicks: ResMut<PendingKicks>,
+) {
+    remote_inputs.0.clear();
+    pending_kicks.0.clear();
+}
+
+/// Sends the inputs of the local player as soon as they are sampled, ahead of the tick they are
+/// applied on.
+fn publish_local_input(
+    mut network_manager: ResMut<NetworkManager>,
+    mut input_events: EventReader<MainCharacterInput>,
+) {
+    for sampled in input_events.iter() {
+        let input = PlayerInput {
+            player_id: network_manager.local_peer_id().into(),
+            tick: sampled.tick.0,
+            movement: sampled.movement,
+            facing: sampled.facing,
+            weapon_input: sampled.weapon_input,
+        };
+
+        let data = serde_json::to_vec(&input).unwrap();
+        network_manager.publish(NetworkTopic::new("input"), data);
+    }
+}
+
+fn validate_player_input(
+    party: &Party,
+    tick: usize,
+    remote_inputs: &RemotePlayerInputs,
+    source: Option<PlayerId>,
+    data: &[u8],
+) -> Result<PlayerInput, InputViolation> {
+    let source = match source {
+        Some(source) if party.players.contains_key(&source) => source,
+        _ => return Err(InputViolation::UnknownSource),
+    };
+
+    let input =
+        serde_json::from_slice::<'_, PlayerInput>(data).map_err(|_| InputViolation::Malformed)?;
+
+    if input.player_id != source {
+        return Err(InputViolation::ImpersonatedPlayer(input.player_id));
+    }
+
+    if party.is_spectator(source) {
+        return Err(InputViolation::Spectator);
+    }
+
+    // Movement is the speed times the direction, allow for rounding at the run speed
+    if !(input.movement.x.is_finite() && input.movement.y.is_finite())
+        || input.movement.length() > MAIN_CHARACTER_RUN_SPEED + 1e-3
+    {
+        return Err(InputViolation::InvalidMovement(input.movement));
+    }
+
+    if !input.facing.is_finite() {
+        return Err(InputViolation::InvalidFacing(input.facing));
+    }
+
+    if input.tick < tick {
+        return Err(InputViolation::Late(input.tick));
+    }
+
+    if input.tick > tick + MAX_INPUT_TICKS_AHEAD {
+        return Err(InputViolation::TooEarly(input.tick));
+    }
+
+    if remote_inputs.contains(source, input.tick) {
+        return Err(InputViolation::Repeated(input.tick));
+    }
+
+    Ok(input)
+}
+
+fn receive_player_inputs(
+    tick: Res<Tick>,
+    party: Res<Party>,
+    pending_kicks: Res<PendingKicks>,
+    mut remote_inputs: ResMut<RemotePlayerInputs>,
+    mut violations: ResMut<InputViolations>,
+    mut network_events: EventReader<NetworkEvent>,
+) {
+    for event in network_events.iter() {
+        if let NetworkEvent::Behaviour(NetworkBehaviourEvent::Gossipsub(
+            GossipsubEvent::Message { message, .. },
+        )) = event
+        {
+            if NetworkTopic::new("input").hash() != message.topic {
+                continue;
+            }
+
+            let source = message.source.map(PlayerId::from);
+
+            match validate_player_input(&party, tick.0, &remote_inputs, source, &message.data) {
+                // Kicked players stop counting on the kick tick, whatever they send after it
+                Ok(input) if pending_kicks.is_kicked(input.player_id, input.tick) => {}
+                Ok(input) => {
+                    remote_inputs
+                        .0
+                        .entry(input.player_id)
+                        .or_default()
+                        .insert(input.tick, input);
+                }
+                Err(violation) => {
+                    warn!("Rejected input from {:?}: {:?}", message.source, violation);
+
+                    if let Some(source) = source.filter(|source| party.players.contains_key(source))
+                    {
+                        *violations.0.entry(source).or_default() += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Holds the next tick back until every living character has an input for it, so that every peer
+/// simulates the tick with the same inputs. Players whose character died stop sending inputs.
+fn wait_for_player_inputs(
+    app_state: Res<State<AppState>>,
+    tick: Res<Tick>,
+    delayed_inputs: Res<DelayedInputs>,
+    remote_inputs: Res<RemotePlayerInputs>,
+    pending_kicks: Res<PendingKicks>,
+    mut tick_clock: ResMut<TickClock>,
+    character_query: Query<(&PlayerCharacter, &Health, Option<&MainCharacter>)>,
+) {
+    if *app_state.current() != AppState::InGame {
+        return;
+    }
+
+    let missing = character_query
+        .iter()
+        .filter(|(character, health, _)| {
+            health.0 > 0.0 && !pending_kicks.is_kicked(character.0, tick.0)
+        })
+        .any(|(character, _, main_character)| {
+            if main_character.is_some() {
+                !delayed_inputs.0.iter().any(|input| input.tick.0 == tick.0)
+            } else {
+                !remote_inputs.contains(character.0, tick.0)
+            }
+        });
+
+    if missing {
+        tick_clock.waiting = true;
+    }
+}
+
+/// Applies the inputs of remote players for this tick to their characters, and drops them.
+fn apply_remote_inputs(
+    tick: Res<Tick>,
+    mut remote_inputs: ResMut<RemotePlayerInputs>,
+    mut character_query: Query<
+        (
+            &PlayerCharacter,
+            &mut MainCharacterMovement,
+            &mut WeaponInput,
+        ),
+        Without<MainCharacter>,
+    >,
+) {
+    for (character, mut movement, mut weapon_input) in character_query.iter_mut() {
+        let input = match remote_inputs
+            .0
+            .get_mut(&character.0)
+            .and_then(|inputs| inputs.remove(&tick.0))
+        {
+            Some(input) => input,
+            None => continue,
+        };
+
+        if movement.want_to_move != input.movement || movement.want_to_face != input.facing {
+            movement.want_to_move = input.movement;
+            movement.want_to_face = input.facing;
+        }
+
+        if *weapon_input != input.weapon_input {
+            *weapon_input = input.weapon_input;
+        }
+    }
+}
+
+/// The host kicks players that keep sending invalid input, telling everyone the tick the kick takes
+/// effect on.
+fn kick_offenders(
+    tick: Res<Tick>,
+    party: Res<Party>,
+    remote_inputs: Res<RemotePlayerInputs>,
+    mut violations: ResMut<InputViolations>,
+    mut pending_kicks: ResMut<PendingKicks>,
+    mut network_manager: ResMut<NetworkManager>,
+) {
+    if party.host_id != network_manager.local_peer_id().into() || !violations.is_changed() {
+        return;
+    }
+
+    let offenders: Vec<_> = violations
+        .0
+        .iter()
+        .filter(|(_, &count)| count >= InputViolations::KICK_THRESHOLD)
+        .map(|(&player_id, _)| player_id)
+        .collect();
+
+    for player_id in offenders {
+        violations.0.remove(&player_id);
+
+        if player_id == party.host_id || pending_kicks.0.contains_key(&player_id) {
+            continue;
+        }
+
+        if let Some(player) = party.players.get(&player_id) {
+            let mut kick_tick = tick.0;
+
+            while remote_inputs.contains(player_id, kick_tick) {
+                kick_tick += 1;
+            }
+
+            warn!(
+                "Kicking {} on tick {} for sending invalid input",
+                player.name, kick_tick
+            );
+
+            let kick = Kick {
+                player_id,
+                tick: kick_tick,
+            };
+            let data = serde_json::to_vec(&kick).unwrap();
+            network_manager.publish(NetworkTopic::new("kicked"), data);
+            pending_kicks.0.insert(player_id, kick_tick);
+        }
+    }
+}
+
+fn handle_kicks(
+    mut cleanup_config: ResMut<CleanupConfig>,
+    mut app_state: ResMut<State<AppState>>,
+    party: Res<Party>,
+    mut pending_kicks: ResMut<PendingKicks>,
+    network_manager: Res<NetworkManager>,
+    mut network_events: EventReader<NetworkEvent>,
+) {
+    for event in network_events.iter() {
+        if let NetworkEvent::Behaviour(NetworkBehaviourEvent::Gossipsub(
+            GossipsubEvent::Message { message, .. },
+        )) = event
+        {
+            if NetworkTopic::new("kicked").hash() != message.topic
+                || message.source != Some(party.host_id.into())
+            {
+                continue;
+            }
+
+            match serde_json::from_slice::<'_, Kick>(&message.data) {
+                Ok(kick) if kick.player_id == network_manager.local_peer_id().into() => {
+                    error!("Kicked from the party by the host");
+                    cleanup_config.next_state_after_cleanup = Some(AppState::MainMenu);
+                    app_state.set(AppState::Cleanup).unwrap();
+                }
+                Ok(kick) => {
+                    pending_kicks.0.insert(kick.player_id, kick.tick);
+                }
+                Err(error) => {
+                    error!("Cannot parse Kick from kicked message: {:?}", error);
+                }
+            };
+        }
+    }
+}
+
+/// Removes kicked players from the party and despawns their character on the kick tick.
+fn apply_kicks(
+    mut commands: Commands,
+    tick: Res<Tick>,
+    mut party: ResMut<Party>,
+    mut pending_kicks: ResMut<PendingKicks>,
+    mut remote_inputs: ResMut<RemotePlayerInputs>,
+    character_query: Query<(Entity, &PlayerCharacter)>,
+) {
+    let kicked: Vec<_> = pending_kicks
+        .0
+        .keys()
+        .copied()
+        .filter(|&player_id| pending_kicks.is_kicked(player_id, tick.0))
+        .collect();
+
+    for player_id in kicked {
+        pending_kicks.0.remove(&player_id);
+        remote_inputs.0.remove(&player_id);
+        party.players.remove(&player_id);
+
+        for (entity, character) in character_query.iter() {
+            if character.0 == player_id {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+    }
+}
@@ -0,0 +1,377 @@
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId, Diagnostics},
+    prelude::*,
+};
+use bevy_rapier3d::prelude::*;
+
+use crate::{app_state::AppState, weapons::Projectile};
+
+/// Keeps short-lived physics entities, such as projectiles and blood splatters, around once they
+/// are done so that they can be reused instead of spawning new ones, along with their Rapier
+/// handles.
+///
+/// Pooled entities go back to their pool when their `DespawnAfter` runs out, where they are
+/// parked: moved out of the way, put to sleep and kept from colliding with anything.
+pub struct PoolPlugin;
+
+impl Plugin for PoolPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(PoolSettings::default())
+            .insert_resource(EntityPools::default())
+            .add_startup_system(Self::setup_system.system())
+            .add_system_to_stage(CoreStage::First, park_released_entities.system())
+            .add_system_to_stage(CoreStage::Last, Self::diagnostic_system.system())
+            .add_system_set(
+                SystemSet::on_enter(AppState::Cleanup).with_system(clear_pools.system()),
+            );
+    }
+}
+
+/// Most entities each pool may hold. Entities needed beyond that are spawned and despawned as
+/// usual, and counted as overflow.
+pub struct PoolSettings {
+    pub projectiles: usize,
+    pub blood_splatters: usize,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        Self {
+            projectiles: 256,
+            blood_splatters: 1024,
+        }
+    }
+}
+
+impl PoolSettings {
+    pub fn capacity(&self, kind: PoolKind) -> usize {
+        match kind {
+            PoolKind::Projectile => self.projectiles,
+            PoolKind::BloodSplatter => self.blood_splatters,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolKind {
+    Projectile,
+    BloodSplatter,
+}
+
+/// Marks an entity as belonging to a pool.
+pub struct Pooled {
+    pub kind: PoolKind,
+    parked: bool,
+}
+
+impl Pooled {
+    pub fn new(kind: PoolKind) -> Self {
+        Self {
+            kind,
+            parked: false,
+        }
+    }
+}
+
+/// Added to pooled entities in place of despawning them.
+pub struct Released;
+
+/// Counters of a pool since the game started.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PoolMetrics {
+    /// Entities spawned into the pool.
+    pub spawned: u64,
+    /// Times an entity was taken out of the pool instead of spawning one.
+    pub reused: u64,
+    /// Entities spawned outside of the pool because it was full.
+    pub overflowed: u64,
+    /// Most entities of the pool in use at once.
+    pub peak_active: usize,
+}
+
+/// Where an entity taken from a pool comes from.
+pub enum PoolSlot {
+    /// A parked entity to reuse. Its state should be reset with a `PooledBodyBundle`.
+    Reused(Entity),
+    /// A new entity should be spawned with a `Pooled` component.
+    New,
+    /// The pool is full. A new entity should be spawned without a `Pooled` component.
+    Overflow,
+}
+
+#[derive(Default)]
+pub struct EntityPool {
+    /// Parked entities ready to be reused.
+    free: Vec<Entity>,
+    /// Entities parked this frame. They are only reused from the next frame on, since events
+    /// from the last physics step may still refer to their previous use.
+    parked: Vec<Entity>,
+    /// Entities spawned into the pool, whether in use or not.
+    size: usize,
+    pub metrics: PoolMetrics,
+    last_metrics: PoolMetrics,
+}
+
+impl EntityPool {
+    /// Takes an entity out of the pool, preferring a parked entity for which `prefer` is true.
+    pub fn acquire(&mut self, capacity: usize, prefer: impl Fn(Entity) -> bool) -> PoolSlot {
+        let slot = if !self.free.is_empty() {
+            let index = self
+                .free
+                .iter()
+                .rposition(|entity| prefer(*entity))
+                .unwrap_or(self.free.len() - 1);
+
+            self.metrics.reused += 1;
+            PoolSlot::Reused(self.free.swap_remove(index))
+        } else if self.size < capacity {
+            self.size += 1;
+            self.metrics.spawned += 1;
+            PoolSlot::New
+        } else {
+            self.metrics.overflowed += 1;
+            PoolSlot::Overflow
+        };
+
+        self.metrics.peak_active = self.metrics.peak_active.max(self.active());
+
+        slot
+    }
+
+    /// Entities of the pool currently in use.
+    pub fn active(&self) -> usize {
+        self.size - self.free.len() - self.parked.len()
+    }
+
+    /// Entities of the pool that are parked.
+    pub fn available(&self) -> usize {
+        self.free.len() + self.parked.len()
+    }
+
+    /// Makes the entities parked last frame available for reuse.
+    fn unpark(&mut self) {
+        let parked = std::mem::take(&mut self.parked);
+        self.free.extend(parked);
+    }
+
+    fn clear(&mut self) {
+        self.free.clear();
+        self.parked.clear();
+        self.size = 0;
+    }
+}
+
+#[derive(Default)]
+pub struct EntityPools {
+    pub projectiles: EntityPool,
+    pub blood_splatters: EntityPool,
+}
+
+impl EntityPools {
+    pub fn get_mut(&mut self, kind: PoolKind) -> &mut EntityPool {
+        match kind {
+            PoolKind::Projectile => &mut self.projectiles,
+            PoolKind::BloodSplatter => &mut self.blood_splatters,
+        }
+    }
+}
+
+/// Physics state that differs between uses of a pooled entity. The rest of its rigid body and
+/// collider, including its Rapier handles, is kept as it is.
+#[derive(Bundle)]
+pub struct PooledBodyBundle {
+    pub pooled: Pooled,
+    pub position: RigidBodyPosition,
+    pub velocity: RigidBodyVelocity,
+    pub activation: RigidBodyActivation,
+    pub ccd: RigidBodyCcd,
+    pub collider_flags: ColliderFlags,
+}
+
+impl PooledBodyBundle {
+    /// State to reuse a pooled entity with, taken from the bundles a new one would be spawned with.
+    pub fn new(kind: PoolKind, rigid_body: RigidBodyBundle, collider: ColliderBundle) -> Self {
+        let position = rigid_body.position.position;
+
+        Self {
+            pooled: Pooled::new(kind),
+            position: RigidBodyPosition {
+                position,
+                next_position: position,
+            },
+            velocity: rigid_body.velocity,
+            activation: RigidBodyActivation::active(),
+            ccd: rigid_body.ccd,
+            collider_flags: collider.flags,
+        }
+    }
+}
+
+/// Where parked entities are kept, well out of sight.
+const PARKING_HEIGHT: f32 = -1000.0;
+
+fn park_released_entities(
+    mut commands: Commands,
+    mut pools: ResMut<EntityPools>,
+    mut query: Query<
+        (
+            Entity,
+            &mut Pooled,
+            &mut RigidBodyPosition,
+            &mut RigidBodyVelocity,
+            &mut RigidBodyActivation,
+            &mut ColliderFlags,
+            Option<&mut Visible>,
+        ),
+        With<Released>,
+    >,
+) {
+    pools.projectiles.unpark();
+    pools.blood_splatters.unpark();
+
+    for (entity, mut pooled, mut position, mut velocity, mut activation, mut flags, visible) in
+        query.iter_mut()
+    {
+        commands.entity(entity).remove::<Released>();
+
+        // Released again by an event about its previous use
+        if pooled.parked {
+            continue;
+        }
+
+        pooled.parked = true;
+
+        // Hits from the last physics step no longer count, as if the entity had been despawned
+        if let PoolKind::Projectile = pooled.kind {
+            commands.entity(entity).remove::<Projectile>();
+        }
+
+        let parking = Isometry::translation(0.0, PARKING_HEIGHT, 0.0);
+        position.position = parking;
+        position.next_position = parking;
+        *velocity = RigidBodyVelocity::default();
+        *activation = RigidBodyActivation::inactive();
+        flags.collision_groups = InteractionGroups::none();
+        flags.solver_groups = InteractionGroups::none();
+
+        if let Some(mut visible) = visible {
+            visible.is_visible = false;
+        }
+
+        pools.get_mut(pooled.kind).parked.push(entity);
+    }
+}
+
+/// Every entity is despawned during the cleanup, pooled or not.
+fn clear_pools(mut pools: ResMut<EntityPools>) {
+    pools.projectiles.clear();
+    pools.blood_splatters.clear();
+}
+
+impl PoolPlugin {
+    pub const PROJECTILES_ACTIVE: DiagnosticId =
+        DiagnosticId::from_u128(9136020634596050640955532619315280506);
+    pub const PROJECTILES_REUSED: DiagnosticId =
+        DiagnosticId::from_u128(158133109173675028614409326153587060631);
+    pub const PROJECTILES_OVERFLOW: DiagnosticId =
+        DiagnosticId::from_u128(110064573200484444977938508487644620185);
+    pub const BLOOD_SPLATTERS_ACTIVE: DiagnosticId =
+        DiagnosticId::from_u128(252864475861077405605993542137207682127);
+    pub const BLOOD_SPLATTERS_REUSED: DiagnosticId =
+        DiagnosticId::from_u128(183779139114796261980439690418831967977);
+    pub const BLOOD_SPLATTERS_OVERFLOW: DiagnosticId =
+        DiagnosticId::from_u128(232001095142586886782461200201393063065);
+
+    pub fn setup_system(mut diagnostics: ResMut<Diagnostics>) {
+        diagnostics.add(Diagnostic::new(
+            Self::PROJECTILES_ACTIVE,
+            "pool_projectiles_active",
+            20,
+        ));
+        diagnostics.add(
+            Diagnostic::new(Self::PROJECTILES_REUSED, "pool_projectiles_reused", 20)
+                .with_suffix("/s"),
+        );
+        diagnostics.add(
+            Diagnostic::new(Self::PROJECTILES_OVERFLOW, "pool_projectiles_overflow", 20)
+                .with_suffix("/s"),
+        );
+        diagnostics.add(Diagnostic::new(
+            Self::BLOOD_SPLATTERS_ACTIVE,
+            "pool_blood_splatters_active",
+            20,
+        ));
+        diagnostics.add(
+            Diagnostic::new(
+                Self::BLOOD_SPLATTERS_REUSED,
+                "pool_blood_splatters_reused",
+                20,
+            )
+            .with_suffix("/s"),
+        );
+        diagnostics.add(
+            Diagnostic::new(
+                Self::BLOOD_SPLATTERS_OVERFLOW,
+                "pool_blood_splatters_overflow",
+                20,
+            )
+            .with_suffix("/s"),
+        );
+    }
+
+    pub fn diagnostic_system(
+        mut diagnostics: ResMut<Diagnostics>,
+        time: Res<Time>,
+        mut pools: ResMut<EntityPools>,
+    ) {
+        let delta_seconds = time.delta_seconds_f64();
+
+        if delta_seconds == 0.0 {
+            return;
+        }
+
+        Self::record_pool(
+            &mut diagnostics,
+            &mut pools.projectiles,
+            delta_seconds,
+            [
+                Self::PROJECTILES_ACTIVE,
+                Self::PROJECTILES_REUSED,
+                Self::PROJECTILES_OVERFLOW,
+            ],
+        );
+        Self::record_pool(
+            &mut diagnostics,
+            &mut pools.blood_splatters,
+            delta_seconds,
+            [
+                Self::BLOOD_SPLATTERS_ACTIVE,
+                Self::BLOOD_SPLATTERS_REUSED,
+                Self::BLOOD_SPLATTERS_OVERFLOW,
+            ],
+        );
+    }
+
+    /// Records the active entities of a pool, and how many it reused and overflowed per second.
+    fn record_pool(
+        diagnostics: &mut Diagnostics,
+        pool: &mut EntityPool,
+        delta_seconds: f64,
+        [active, reused, overflow]: [DiagnosticId; 3],
+    ) {
+        let metrics = pool.metrics;
+        let last_metrics = pool.last_metrics;
+
+        diagnostics.add_measurement(active, pool.active() as f64);
+        diagnostics.add_measurement(
+            reused,
+            (metrics.reused - last_metrics.reused) as f64 / delta_seconds,
+        );
+        diagnostics.add_measurement(
+            overflow,
+            (metrics.overflowed - last_metrics.overflowed) as f64 / delta_seconds,
+        );
+
+        pool.last_metrics = metrics;
+    }
+}
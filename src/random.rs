@@ -19,6 +19,12 @@ pub struct Random {
     pub generator: Pcg64Mcg,
 }
 
+/// Generator seeded with the tick and a key of the caller's choosing, for systems whose draws must
+/// not depend on which other systems drew from `Random` before them.
+pub fn seeded_generator(tick: usize, key: u64) -> Pcg64Mcg {
+    Pcg64Mcg::seed_from_u64((tick as u64) ^ key.rotate_left(32))
+}
+
 fn reseed_generator(tick: Res<Tick>, mut random: ResMut<Random>) {
     random.generator = Pcg64Mcg::seed_from_u64(tick.0 as u64);
 }
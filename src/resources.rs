@@ -1,3 +1,5 @@
+use std::collections::VecDeque;
+
 use bevy::{
     pbr::AmbientLight,
     prelude::*,
@@ -9,6 +11,9 @@ use bevy::{
         shader::ShaderStages,
     },
 };
+use serde::{Deserialize, Serialize};
+
+use crate::weapons::WeaponInput;
 
 pub struct PbrResources {
     pub checkerboard_material: Handle<CheckerboardMaterial>,
@@ -35,6 +40,10 @@ pub struct InitResourcesPlugin;
 impl Plugin for InitResourcesPlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.insert_resource(Tick(0))
+            .insert_resource(Score(0))
+            .insert_resource(SimulationSettings::default())
+            .insert_resource(DelayedInputs::default())
+            .add_event::<MainCharacterInput>()
             .add_asset::<CheckerboardMaterial>()
             .insert_resource(AmbientLight {
                 color: Color::WHITE,
@@ -54,6 +63,39 @@ impl Plugin for InitResourcesPlugin {
 #[derive(Clone, Copy)]
 pub struct Tick(pub usize);
 
+/// Points earned by the party in the current game.
+#[derive(Default)]
+pub struct Score(pub u32);
+
+/// Simulation parameters every peer must agree on, chosen by the host when the game starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimulationSettings {
+    /// Simulation ticks per second.
+    pub tick_rate: u32,
+    /// Number of ticks between sampling an input and applying it.
+    pub input_delay: u32,
+}
+
+impl SimulationSettings {
+    pub fn tick_duration(&self) -> f32 {
+        1.0 / self.tick_rate as f32
+    }
+
+    /// Number of ticks closest to the given duration.
+    pub fn ticks(&self, seconds: f32) -> usize {
+        (seconds * self.tick_rate as f32).round() as usize
+    }
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        Self {
+            tick_rate: 60,
+            input_delay: 2,
+        }
+    }
+}
+
 pub struct GameReplay {
     pub tick: Tick,
     pub main_character_inputs: Vec<MainCharacterInput>,
@@ -61,11 +103,20 @@ pub struct GameReplay {
     pub main_character_final_position: Vec3,
 }
 
+/// Input of the main character, applied on `tick`. Sent as an event when it is sampled.
+#[derive(Clone, Copy)]
 pub struct MainCharacterInput {
     pub tick: Tick,
     pub movement: Vec2,
+    /// Facing in radians around the up axis.
+    pub facing: f32,
+    pub weapon_input: WeaponInput,
 }
 
+/// Sampled inputs of the main character waiting for the tick they are applied on, in tick order.
+#[derive(Default)]
+pub struct DelayedInputs(pub VecDeque<MainCharacterInput>);
+
 #[derive(RenderResources, Default, TypeUuid)]
 #[uuid = "c16c38f6-53fe-499c-832f-acc879f36454"]
 pub struct CheckerboardMaterial {
@@ -11,11 +11,13 @@ impl Plugin for InitSoundPlugin {
 pub struct SoundResources {
     pub pistol_shoot: Handle<AudioSource>,
     pub pistol_reload: Handle<AudioSource>,
+    pub melee_swing: Handle<AudioSource>,
 }
 
 fn init_sound_resources(mut commands: Commands, asset_server: Res<AssetServer>) {
     commands.insert_resource(SoundResources {
         pistol_shoot: asset_server.load("sounds/pistol_shoot.mp3"),
         pistol_reload: asset_server.load("sounds/pistol_reload.mp3"),
+        melee_swing: asset_server.load("sounds/melee_swing.mp3"),
     });
 }
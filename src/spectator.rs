@@ -0,0 +1,172 @@
+use bevy::prelude::*;
+
+use crate::{
+    app_state::AppState, damage::PlayerCharacter, network::NetworkManager, party::Party, MainCamera,
+};
+
+pub struct SpectatorPlugin;
+
+impl Plugin for SpectatorPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(
+            SystemSet::on_enter(AppState::InGame).with_system(spectator_setup.system()),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::InGame).with_system(
+                spectator_camera_input
+                    .system()
+                    .label("spectator_camera_input"),
+            ),
+        )
+        .add_system_to_stage(CoreStage::PostUpdate, spectator_camera_movement.system());
+    }
+}
+
+/// Camera used instead of a `MainCharacter`-bound camera when the local player is a spectator.
+pub struct SpectatorCamera {
+    pub mode: SpectatorCameraMode,
+    pub follow_offset: Vec3,
+    pub speed: f32,
+}
+
+pub enum SpectatorCameraMode {
+    Free,
+    Follow(Entity),
+}
+
+pub fn local_player_is_spectator(
+    party: Option<Res<Party>>,
+    network_manager: &NetworkManager,
+) -> bool {
+    party.map_or(false, |party| {
+        party.is_spectator(network_manager.local_peer_id().into())
+    })
+}
+
+fn spectator_setup(
+    mut commands: Commands,
+    party: Option<Res<Party>>,
+    network_manager: Res<NetworkManager>,
+) {
+    if !local_player_is_spectator(party, &network_manager) {
+        return;
+    }
+
+    commands
+        .spawn_bundle(PerspectiveCameraBundle {
+            transform: Transform::from_xyz(0.0, 20.0, 20.0).looking_at(Vec3::ZERO, Vec3::Y),
+            ..Default::default()
+        })
+        .insert(MainCamera)
+        .insert(SpectatorCamera {
+            mode: SpectatorCameraMode::Free,
+            follow_offset: Vec3::new(0.0, 20.0, 20.0),
+            speed: 10.0,
+        });
+}
+
+/// WASD pans, Q/E lowers and raises the camera, Tab cycles through the characters of the players
+/// to follow.
+fn spectator_camera_input(
+    time: Res<Time>,
+    input: Res<Input<KeyCode>>,
+    character_query: Query<Entity, With<PlayerCharacter>>,
+    mut camera_query: Query<(&mut SpectatorCamera, &mut Transform)>,
+) {
+    for (mut camera, mut transform) in camera_query.iter_mut() {
+        if input.just_pressed(KeyCode::Tab) {
+            let mut characters: Vec<_> = character_query.iter().collect();
+            characters.sort();
+
+            camera.mode = match camera.mode {
+                SpectatorCameraMode::Free => characters
+                    .first()
+                    .map_or(SpectatorCameraMode::Free, |&entity| {
+                        SpectatorCameraMode::Follow(entity)
+                    }),
+                SpectatorCameraMode::Follow(current) => characters
+                    .iter()
+                    .position(|&entity| entity == current)
+                    .and_then(|index| characters.get(index + 1))
+                    .map_or(SpectatorCameraMode::Free, |&entity| {
+                        SpectatorCameraMode::Follow(entity)
+                    }),
+            };
+        }
+
+        if let SpectatorCameraMode::Follow(_) = camera.mode {
+            continue;
+        }
+
+        let mut direction = Vec3::ZERO;
+
+        if input.pressed(KeyCode::W) {
+            direction.z -= 1.0;
+        }
+
+        if input.pressed(KeyCode::A) {
+            direction.x -= 1.0;
+        }
+
+        if input.pressed(KeyCode::S) {
+            direction.z += 1.0;
+        }
+
+        if input.pressed(KeyCode::D) {
+            direction.x += 1.0;
+        }
+
+        if input.pressed(KeyCode::Q) {
+            direction.y -= 1.0;
+        }
+
+        if input.pressed(KeyCode::E) {
+            direction.y += 1.0;
+        }
+
+        let mut forward = transform.local_z();
+        forward.y = 0.0;
+        let forward = forward.try_normalize().unwrap_or(Vec3::Z);
+        let right = transform.local_x();
+
+        let velocity = direction.x * right + direction.y * Vec3::Y + direction.z * forward;
+
+        transform.translation += camera.speed * time.delta_seconds() * velocity.normalize_or_zero();
+    }
+}
+
+fn spectator_camera_movement(
+    mut query_set: QuerySet<(
+        Query<&Transform, With<PlayerCharacter>>,
+        Query<(&mut SpectatorCamera, &mut Transform)>,
+    )>,
+) {
+    let followed: Vec<_> = query_set
+        .q1_mut()
+        .iter_mut()
+        .map(|(camera, _)| match camera.mode {
+            SpectatorCameraMode::Follow(entity) => Some(entity),
+            SpectatorCameraMode::Free => None,
+        })
+        .collect();
+    let targets: Vec<_> = followed
+        .into_iter()
+        .map(|entity| entity.and_then(|entity| query_set.q0().get(entity).ok().copied()))
+        .collect();
+
+    for ((mut camera, mut camera_transform), target) in query_set.q1_mut().iter_mut().zip(targets) {
+        if let SpectatorCameraMode::Follow(_) = camera.mode {
+            match target {
+                Some(character_transform) => {
+                    camera_transform.translation = Vec3::lerp(
+                        camera_transform.translation,
+                        character_transform.translation + camera.follow_offset,
+                        0.05,
+                    );
+                }
+                // The followed character is gone, stay where we are
+                None => camera.mode = SpectatorCameraMode::Free,
+            }
+        }
+    }
+}
@@ -0,0 +1,542 @@
+//! Headless tests, including multi-peer ones with every peer in its own `App` connected over the
+//! memory transport.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    thread,
+    time::{Duration, Instant},
+};
+
+use bevy::{app::Events, prelude::*};
+use bevy_rapier3d::prelude::*;
+use libp2p::gossipsub::{GossipsubEvent, TopicHash};
+
+use crate::{
+    app_state::{
+        lobby::{LobbyNetworkPlugin, LobbyUIEvent},
+        AppState,
+    },
+    cleanup::CleanupPlugin,
+    damage::PlayerCharacter,
+    enemy::{archetype::WeakPointDefinition, spawn_weak_point},
+    latency::PeerLatencies,
+    network::{
+        NetworkAddress, NetworkBehaviourEvent, NetworkEvent, NetworkManager, NetworkPlugin,
+        NetworkTopic, NetworkTransport, PeerId,
+    },
+    party::Party,
+    physics::PhysicsPlugin,
+    player::{Player, PlayerId, PlayerRole},
+    player_input::PlayerInputPlugin,
+    resources::{
+        DelayedInputs, GameReplay, MainCharacterInput, PbrResources, Score, SimulationSettings,
+        Tick,
+    },
+    tick::{TickClock, TickPlugin},
+    weapons::{definition::WeaponDefinitions, WeaponInput},
+    MainCharacterMovement, PhysicsFlags, SimulationPlugin, MAIN_CHARACTER_WALK_SPEED,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(20);
+const CLIENTS: usize = 2;
+const TICKS: usize = 120;
+/// Ticks between changes of the scripted inputs.
+const INPUT_INTERVAL: usize = 8;
+
+/// Topics the connected peers subscribed to, as seen by the local gossipsub. Messages published
+/// before a subscription is seen are not sent to that peer.
+#[derive(Default)]
+struct PeerSubscriptions(HashSet<(PeerId, TopicHash)>);
+
+impl PeerSubscriptions {
+    fn subscribers(&self, topic: &str) -> usize {
+        let topic = NetworkTopic::new(topic).hash();
+
+        self.0
+            .iter()
+            .filter(|(_, subscribed_topic)| *subscribed_topic == topic)
+            .count()
+    }
+}
+
+fn track_peer_subscriptions(
+    mut subscriptions: ResMut<PeerSubscriptions>,
+    mut network_events: EventReader<NetworkEvent>,
+) {
+    for event in network_events.iter() {
+        if let NetworkEvent::Behaviour(NetworkBehaviourEvent::Gossipsub(
+            GossipsubEvent::Subscribed { peer_id, topic },
+        )) = event
+        {
+            subscriptions.0.insert((*peer_id, topic.clone()));
+        }
+    }
+}
+
+/// Ticks the test lets the app simulate. Inputs published before the other peers are seen on the
+/// input topic are lost, so the game is held on its first tick until the test starts it.
+struct TestTicks {
+    started: bool,
+    last: usize,
+}
+
+/// Holds the next tick back the way `wait_for_player_inputs` does, outside of the test ticks.
+fn hold_test_ticks(test_ticks: Res<TestTicks>, tick: Res<Tick>, mut tick_clock: ResMut<TickClock>) {
+    if !test_ticks.started || tick.0 >= test_ticks.last {
+        tick_clock.waiting = true;
+    }
+}
+
+/// Stands in for `game_main_character_input`, queueing and sending the scripted input of the local
+/// player for the ticks up to the input delay ahead.
+fn sample_scripted_input(
+    tick: Res<Tick>,
+    simulation_settings: Res<SimulationSettings>,
+    test_ticks: Res<TestTicks>,
+    party: Res<Party>,
+    network_manager: Res<NetworkManager>,
+    mut delayed_inputs: ResMut<DelayedInputs>,
+    mut input_events: EventWriter<MainCharacterInput>,
+) {
+    if !test_ticks.started {
+        return;
+    }
+
+    let local_player_id = network_manager.local_peer_id().into();
+    let player_index = match party
+        .character_players()
+        .iter()
+        .position(|&player_id| player_id == local_player_id)
+    {
+        Some(player_index) => player_index,
+        None => return,
+    };
+
+    let apply_tick = tick.0 + simulation_settings.input_delay as usize;
+    let first_tick = delayed_inputs
+        .0
+        .back()
+        .map_or(tick.0, |latest| latest.tick.0 + 1);
+
+    for input_tick in first_tick..=apply_tick {
+        let (movement, facing, weapon_input) = scripted_input(player_index, input_tick);
+        let sampled = MainCharacterInput {
+            tick: Tick(input_tick),
+            movement,
+            facing,
+            weapon_input,
+        };
+
+        delayed_inputs.0.push_back(sampled);
+        input_events.send(sampled);
+    }
+}
+
+/// Floor the characters stand on, in place of the environment of `setup`, which needs the render
+/// resources.
+fn spawn_floor(mut commands: Commands) {
+    commands.spawn_bundle(ColliderBundle {
+        shape: ColliderShape::halfspace(Vector::y_axis()),
+        ..Default::default()
+    });
+}
+
+/// An app with the lobby, the input path and the simulation of the game, including physics,
+/// without rendering or the UI.
+fn headless_app() -> App {
+    let mut builder = App::build();
+
+    builder
+        .insert_resource(NetworkTransport::Memory)
+        .add_plugins(MinimalPlugins)
+        .add_plugin(PhysicsPlugin::<NoUserData>::default())
+        .add_plugin(NetworkPlugin)
+        .insert_resource(State::new(AppState::MainMenu))
+        .add_system_set_to_stage(CoreStage::Update, State::<AppState>::get_driver())
+        .add_plugin(TickPlugin)
+        .add_plugin(LobbyNetworkPlugin)
+        .add_plugin(PlayerInputPlugin)
+        .add_plugin(SimulationPlugin)
+        .add_plugin(CleanupPlugin)
+        .insert_resource(PeerLatencies::default())
+        .insert_resource(Tick(0))
+        .insert_resource(Score(0))
+        .insert_resource(SimulationSettings::default())
+        .insert_resource(DelayedInputs::default())
+        .insert_resource(GameReplay {
+            tick: Tick(0),
+            main_character_inputs: Vec::new(),
+            main_character_inputs_index: 0,
+            main_character_final_position: Vec3::ZERO,
+        })
+        .insert_resource(PbrResources {
+            checkerboard_material: Default::default(),
+            checkerboard_render_pipelines: RenderPipelines::default(),
+            main_character_mesh: Default::default(),
+            main_character_material: Default::default(),
+            weapon_mesh: Default::default(),
+            weapon_material: Default::default(),
+            projectile_mesh: Default::default(),
+            projectile_material: Default::default(),
+        })
+        // Characters spawn without weapons
+        .insert_resource(WeaponDefinitions {
+            handles: HashMap::new(),
+            assets: HashMap::new(),
+        })
+        .insert_resource(PeerSubscriptions::default())
+        .insert_resource(TestTicks {
+            started: false,
+            last: TICKS,
+        })
+        .add_event::<MainCharacterInput>()
+        .add_system(track_peer_subscriptions.system())
+        .add_system_to_stage(
+            CoreStage::First,
+            hold_test_ticks.system().before("tick_clock"),
+        )
+        .add_system_set(SystemSet::on_enter(AppState::InGame).with_system(spawn_floor.system()))
+        .add_system_set(
+            SystemSet::on_update(AppState::InGame)
+                .with_system(sample_scripted_input.system().label("sample_input")),
+        );
+
+    builder.app
+}
+
+fn local_player_id(app: &App) -> PlayerId {
+    app.world
+        .get_resource::<NetworkManager>()
+        .unwrap()
+        .local_peer_id()
+        .into()
+}
+
+fn local_player(app: &App, name: &str) -> Player {
+    Player {
+        id: local_player_id(app),
+        name: name.to_string(),
+        role: PlayerRole::Player,
+    }
+}
+
+/// Number of peers the app has seen subscribe to the topic.
+fn subscribers(app: &App, topic: &str) -> usize {
+    app.world
+        .get_resource::<PeerSubscriptions>()
+        .unwrap()
+        .subscribers(topic)
+}
+
+fn app_state(app: &App) -> AppState {
+    *app.world
+        .get_resource::<State<AppState>>()
+        .unwrap()
+        .current()
+}
+
+fn set_app_state(app: &mut App, state: AppState) {
+    app.world
+        .get_resource_mut::<State<AppState>>()
+        .unwrap()
+        .set(state)
+        .unwrap();
+}
+
+fn tick(app: &App) -> usize {
+    app.world.get_resource::<Tick>().unwrap().0
+}
+
+/// Updates every app until `done` holds, giving the network tasks time to run in between.
+fn update_until(apps: &mut [App], description: &str, done: impl Fn(&[App]) -> bool) {
+    let start = Instant::now();
+
+    loop {
+        for app in apps.iter_mut() {
+            app.update();
+        }
+
+        if done(apps) {
+            return;
+        }
+
+        assert!(
+            start.elapsed() < TIMEOUT,
+            "Timed out waiting for {}",
+            description
+        );
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+fn host_lobby(app: &mut App) -> NetworkAddress {
+    let address: NetworkAddress = format!("/memory/{}", rand::random::<u64>().max(1))
+        .parse()
+        .unwrap();
+    let player = local_player(app, "Host");
+    app.world.insert_resource(Party::new(player));
+
+    let mut network_manager = app.world.get_resource_mut::<NetworkManager>().unwrap();
+    network_manager.listen_on(address.clone()).unwrap();
+    network_manager.subscribe(NetworkTopic::new("join_request"));
+
+    set_app_state(app, AppState::InLobby);
+
+    address
+}
+
+fn join_lobby(app: &mut App, name: &str, address: NetworkAddress) {
+    let player = local_player(app, name);
+    app.world.insert_resource(Party::new(player));
+
+    let mut network_manager = app.world.get_resource_mut::<NetworkManager>().unwrap();
+    let listen_address = network_manager.any_address();
+    network_manager.listen_on(listen_address).unwrap();
+    network_manager.subscribe(NetworkTopic::new("join_accepted"));
+    network_manager.subscribe(NetworkTopic::new("join_rejected"));
+    network_manager.dial_addr(address);
+
+    set_app_state(app, AppState::JoiningLobby);
+}
+
+/// Input of the player with the given index for the interval the tick falls in.
+fn scripted_input(player_index: usize, tick: usize) -> (Vec2, f32, WeaponInput) {
+    let step = tick / INPUT_INTERVAL + player_index;
+    let angle = step as f32 * 0.7;
+
+    (
+        MAIN_CHARACTER_WALK_SPEED * Vec2::new(angle.cos(), angle.sin()),
+        angle,
+        WeaponInput {
+            fire: step % 2 == 0,
+            aim: step % 3 == 0,
+            ..Default::default()
+        },
+    )
+}
+
+fn state_hash(app: &mut App) -> u64 {
+    let mut query = app.world.query::<(
+        &PlayerCharacter,
+        &MainCharacterMovement,
+        &WeaponInput,
+        &RigidBodyPosition,
+    )>();
+    let mut characters: Vec<_> = query
+        .iter(&app.world)
+        .map(|(character, movement, weapon_input, position)| {
+            let isometry = position.position;
+
+            (
+                Into::<PeerId>::into(character.0).to_base58(),
+                isometry
+                    .translation
+                    .vector
+                    .iter()
+                    .map(|coordinate| coordinate.to_bits())
+                    .collect::<Vec<_>>(),
+                isometry
+                    .rotation
+                    .coords
+                    .iter()
+                    .map(|coordinate| coordinate.to_bits())
+                    .collect::<Vec<_>>(),
+                (
+                    movement.want_to_move.x.to_bits(),
+                    movement.want_to_move.y.to_bits(),
+                ),
+                movement.want_to_face.to_bits(),
+                format!("{:?}", weapon_input),
+            )
+        })
+        .collect();
+
+    characters.sort();
+
+    let mut hasher = DefaultHasher::new();
+    characters.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[test]
+fn party_simulation_stays_in_sync_over_memory_transport() {
+    let mut apps: Vec<App> = (0..=CLIENTS).map(|_| headless_app()).collect();
+    let party_size = apps.len();
+
+    // Lobby
+    let address = host_lobby(&mut apps[0]);
+
+    for client in 1..party_size {
+        join_lobby(
+            &mut apps[client],
+            &format!("Client {}", client),
+            address.clone(),
+        );
+
+        update_until(&mut apps, "the client to join", |apps| {
+            app_state(&apps[client]) == AppState::InLobby
+        });
+    }
+
+    update_until(&mut apps, "every peer to know the whole party", |apps| {
+        apps.iter()
+            .all(|app| app.world.get_resource::<Party>().unwrap().players.len() == party_size)
+            && subscribers(&apps[0], "start_game") == party_size - 1
+    });
+
+    apps[0]
+        .world
+        .get_resource_mut::<Events<LobbyUIEvent>>()
+        .unwrap()
+        .send(LobbyUIEvent::StartGame);
+
+    update_until(&mut apps, "every peer to start the game", |apps| {
+        apps.iter().all(|app| app_state(app) == AppState::InGame)
+    });
+
+    let settings = *apps[0].world.get_resource::<SimulationSettings>().unwrap();
+
+    for app in apps.iter() {
+        assert_eq!(
+            *app.world.get_resource::<SimulationSettings>().unwrap(),
+            settings
+        );
+    }
+
+    // The host relays between clients, which are only connected to it
+    update_until(&mut apps, "the input topic to be subscribed", |apps| {
+        subscribers(&apps[0], "input") == party_size - 1
+            && apps[1..].iter().all(|app| subscribers(app, "input") == 1)
+    });
+
+    let initial_hash = state_hash(&mut apps[0]);
+
+    for app in apps.iter_mut() {
+        app.world.get_resource_mut::<TestTicks>().unwrap().started = true;
+    }
+
+    // Lockstep, every peer waiting before each tick for the inputs the other peers sampled
+    update_until(&mut apps, "every peer to simulate the ticks", |apps| {
+        apps.iter().all(|app| tick(app) == TICKS)
+    });
+
+    let hashes: Vec<_> = apps.iter_mut().map(|app| state_hash(app)).collect();
+
+    assert_ne!(hashes[0], initial_hash, "Characters did not move");
+
+    for hash in hashes.iter() {
+        assert_eq!(*hash, hashes[0], "Peers diverged");
+    }
+}
+
+/// Ray cast by `cast_test_ray` after the physics step, the way hitscan shots are.
+struct TestRay {
+    origin: Vec3,
+    direction: Vec3,
+    hit: Option<Entity>,
+}
+
+fn cast_test_ray(
+    query_pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    mut test_ray: ResMut<TestRay>,
+) {
+    let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+    let ray = Ray::new(test_ray.origin.into(), test_ray.direction.into());
+
+    test_ray.hit = query_pipeline
+        .cast_ray(
+            &collider_set,
+            &ray,
+            10.0,
+            true,
+            InteractionGroups::new(PhysicsFlags::PROJECTILE.bits(), u32::MAX),
+            None,
+        )
+        .map(|(handle, _)| handle.entity());
+}
+
+#[test]
+fn weak_points_follow_moving_enemies() {
+    let mut builder = App::build();
+
+    builder
+        .add_plugins(MinimalPlugins)
+        .add_plugin(PhysicsPlugin::<NoUserData>::default())
+        .insert_resource(TickClock {
+            accumulated: 0.0,
+            tick_due: true,
+            waiting: false,
+        })
+        .insert_resource(TestRay {
+            origin: Vec3::ZERO,
+            direction: Vec3::X,
+            hit: None,
+        })
+        .add_system_to_stage(CoreStage::PostUpdate, cast_test_ray.system());
+
+    let mut app = builder.app;
+
+    let enemy = app
+        .world
+        .spawn()
+        .insert_bundle(RigidBodyBundle {
+            body_type: RigidBodyType::Dynamic,
+            velocity: RigidBodyVelocity {
+                linvel: Vec3::new(0.0, 0.0, 4.0).into(),
+                ..Default::default()
+            },
+            forces: RigidBodyForces {
+                gravity_scale: 0.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert_bundle(ColliderBundle {
+            shape: SharedShape::ball(0.5),
+            ..Default::default()
+        })
+        .id();
+
+    let mut command_queue = Default::default();
+    let weak_point = spawn_weak_point(
+        &mut Commands::new(&mut command_queue, &app.world),
+        enemy,
+        Vec3::ZERO,
+        &WeakPointDefinition {
+            offset: [0.0, 2.0, 0.0],
+            radius: 0.3,
+            damage_multiplier: 2.0,
+        },
+    );
+    command_queue.apply(&mut app.world);
+
+    // One second at the default integration time step
+    for _ in 0..60 {
+        app.update();
+    }
+
+    let enemy_position: Vec3 = app
+        .world
+        .get::<RigidBodyPosition>(enemy)
+        .unwrap()
+        .position
+        .translation
+        .into();
+
+    assert!(enemy_position.z > 3.0, "Enemy did not move");
+
+    // Across the head of the enemy where it stands now, clear of its body
+    *app.world.get_resource_mut::<TestRay>().unwrap() = TestRay {
+        origin: enemy_position + Vec3::new(-5.0, 2.0, 0.0),
+        direction: Vec3::X,
+        hit: None,
+    };
+    app.update();
+
+    assert_eq!(
+        app.world.get_resource::<TestRay>().unwrap().hit,
+        Some(weak_point)
+    );
+}
@@ -0,0 +1,100 @@
+use bevy::{
+    app::Events,
+    ecs::{component::Component, schedule::ShouldRun},
+    prelude::*,
+};
+
+use crate::{app_state::AppState, resources::SimulationSettings};
+
+/// Runs the simulation at the tick rate whatever the frame rate.
+///
+/// Every frame runs to completion, but the systems that advance the simulation only run on the
+/// frames where a tick is due. They are added in `on_tick` sets or gated with `tick_due`.
+pub struct TickPlugin;
+
+impl Plugin for TickPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(TickClock::default())
+            .add_system_to_stage(
+                CoreStage::First,
+                advance_tick_clock.system().label("tick_clock"),
+            );
+    }
+}
+
+/// Frame time not simulated yet, and whether the current frame runs a tick.
+#[derive(Default)]
+pub struct TickClock {
+    pub accumulated: f32,
+    pub tick_due: bool,
+    /// Holds the next tick back for the current frame. Set before `tick_clock`, for instance while
+    /// inputs for the tick are missing.
+    pub waiting: bool,
+}
+
+/// Run criteria of the systems that advance the simulation, whatever the state.
+pub fn tick_due(tick_clock: Res<TickClock>) -> ShouldRun {
+    if tick_clock.tick_due {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+/// Like `SystemSet::on_update`, but only on the frames where a tick is due.
+pub fn on_tick(state: AppState) -> SystemSet {
+    SystemSet::new().with_run_criteria(
+        (move |app_state: Res<State<AppState>>, tick_clock: Res<TickClock>| {
+            if tick_clock.tick_due && *app_state.current() == state {
+                ShouldRun::Yes
+            } else {
+                ShouldRun::No
+            }
+        })
+        .system(),
+    )
+}
+
+pub trait AddTickEvent {
+    /// Adds an event sent and read by tick systems. Its buffers are swapped after every tick instead
+    /// of every frame, so events sent on one tick are still there on the next however many frames
+    /// apart the ticks run.
+    fn add_tick_event<T: Component>(&mut self) -> &mut Self;
+}
+
+impl AddTickEvent for AppBuilder {
+    fn add_tick_event<T: Component>(&mut self) -> &mut Self {
+        self.insert_resource(Events::<T>::default())
+            .add_system_to_stage(
+                CoreStage::Last,
+                Events::<T>::update_system
+                    .system()
+                    .with_run_criteria(tick_due.system()),
+            )
+    }
+}
+
+fn advance_tick_clock(
+    time: Res<Time>,
+    app_state: Res<State<AppState>>,
+    simulation_settings: Res<SimulationSettings>,
+    mut tick_clock: ResMut<TickClock>,
+) {
+    if !matches!(app_state.current(), AppState::InGame | AppState::Replay) {
+        *tick_clock = TickClock::default();
+        return;
+    }
+
+    let tick_duration = simulation_settings.tick_duration();
+
+    // A frame runs at most one tick, so time lost to slow frames is dropped instead of being caught
+    // up on every following frame
+    tick_clock.accumulated =
+        (tick_clock.accumulated + time.delta_seconds()).min(2.0 * tick_duration);
+    tick_clock.tick_due = tick_clock.accumulated >= tick_duration && !tick_clock.waiting;
+    tick_clock.waiting = false;
+
+    if tick_clock.tick_due {
+        tick_clock.accumulated -= tick_duration;
+    }
+}
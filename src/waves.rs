@@ -0,0 +1,427 @@
+use std::collections::HashMap;
+
+use bevy::{prelude::*, reflect::TypeUuid};
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_pcg::Pcg64Mcg;
+use serde::Deserialize;
+
+use crate::{
+    enemy::{
+        archetype::{EnemyArchetype, EnemyArchetypes},
+        spawn_enemy, Enemy, EnemyBehavior, EnemyIds, EnemyResources,
+    },
+    json_asset::AddJsonAsset,
+    party::Party,
+    player::PlayerRole,
+    tick::{on_tick, AddTickEvent},
+    AppState, Tick,
+};
+
+pub struct WavePlugin;
+
+impl Plugin for WavePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_tick_event::<SpawnEnemyEvent>()
+            .add_json_asset::<WaveTable>(&["waves.json"])
+            .add_startup_system(init_wave_resources.system())
+            .add_system(check_wave_tables.system())
+            .add_system_set(
+                SystemSet::on_enter(AppState::InGame).with_system(wave_director_setup.system()),
+            )
+            .add_system_set(
+                on_tick(AppState::InGame)
+                    .with_system(wave_director.system().label("wave_director"))
+                    // Enemy ids are handed out in the same order on every peer
+                    .with_system(spawn_requested_enemies.system().after("wave_director")),
+            );
+    }
+}
+
+/// Wave definitions loaded from a `.waves.json` file.
+#[derive(Deserialize, TypeUuid)]
+#[uuid = "5c3a7f0e-2b1d-4d8e-9a64-0f7e1c9b2a41"]
+pub struct WaveTable {
+    /// Ticks before the first wave starts.
+    pub first_break_ticks: usize,
+    /// Ticks between the last enemy of a wave dying and the next wave starting.
+    pub break_ticks: usize,
+    pub spawn_zones: HashMap<String, SpawnZone>,
+    /// Waves in order. The last one repeats with scaling once the table is exhausted.
+    pub waves: Vec<WaveDefinition>,
+    pub scaling: WaveScaling,
+}
+
+#[derive(Deserialize)]
+pub struct WaveDefinition {
+    pub spawn_interval_ticks: usize,
+    pub spawn_zones: Vec<String>,
+    pub groups: Vec<WaveGroup>,
+    /// Enemy type spawned on its own once the rest of the wave is cleared.
+    #[serde(default)]
+    pub boss: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct WaveGroup {
+    /// Name of the enemy type to spawn.
+    pub enemy: String,
+    pub count: u32,
+}
+
+/// Axis-aligned rectangle on the ground, given as `[x, z]` corners.
+#[derive(Deserialize)]
+pub struct SpawnZone {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+#[derive(Deserialize)]
+pub struct WaveScaling {
+    /// Extra enemies per wave past the end of the table, as a fraction of the base count.
+    pub count_per_wave: f32,
+    /// Extra enemies per player beyond the first, as a fraction of the base count.
+    pub count_per_extra_player: f32,
+    /// Extra enemy health per wave past the end of the table, as a fraction of the base health.
+    pub health_per_wave: f32,
+}
+
+impl WaveTable {
+    /// Definition of the given wave, the last one repeating. `None` for a table without waves.
+    fn wave(&self, number: usize) -> Option<&WaveDefinition> {
+        self.waves.get(number).or_else(|| self.waves.last())
+    }
+
+    fn overflow(&self, number: usize) -> f32 {
+        number.saturating_sub(self.waves.len().saturating_sub(1)) as f32
+    }
+
+    fn count_multiplier(&self, number: usize, player_count: usize) -> f32 {
+        (1.0 + self.scaling.count_per_wave * self.overflow(number))
+            * (1.0 + self.scaling.count_per_extra_player * player_count.saturating_sub(1) as f32)
+    }
+
+    fn health_multiplier(&self, number: usize) -> f32 {
+        1.0 + self.scaling.health_per_wave * self.overflow(number)
+    }
+}
+
+pub struct WaveResources {
+    pub table: Handle<WaveTable>,
+}
+
+pub enum WavePhase {
+    Starting,
+    Break { until_tick: usize },
+    Spawning { next_spawn_tick: usize },
+    Fighting,
+}
+
+/// Progress through the wave table. Number 0 is the first wave.
+pub struct WaveDirector {
+    pub number: usize,
+    pub phase: WavePhase,
+    spawn_queue: Vec<String>,
+    boss_spawned: bool,
+}
+
+/// Request to spawn an enemy outside of the wave table, such as a boss summoning minions.
+///
+/// Spawned enemies count towards the current wave and get its health scaling.
+pub struct SpawnEnemyEvent {
+    pub enemy: String,
+    pub position: Vec3,
+}
+
+fn init_wave_resources(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(WaveResources {
+        table: asset_server.load("waves/default.waves.json"),
+    });
+}
+
+/// Reports the parts of loaded wave tables that the director has to skip.
+fn check_wave_tables(
+    wave_tables: Res<Assets<WaveTable>>,
+    mut table_events: EventReader<AssetEvent<WaveTable>>,
+) {
+    for table_event in table_events.iter() {
+        let table = match table_event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                match wave_tables.get(handle) {
+                    Some(table) => table,
+                    None => continue,
+                }
+            }
+            AssetEvent::Removed { .. } => continue,
+        };
+
+        if table.waves.is_empty() {
+            warn!("Wave table has no waves");
+        }
+
+        for (number, wave) in table.waves.iter().enumerate() {
+            for zone_name in wave.spawn_zones.iter() {
+                if !table.spawn_zones.contains_key(zone_name) {
+                    warn!("Unknown spawn zone {} in wave {}", zone_name, number + 1);
+                }
+            }
+
+            if !wave
+                .spawn_zones
+                .iter()
+                .any(|zone_name| table.spawn_zones.contains_key(zone_name))
+            {
+                warn!(
+                    "Wave {} has no spawn zone, its enemies are skipped",
+                    number + 1
+                );
+            }
+        }
+    }
+}
+
+fn wave_director_setup(mut commands: Commands) {
+    commands.insert_resource(WaveDirector {
+        number: 0,
+        phase: WavePhase::Starting,
+        spawn_queue: Vec::new(),
+        boss_spawned: false,
+    });
+}
+
+/// Runs the wave state machine off the simulation tick.
+///
+/// Randomness comes from a generator seeded with the tick and wave number rather than the shared
+/// `Random`, so the result does not depend on which other systems drew from it this frame.
+fn wave_director(
+    mut commands: Commands,
+    tick: Res<Tick>,
+    party: Option<Res<Party>>,
+    wave_resources: Res<WaveResources>,
+    wave_tables: Res<Assets<WaveTable>>,
+    enemy_resources: Res<EnemyResources>,
+    mut enemy_ids: ResMut<EnemyIds>,
+    enemy_archetypes: Res<EnemyArchetypes>,
+    archetypes: Res<Assets<EnemyArchetype>>,
+    mut director: ResMut<WaveDirector>,
+    enemy_query: Query<&EnemyBehavior, With<Enemy>>,
+) {
+    let table = match wave_tables.get(&wave_resources.table) {
+        Some(table) => table,
+        None => return,
+    };
+    // Reported by `check_wave_tables`
+    let wave = match table.wave(director.number) {
+        Some(wave) => wave,
+        None => return,
+    };
+
+    let mut generator = Pcg64Mcg::seed_from_u64((tick.0 as u64) ^ ((director.number as u64) << 32));
+
+    match director.phase {
+        WavePhase::Starting => {
+            director.phase = WavePhase::Break {
+                until_tick: tick.0 + table.first_break_ticks,
+            };
+        }
+        WavePhase::Break { until_tick } => {
+            if tick.0 < until_tick {
+                return;
+            }
+
+            let player_count = party.map_or(1, |party| {
+                party
+                    .players
+                    .values()
+                    .filter(|player| player.role == PlayerRole::Player)
+                    .count()
+                    .max(1)
+            });
+            let count_multiplier = table.count_multiplier(director.number, player_count);
+
+            let mut spawn_queue = Vec::new();
+
+            for group in &wave.groups {
+                let count = (group.count as f32 * count_multiplier).round() as usize;
+                spawn_queue.extend(std::iter::repeat(group.enemy.clone()).take(count));
+            }
+
+            spawn_queue.shuffle(&mut generator);
+
+            info!(
+                "Wave {} starting with {} enemies",
+                director.number + 1,
+                spawn_queue.len()
+            );
+
+            director.spawn_queue = spawn_queue;
+            director.boss_spawned = false;
+            director.phase = WavePhase::Spawning {
+                next_spawn_tick: tick.0,
+            };
+        }
+        WavePhase::Spawning { next_spawn_tick } => {
+            if tick.0 < next_spawn_tick {
+                return;
+            }
+
+            if let Some(enemy_name) = director.spawn_queue.last() {
+                let archetype = match enemy_archetypes.handles.get(enemy_name) {
+                    Some(archetype) => archetype,
+                    None => {
+                        warn!("Unknown enemy type {} in wave table", enemy_name);
+                        director.spawn_queue.pop();
+                        return;
+                    }
+                };
+
+                let position = match random_spawn_position(table, wave, &mut generator) {
+                    Some(position) => position,
+                    // Reported by `check_wave_tables`
+                    None => {
+                        director.spawn_queue.clear();
+                        director.phase = WavePhase::Fighting;
+                        return;
+                    }
+                };
+
+                // Wait for the archetype to load rather than skipping the enemy
+                if spawn_enemy(
+                    &mut commands,
+                    &enemy_resources,
+                    &mut enemy_ids,
+                    &enemy_archetypes,
+                    &archetypes,
+                    archetype,
+                    position,
+                    table.health_multiplier(director.number),
+                )
+                .is_none()
+                {
+                    return;
+                }
+
+                director.spawn_queue.pop();
+            }
+
+            director.phase = if director.spawn_queue.is_empty() {
+                WavePhase::Fighting
+            } else {
+                WavePhase::Spawning {
+                    next_spawn_tick: tick.0 + wave.spawn_interval_ticks,
+                }
+            };
+        }
+        WavePhase::Fighting => {
+            let alive = enemy_query
+                .iter()
+                .any(|behavior| !matches!(behavior, EnemyBehavior::Death));
+
+            if alive {
+                return;
+            }
+
+            if let (Some(boss_name), false) = (&wave.boss, director.boss_spawned) {
+                let archetype = match enemy_archetypes.handles.get(boss_name) {
+                    Some(archetype) => archetype,
+                    None => {
+                        warn!("Unknown boss type {} in wave table", boss_name);
+                        director.boss_spawned = true;
+                        return;
+                    }
+                };
+
+                let position = match random_spawn_position(table, wave, &mut generator) {
+                    Some(position) => position,
+                    None => {
+                        director.boss_spawned = true;
+                        return;
+                    }
+                };
+
+                if spawn_enemy(
+                    &mut commands,
+                    &enemy_resources,
+                    &mut enemy_ids,
+                    &enemy_archetypes,
+                    &archetypes,
+                    archetype,
+                    position,
+                    table.health_multiplier(director.number),
+                )
+                .is_some()
+                {
+                    info!("Wave {} boss {} appears", director.number + 1, boss_name);
+                    director.boss_spawned = true;
+                }
+            } else {
+                info!("Wave {} cleared", director.number + 1);
+
+                director.number += 1;
+                director.phase = WavePhase::Break {
+                    until_tick: tick.0 + table.break_ticks,
+                };
+            }
+        }
+    }
+}
+
+/// Random position in one of the spawn zones of the wave, or `None` if it names no known zone.
+fn random_spawn_position(
+    table: &WaveTable,
+    wave: &WaveDefinition,
+    generator: &mut Pcg64Mcg,
+) -> Option<Vec3> {
+    let zones: Vec<_> = wave
+        .spawn_zones
+        .iter()
+        .filter_map(|zone_name| table.spawn_zones.get(zone_name))
+        .collect();
+    let zone = zones.choose(generator)?;
+
+    Some(Vec3::new(
+        generator.gen_range(zone.min[0]..=zone.max[0]),
+        0.0,
+        generator.gen_range(zone.min[1]..=zone.max[1]),
+    ))
+}
+
+fn spawn_requested_enemies(
+    mut commands: Commands,
+    wave_resources: Res<WaveResources>,
+    wave_tables: Res<Assets<WaveTable>>,
+    enemy_resources: Res<EnemyResources>,
+    mut enemy_ids: ResMut<EnemyIds>,
+    enemy_archetypes: Res<EnemyArchetypes>,
+    archetypes: Res<Assets<EnemyArchetype>>,
+    director: Res<WaveDirector>,
+    mut spawn_events: EventReader<SpawnEnemyEvent>,
+) {
+    let health_multiplier = wave_tables
+        .get(&wave_resources.table)
+        .map_or(1.0, |table| table.health_multiplier(director.number));
+
+    for spawn_event in spawn_events.iter() {
+        let archetype = match enemy_archetypes.handles.get(&spawn_event.enemy) {
+            Some(archetype) => archetype,
+            None => {
+                warn!("Unknown enemy type {} requested", spawn_event.enemy);
+                continue;
+            }
+        };
+
+        if spawn_enemy(
+            &mut commands,
+            &enemy_resources,
+            &mut enemy_ids,
+            &enemy_archetypes,
+            &archetypes,
+            archetype,
+            spawn_event.position,
+            health_multiplier,
+        )
+        .is_none()
+        {
+            warn!("Enemy type {} is not loaded yet", spawn_event.enemy);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{resources::SimulationSettings, Tick};
+
+use super::{
+    definition::WeaponDefinition, Weapon, WeaponAmmoCount, WeaponEnabled, WeaponInput, WeaponReload,
+};
+
+/// Kind of ammunition a weapon loads. Weapons of the same type share the reserve of their holder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum AmmoType {
+    Light,
+    Shell,
+    Explosive,
+}
+
+impl Default for AmmoType {
+    fn default() -> Self {
+        AmmoType::Light
+    }
+}
+
+/// Rounds a character carries outside of its magazines, per ammo type.
+pub struct AmmoReserve {
+    pub counts: HashMap<AmmoType, u32>,
+}
+
+impl AmmoReserve {
+    /// Reserve characters start with.
+    pub fn starting() -> Self {
+        let mut counts = HashMap::new();
+        counts.insert(AmmoType::Light, 120);
+        counts.insert(AmmoType::Shell, 24);
+        counts.insert(AmmoType::Explosive, 8);
+
+        Self { counts }
+    }
+
+    pub fn count(&self, ammo_type: AmmoType) -> u32 {
+        self.counts.get(&ammo_type).copied().unwrap_or(0)
+    }
+
+    pub fn add(&mut self, ammo_type: AmmoType, amount: u32) {
+        *self.counts.entry(ammo_type).or_insert(0) += amount;
+    }
+
+    /// Takes up to `amount` rounds out of the reserve and returns how many were taken.
+    pub fn take(&mut self, ammo_type: AmmoType, amount: u32) -> u32 {
+        let count = self.counts.entry(ammo_type).or_insert(0);
+        let taken = amount.min(*count);
+        *count -= taken;
+
+        taken
+    }
+}
+
+/// Starts reloading a weapon, unless its magazine is full or there is no reserve ammo left for it.
+///
+/// A reload from an empty magazine takes the longer `empty_reload_time`. Weapons held by something
+/// without a reserve can always be reloaded.
+pub fn start_reload(
+    definition: &WeaponDefinition,
+    ammo_count: &WeaponAmmoCount,
+    reload: &mut WeaponReload,
+    reserve: Option<&AmmoReserve>,
+    tick: usize,
+    simulation_settings: &SimulationSettings,
+) -> bool {
+    if reload.done_tick.is_some()
+        || ammo_count.0 >= definition.magazine_size
+        || reserve.map_or(false, |reserve| reserve.count(definition.ammo_type) == 0)
+    {
+        return false;
+    }
+
+    let reload_time = if ammo_count.0 <= 0 {
+        definition.empty_reload_time
+    } else {
+        definition.reload_time
+    };
+    reload.done_tick = Some(tick + simulation_settings.ticks(reload_time));
+
+    true
+}
+
+/// Reloads the active weapon of characters whose input asks for it.
+pub(super) fn manual_reload(
+    tick: Res<Tick>,
+    simulation_settings: Res<SimulationSettings>,
+    definitions: Res<Assets<WeaponDefinition>>,
+    holder_query: Query<(&WeaponInput, Option<&AmmoReserve>)>,
+    mut weapon_query: Query<
+        (&Weapon, &WeaponAmmoCount, &mut WeaponReload, &Parent),
+        With<WeaponEnabled>,
+    >,
+) {
+    for (weapon, ammo_count, mut reload, parent) in weapon_query.iter_mut() {
+        let reserve = match holder_query.get(parent.0) {
+            Ok((input, reserve)) if input.reload => reserve,
+            _ => continue,
+        };
+        let definition = match definitions.get(&weapon.definition) {
+            Some(definition) => definition,
+            None => continue,
+        };
+
+        start_reload(
+            definition,
+            ammo_count,
+            &mut reload,
+            reserve,
+            tick.0,
+            &simulation_settings,
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use bevy::{prelude::*, reflect::TypeUuid};
+use serde::Deserialize;
+
+use super::{
+    ammo::AmmoType,
+    explosive::ExplosionDefinition,
+    recoil::{Recoil, SpreadBloom},
+    WeaponFireMode,
+};
+
+/// Stats and looks of one kind of weapon, loaded from a `.weapon.json` file.
+#[derive(Deserialize, TypeUuid)]
+#[uuid = "b4e81d27-93c5-4f0a-a6d2-58f1c7e3b094"]
+pub struct WeaponDefinition {
+    pub name: String,
+    pub fire_mode: WeaponFireMode,
+    /// Shots per second. Within a burst, the delay between its shots.
+    pub rate_of_fire: f32,
+    pub magazine_size: i32,
+    #[serde(default)]
+    pub ammo_type: AmmoType,
+    /// Seconds a reload takes while there are rounds left in the magazine.
+    pub reload_time: f32,
+    /// Seconds a reload takes from an empty magazine.
+    pub empty_reload_time: f32,
+    /// Seconds between switching to the weapon and being able to fire it.
+    #[serde(default = "default_draw_time")]
+    pub draw_time: f32,
+    /// Width of the cone shots are spread in, in degrees.
+    pub spread: f32,
+    /// Growth of the spread with sustained fire and movement.
+    #[serde(default)]
+    pub bloom: Option<SpreadBloom>,
+    #[serde(default)]
+    pub recoil: Option<Recoil>,
+    /// Multiplier applied to the spread while aiming.
+    #[serde(default = "default_aim_spread_multiplier")]
+    pub aim_spread_multiplier: f32,
+    pub projectile_speed: f32,
+    /// Makes shots instant ray casts of this length instead of projectiles. Only a tracer is seen.
+    #[serde(default)]
+    pub hitscan_range: Option<f32>,
+    /// Damage dealt by each projectile.
+    pub damage: f32,
+    /// Loss of damage over distance. Damage is the same at any distance when missing.
+    #[serde(default)]
+    pub falloff: Option<DamageFalloff>,
+    /// Number of enemies each projectile goes through before stopping.
+    #[serde(default)]
+    pub penetration: u32,
+    /// Makes shots explode on the first solid thing they hit.
+    #[serde(default)]
+    pub explosion: Option<ExplosionDefinition>,
+    /// Projectiles fired per shot.
+    #[serde(default = "default_pellets")]
+    pub pellets: u32,
+    /// Scene used as the model held by characters.
+    pub model: String,
+    #[serde(default = "default_model_scale")]
+    pub model_scale: f32,
+    #[serde(default = "default_projectile_model")]
+    pub projectile_model: String,
+    pub shoot_sound: String,
+    pub reload_sound: String,
+    /// Distance up to which enemies hear the weapon being fired.
+    #[serde(default = "default_noise_radius")]
+    pub noise_radius: f32,
+}
+
+/// Full damage up to `start` meters, then linearly less down to `min_multiplier` times the damage
+/// at `end` meters and beyond.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct DamageFalloff {
+    pub start: f32,
+    pub end: f32,
+    pub min_multiplier: f32,
+}
+
+impl DamageFalloff {
+    pub fn multiplier(&self, distance: f32) -> f32 {
+        let t =
+            ((distance - self.start) / (self.end - self.start).max(f32::EPSILON)).clamp(0.0, 1.0);
+
+        1.0 + (self.min_multiplier - 1.0) * t
+    }
+}
+
+fn default_pellets() -> u32 {
+    1
+}
+
+fn default_aim_spread_multiplier() -> f32 {
+    0.5
+}
+
+fn default_draw_time() -> f32 {
+    0.4
+}
+
+fn default_model_scale() -> f32 {
+    0.2
+}
+
+fn default_projectile_model() -> String {
+    String::from("models/weapons/pistol_bullet/scene.gltf#Scene0")
+}
+
+fn default_noise_radius() -> f32 {
+    20.0
+}
+
+/// Names of the weapons loaded at startup.
+pub const WEAPON_NAMES: &[&str] = &[
+    "pistol_1",
+    "pistol_2",
+    "pistol_3",
+    "pistol_4",
+    "grenade_launcher",
+];
+
+/// Scene and sound handles loaded for a definition once it has loaded.
+pub struct WeaponDefinitionAssets {
+    pub model: Handle<Scene>,
+    pub projectile_scene: Handle<Scene>,
+    pub shoot_sound: Handle<AudioSource>,
+    pub reload_sound: Handle<AudioSource>,
+}
+
+pub struct WeaponDefinitions {
+    pub handles: HashMap<String, Handle<WeaponDefinition>>,
+    pub assets: HashMap<Handle<WeaponDefinition>, WeaponDefinitionAssets>,
+}
+
+pub(super) fn load_weapon_definitions(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let handles = WEAPON_NAMES
+        .iter()
+        .map(|&name| {
+            (
+                name.to_string(),
+                asset_server.load(format!("weapons/{}.weapon.json", name).as_str()),
+            )
+        })
+        .collect();
+
+    commands.insert_resource(WeaponDefinitions {
+        handles,
+        assets: HashMap::new(),
+    });
+}
+
+pub(super) fn prepare_weapon_definition_assets(
+    asset_server: Res<AssetServer>,
+    definitions: Res<Assets<WeaponDefinition>>,
+    mut weapon_definitions: ResMut<WeaponDefinitions>,
+    mut definition_events: EventReader<AssetEvent<WeaponDefinition>>,
+) {
+    for event in definition_events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                let definition = match definitions.get(handle) {
+                    Some(definition) => definition,
+                    None => continue,
+                };
+
+                weapon_definitions.assets.insert(
+                    handle.clone(),
+                    WeaponDefinitionAssets {
+                        model: asset_server.load(definition.model.as_str()),
+                        projectile_scene: asset_server.load(definition.projectile_model.as_str()),
+                        shoot_sound: asset_server.load(definition.shoot_sound.as_str()),
+                        reload_sound: asset_server.load(definition.reload_sound.as_str()),
+                    },
+                );
+            }
+            AssetEvent::Removed { handle } => {
+                weapon_definitions.assets.remove(handle);
+            }
+        }
+    }
+}
@@ -0,0 +1,393 @@
+use bevy::{prelude::*, render::mesh::shape};
+use bevy_rapier3d::{prelude::*, rapier::parry::shape::Ball};
+use serde::Deserialize;
+
+use super::{Projectile, WeaponInput};
+use crate::{
+    damage::{DamageEvent, DamageRules, PlayerCharacter},
+    despawn::DespawnAfter,
+    enemy::{EnemyBehavior, EnemyHitEvent, WeakPoint},
+    player::PlayerId,
+    resources::SimulationSettings,
+    PhysicsFlags, Tick,
+};
+
+/// Blast of a grenade or explosive projectile, as written in the `explosion` field of a weapon.
+///
+/// Damage and impulse are full at the center and drop linearly to nothing at `radius`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ExplosionDefinition {
+    pub radius: f32,
+    pub damage: f32,
+    pub impulse: f32,
+}
+
+/// Sent when something explodes at `position`.
+pub struct ExplosionEvent {
+    pub position: Vec3,
+    pub explosion: ExplosionDefinition,
+    /// Player who caused the explosion, for the damage rules.
+    pub attacker: Option<PlayerId>,
+}
+
+/// Thrown explosive that goes off once its fuse runs out.
+pub struct Grenade {
+    /// Tick the grenade explodes on.
+    pub explode_tick: usize,
+    pub thrower: Option<PlayerId>,
+}
+
+/// Grenades a character has left to throw.
+pub struct GrenadeCount(pub u32);
+
+pub struct ExplosiveResources {
+    grenade_mesh: Handle<Mesh>,
+    grenade_material: Handle<StandardMaterial>,
+    grenade_shape: SharedShape,
+    explosion_mesh: Handle<Mesh>,
+    explosion_material: Handle<StandardMaterial>,
+}
+
+pub const GRENADE_EXPLOSION: ExplosionDefinition = ExplosionDefinition {
+    radius: 5.0,
+    damage: 150.0,
+    impulse: 40.0,
+};
+
+/// Grenades characters start with.
+pub const STARTING_GRENADES: u32 = 3;
+
+/// Seconds between the throw and the explosion.
+const GRENADE_FUSE: f32 = 2.0;
+const GRENADE_RADIUS: f32 = 0.15;
+const GRENADE_THROW_SPEED: f32 = 12.0;
+const GRENADE_THROW_LIFT: f32 = 4.0;
+
+/// Seconds the flash of an explosion stays visible.
+const EXPLOSION_FLASH_LIFETIME: f32 = 0.15;
+
+/// Distance explosions are moved back from the surface that set them off, so the line of sight
+/// checks do not start inside of it.
+pub(super) const SURFACE_OFFSET: f32 = 0.2;
+
+pub(super) fn init_explosive_resources(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(ExplosiveResources {
+        grenade_mesh: meshes.add(Mesh::from(shape::Icosphere {
+            radius: GRENADE_RADIUS,
+            subdivisions: 2,
+        })),
+        grenade_material: materials.add(Color::DARK_GREEN.into()),
+        grenade_shape: SharedShape::ball(GRENADE_RADIUS),
+        explosion_mesh: meshes.add(Mesh::from(shape::Icosphere {
+            radius: 1.0,
+            subdivisions: 3,
+        })),
+        explosion_material: materials.add(StandardMaterial {
+            base_color: Color::rgba(1.0, 0.5, 0.1, 0.5),
+            unlit: true,
+            ..Default::default()
+        }),
+    });
+}
+
+pub(super) fn throw_grenade(
+    mut commands: Commands,
+    tick: Res<Tick>,
+    simulation_settings: Res<SimulationSettings>,
+    resources: Res<ExplosiveResources>,
+    mut character_query: Query<(
+        &Transform,
+        &PlayerCharacter,
+        &WeaponInput,
+        &mut GrenadeCount,
+    )>,
+) {
+    for (transform, character, input, mut grenade_count) in character_query.iter_mut() {
+        if !input.throw || grenade_count.0 == 0 {
+            continue;
+        }
+
+        grenade_count.0 -= 1;
+
+        let forward = transform.rotation * Vec3::Z;
+        let origin = transform.translation + Vec3::Y * 1.5 + forward * 0.8;
+        let velocity = forward * GRENADE_THROW_SPEED + Vec3::Y * GRENADE_THROW_LIFT;
+
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: resources.grenade_mesh.clone(),
+                material: resources.grenade_material.clone(),
+                ..Default::default()
+            })
+            .insert_bundle(RigidBodyBundle {
+                body_type: RigidBodyType::Dynamic,
+                position: origin.into(),
+                velocity: RigidBodyVelocity {
+                    linvel: velocity.into(),
+                    ..Default::default()
+                },
+                damping: RigidBodyDamping {
+                    linear_damping: 0.5,
+                    angular_damping: 1.0,
+                },
+                ccd: RigidBodyCcd {
+                    ccd_enabled: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert_bundle(ColliderBundle {
+                shape: resources.grenade_shape.clone(),
+                material: ColliderMaterial {
+                    friction: 0.8,
+                    restitution: 0.4,
+                    ..Default::default()
+                },
+                flags: ColliderFlags {
+                    collision_groups: InteractionGroups::new(
+                        PhysicsFlags::PROJECTILE.bits(),
+                        (PhysicsFlags::ENEMY | PhysicsFlags::ENVIRONMENT).bits(),
+                    ),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(RigidBodyPositionSync::Discrete)
+            .insert(Grenade {
+                explode_tick: tick.0 + simulation_settings.ticks(GRENADE_FUSE),
+                thrower: Some(character.0),
+            });
+    }
+}
+
+pub(super) fn fuse_grenades(
+    mut commands: Commands,
+    tick: Res<Tick>,
+    mut explosion_events: EventWriter<ExplosionEvent>,
+    grenade_query: Query<(Entity, &Grenade, &RigidBodyPosition)>,
+) {
+    for (entity, grenade, position) in grenade_query.iter() {
+        if tick.0 < grenade.explode_tick {
+            continue;
+        }
+
+        explosion_events.send(ExplosionEvent {
+            position: position.position.translation.into(),
+            explosion: GRENADE_EXPLOSION,
+            attacker: grenade.thrower,
+        });
+
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Sets explosive projectiles off on the first solid collider they touch, other than the character
+/// of their shooter.
+pub(super) fn detonate_projectiles(
+    mut commands: Commands,
+    mut intersection_events: EventReader<IntersectionEvent>,
+    mut explosion_events: EventWriter<ExplosionEvent>,
+    projectile_query: Query<(&Projectile, &RigidBodyPosition, &RigidBodyVelocity)>,
+    collider_type_query: Query<&ColliderType>,
+    character_query: Query<&PlayerCharacter>,
+) {
+    let mut detonated = Vec::new();
+
+    for intersection_event in intersection_events.iter() {
+        if !intersection_event.intersecting {
+            continue;
+        }
+
+        let entity1 = intersection_event.collider1.entity();
+        let entity2 = intersection_event.collider2.entity();
+
+        let (projectile_entity, other_entity) = if projectile_query.get(entity1).is_ok() {
+            (entity1, entity2)
+        } else if projectile_query.get(entity2).is_ok() {
+            (entity2, entity1)
+        } else {
+            continue;
+        };
+
+        let (projectile, position, velocity) = projectile_query.get(projectile_entity).unwrap();
+
+        let explosion = match projectile.explosion {
+            Some(explosion) => explosion,
+            None => continue,
+        };
+
+        if detonated.contains(&projectile_entity)
+            || !matches!(
+                collider_type_query.get(other_entity),
+                Ok(ColliderType::Solid)
+            )
+        {
+            continue;
+        }
+
+        if let Ok(character) = character_query.get(other_entity) {
+            if projectile.shooter == Some(character.0) {
+                continue;
+            }
+        }
+
+        let backwards: Vec3 = (-velocity.linvel).into();
+        let position: Vec3 = position.position.translation.into();
+
+        explosion_events.send(ExplosionEvent {
+            position: position + backwards.normalize_or_zero() * SURFACE_OFFSET,
+            explosion,
+            attacker: projectile.shooter,
+        });
+
+        detonated.push(projectile_entity);
+        commands.entity(projectile_entity).insert(DespawnAfter(0));
+    }
+}
+
+/// Damages and pushes everything within reach of explosions.
+///
+/// Enemies take an `EnemyHitEvent` and characters a `DamageEvent` following the damage rules,
+/// while any dynamic body, including corpses and props, is pushed away from the center. Only
+/// targets with a clear line to the center, with no environment in between, are affected.
+pub(super) fn explode(
+    mut commands: Commands,
+    simulation_settings: Res<SimulationSettings>,
+    resources: Res<ExplosiveResources>,
+    damage_rules: Res<DamageRules>,
+    query_pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    mut explosion_events: EventReader<ExplosionEvent>,
+    mut enemy_hit_events: EventWriter<EnemyHitEvent>,
+    mut damage_events: EventWriter<DamageEvent>,
+    enemy_query: Query<&EnemyBehavior>,
+    character_query: Query<&PlayerCharacter>,
+    weak_point_query: Query<&WeakPoint>,
+    shape_query: Query<(&ColliderPosition, &ColliderShape)>,
+    mut body_query: Query<(
+        &RigidBodyType,
+        &RigidBodyMassProps,
+        &mut RigidBodyVelocity,
+        &mut RigidBodyActivation,
+    )>,
+) {
+    let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+    let environment = InteractionGroups::new(u32::MAX, PhysicsFlags::ENVIRONMENT.bits());
+
+    for explosion_event in explosion_events.iter() {
+        let center = explosion_event.position;
+        let explosion = explosion_event.explosion;
+        let mut in_range = Vec::new();
+
+        query_pipeline.intersections_with_shape(
+            &collider_set,
+            &Isometry::translation(center.x, center.y, center.z),
+            &Ball::new(explosion.radius),
+            InteractionGroups::all(),
+            None,
+            |handle| {
+                in_range.push(handle.entity());
+                true
+            },
+        );
+
+        for entity in in_range {
+            // Weak points are part of an enemy that is in range on its own
+            if weak_point_query.get(entity).is_ok() {
+                continue;
+            }
+
+            let (collider_position, collider_shape) = match shape_query.get(entity) {
+                Ok(components) => components,
+                Err(_) => continue,
+            };
+
+            let target: Vec3 = collider_shape
+                .compute_aabb(collider_position)
+                .center()
+                .coords
+                .into();
+            let offset = target - center;
+            let distance = offset.length();
+            let direction = offset.try_normalize().unwrap_or(Vec3::Y);
+
+            let ray = Ray::new(center.into(), direction.into());
+            let occluded = query_pipeline
+                .cast_ray(
+                    &collider_set,
+                    &ray,
+                    distance,
+                    true,
+                    environment,
+                    Some(&|handle: ColliderHandle| handle.entity() != entity),
+                )
+                .is_some();
+
+            if occluded {
+                continue;
+            }
+
+            let strength = (1.0 - distance / explosion.radius).clamp(0.0, 1.0);
+
+            match enemy_query.get(entity) {
+                Ok(EnemyBehavior::Death) => {}
+                Ok(_) => enemy_hit_events.send(EnemyHitEvent {
+                    enemy: entity,
+                    damage: explosion.damage * strength,
+                    position: target.into(),
+                    direction: UnitVector::new_normalize(direction.into()),
+                }),
+                Err(_) => {
+                    if let Ok(character) = character_query.get(entity) {
+                        if let Some(damage_multiplier) = damage_rules
+                            .player_damage_multiplier(explosion_event.attacker, character.0)
+                        {
+                            damage_events.send(DamageEvent {
+                                target: entity,
+                                attacker: explosion_event.attacker,
+                                amount: explosion.damage * strength * damage_multiplier,
+                                position: target.into(),
+                                direction: UnitVector::new_normalize(direction.into()),
+                            });
+                        }
+                    }
+                }
+            }
+
+            if let Ok((body_type, mass_props, mut velocity, mut activation)) =
+                body_query.get_mut(entity)
+            {
+                if let RigidBodyType::Dynamic = body_type {
+                    velocity.apply_impulse(
+                        mass_props,
+                        (direction * explosion.impulse * strength).into(),
+                    );
+                    activation.wake_up(true);
+                }
+            }
+        }
+
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: resources.explosion_mesh.clone(),
+                material: resources.explosion_material.clone(),
+                transform: Transform {
+                    translation: center,
+                    scale: Vec3::ONE * explosion.radius,
+                    ..Default::default()
+                },
+                visible: Visible {
+                    is_transparent: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(DespawnAfter(
+                simulation_settings.ticks(EXPLOSION_FLASH_LIFETIME),
+            ));
+    }
+}
@@ -0,0 +1,220 @@
+use std::cmp::Ordering;
+
+use bevy::{prelude::*, render::mesh::shape};
+use bevy_rapier3d::prelude::*;
+
+use super::{
+    definition::DamageFalloff,
+    explosive::{ExplosionDefinition, ExplosionEvent, SURFACE_OFFSET},
+};
+use crate::{
+    damage::{DamageEvent, DamageRules, PlayerCharacter},
+    despawn::DespawnAfter,
+    enemy::{EnemyBehavior, EnemyHitEvent, WeakPoint},
+    player::PlayerId,
+    resources::SimulationSettings,
+    PhysicsFlags,
+};
+
+/// A shot of a hitscan weapon, resolved with a ray cast instead of a projectile body.
+pub struct HitscanEvent {
+    /// Player whose character fired the shot.
+    pub shooter: Option<PlayerId>,
+    pub origin: Vec3,
+    pub direction: Vec3,
+    pub range: f32,
+    pub damage: f32,
+    pub falloff: Option<DamageFalloff>,
+    /// Number of enemies the shot goes through before stopping.
+    pub penetration: u32,
+    pub explosion: Option<ExplosionDefinition>,
+}
+
+pub struct TracerResources {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+/// Seconds a tracer stays visible.
+const TRACER_LIFETIME: f32 = 0.05;
+
+const TRACER_WIDTH: f32 = 0.03;
+
+enum HitscanTarget {
+    Enemy,
+    Character,
+}
+
+pub(super) fn init_tracer_resources(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(TracerResources {
+        mesh: meshes.add(Mesh::from(shape::Box::new(1.0, 1.0, 1.0))),
+        material: materials.add(StandardMaterial {
+            base_color: Color::rgb(1.0, 0.9, 0.5),
+            unlit: true,
+            ..Default::default()
+        }),
+    });
+}
+
+/// Ray casts hitscan shots and turns the enemies and characters along the ray into hits.
+///
+/// The ray uses the collision groups of player projectiles, so it hits what they would. It stops
+/// at the first solid collider that is not an enemy or a character, or once it has gone through as
+/// many targets as its penetration allows. Like projectiles, it hits each enemy once with the
+/// highest multiplier among the body and weak points it crosses, and goes through the characters
+/// that the `DamageRules` keep it from hurting.
+pub(super) fn resolve_hitscan_shots(
+    mut commands: Commands,
+    resources: Res<TracerResources>,
+    simulation_settings: Res<SimulationSettings>,
+    damage_rules: Res<DamageRules>,
+    query_pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    collider_type_query: Query<&ColliderType>,
+    enemy_query: Query<&EnemyBehavior>,
+    weak_point_query: Query<&WeakPoint>,
+    character_query: Query<&PlayerCharacter>,
+    mut hitscan_events: EventReader<HitscanEvent>,
+    mut enemy_hit_events: EventWriter<EnemyHitEvent>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut explosion_events: EventWriter<ExplosionEvent>,
+) {
+    let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+    let groups = InteractionGroups::new(PhysicsFlags::PROJECTILE.bits(), u32::MAX);
+
+    for shot in hitscan_events.iter() {
+        let ray = Ray::new(shot.origin.into(), shot.direction.into());
+        let mut intersections = Vec::new();
+
+        query_pipeline.intersections_with_ray(
+            &collider_set,
+            &ray,
+            shot.range,
+            true,
+            groups,
+            None,
+            |handle, intersection| {
+                intersections.push((handle.entity(), intersection.toi));
+                true
+            },
+        );
+
+        intersections.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+
+        // Targets along the ray with the distance they were first hit at
+        let mut hits: Vec<(Entity, HitscanTarget, f32, f32)> = Vec::new();
+        let mut end = shot.range;
+
+        for (entity, toi) in intersections {
+            if let Ok(character) = character_query.get(entity) {
+                if let Some(damage_multiplier) =
+                    damage_rules.player_damage_multiplier(shot.shooter, character.0)
+                {
+                    hits.push((entity, HitscanTarget::Character, toi, damage_multiplier));
+                }
+
+                continue;
+            }
+
+            let (enemy_entity, damage_multiplier) = match weak_point_query.get(entity) {
+                Ok(weak_point) => (weak_point.enemy, weak_point.damage_multiplier),
+                Err(_) => (entity, 1.0),
+            };
+
+            match enemy_query.get(enemy_entity) {
+                Ok(EnemyBehavior::Death) => {}
+                Ok(_) => match hits.iter_mut().find(|hit| hit.0 == enemy_entity) {
+                    Some(hit) => hit.3 = hit.3.max(damage_multiplier),
+                    None => hits.push((enemy_entity, HitscanTarget::Enemy, toi, damage_multiplier)),
+                },
+                Err(_) => {
+                    // Other sensors, such as projectiles in flight, do not block the ray
+                    if let Ok(ColliderType::Solid) = collider_type_query.get(entity) {
+                        end = toi;
+                        break;
+                    }
+                }
+            }
+        }
+
+        let max_hits = shot.penetration as usize + 1;
+
+        if hits.len() >= max_hits {
+            hits.truncate(max_hits);
+            end = hits[max_hits - 1].2;
+        }
+
+        let direction = UnitVector::new_normalize(shot.direction.into());
+
+        for (entity, target, toi, damage_multiplier) in hits {
+            let falloff = shot.falloff.map_or(1.0, |falloff| falloff.multiplier(toi));
+            let damage = shot.damage * damage_multiplier * falloff;
+            let position = ray.point_at(toi);
+
+            match target {
+                HitscanTarget::Enemy => enemy_hit_events.send(EnemyHitEvent {
+                    enemy: entity,
+                    damage,
+                    position,
+                    direction,
+                }),
+                HitscanTarget::Character => damage_events.send(DamageEvent {
+                    target: entity,
+                    attacker: shot.shooter,
+                    amount: damage,
+                    position,
+                    direction,
+                }),
+            }
+        }
+
+        // Explosive shots go off where the ray stopped, if it hit anything
+        if let Some(explosion) = shot.explosion {
+            if end < shot.range {
+                explosion_events.send(ExplosionEvent {
+                    position: shot.origin + shot.direction * (end - SURFACE_OFFSET).max(0.0),
+                    explosion,
+                    attacker: shot.shooter,
+                });
+            }
+        }
+
+        spawn_tracer(
+            &mut commands,
+            &resources,
+            &simulation_settings,
+            shot.origin,
+            shot.origin + shot.direction * end,
+        );
+    }
+}
+
+fn spawn_tracer(
+    commands: &mut Commands,
+    resources: &TracerResources,
+    simulation_settings: &SimulationSettings,
+    from: Vec3,
+    to: Vec3,
+) {
+    let length = from.distance(to);
+
+    if length <= f32::EPSILON {
+        return;
+    }
+
+    let mut transform = Transform::from_translation((from + to) / 2.0).looking_at(to, Vec3::Y);
+    transform.scale = Vec3::new(TRACER_WIDTH, TRACER_WIDTH, length);
+
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: resources.mesh.clone(),
+            material: resources.material.clone(),
+            transform,
+            ..Default::default()
+        })
+        .insert(DespawnAfter(simulation_settings.ticks(TRACER_LIFETIME)));
+}
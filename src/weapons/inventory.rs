@@ -0,0 +1,196 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{resources::SimulationSettings, Tick};
+
+use super::{
+    definition::WeaponDefinition, Weapon, WeaponEnabled, WeaponInput, WeaponReload, WeaponTrigger,
+};
+
+/// Weapons carried by a character, one per slot. Only the active one can be fired.
+pub struct WeaponInventory {
+    pub slots: Vec<Option<Entity>>,
+    pub active: usize,
+}
+
+impl WeaponInventory {
+    pub const SLOTS: usize = 4;
+
+    pub fn new(weapon: Entity) -> Self {
+        let mut slots = vec![None; Self::SLOTS];
+        slots[0] = Some(weapon);
+
+        Self { slots, active: 0 }
+    }
+
+    pub fn active_weapon(&self) -> Option<Entity> {
+        self.slots[self.active]
+    }
+
+    pub fn first_empty_slot(&self) -> Option<usize> {
+        self.slots.iter().position(Option::is_none)
+    }
+
+    /// Occupied slot, other than the active one, that a switch asks for.
+    fn requested_slot(&self, switch: WeaponSwitch) -> Option<usize> {
+        let slot = match switch {
+            WeaponSwitch::Slot(slot) => slot,
+            WeaponSwitch::Next => self.cycle(true)?,
+            WeaponSwitch::Previous => self.cycle(false)?,
+        };
+
+        if slot != self.active && self.slots.get(slot)?.is_some() {
+            Some(slot)
+        } else {
+            None
+        }
+    }
+
+    /// Nearest occupied slot in the given direction from the active one, wrapping around.
+    fn cycle(&self, forward: bool) -> Option<usize> {
+        (1..Self::SLOTS)
+            .map(|offset| {
+                if forward {
+                    (self.active + offset) % Self::SLOTS
+                } else {
+                    (self.active + Self::SLOTS - offset) % Self::SLOTS
+                }
+            })
+            .find(|&slot| self.slots[slot].is_some())
+    }
+}
+
+/// Change of active weapon a character asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum WeaponSwitch {
+    Slot(usize),
+    Next,
+    Previous,
+}
+
+impl WeaponSwitch {
+    const SLOT_KEYS: [KeyCode; WeaponInventory::SLOTS] =
+        [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4];
+
+    /// Switch asked for by the slot keys pressed this frame, or else by the mouse wheel.
+    pub fn from_input(input: &Input<KeyCode>, scroll: f32) -> Option<Self> {
+        Self::SLOT_KEYS
+            .iter()
+            .position(|&key| input.just_pressed(key))
+            .map(WeaponSwitch::Slot)
+            .or_else(|| {
+                if scroll < 0.0 {
+                    Some(WeaponSwitch::Next)
+                } else if scroll > 0.0 {
+                    Some(WeaponSwitch::Previous)
+                } else {
+                    None
+                }
+            })
+    }
+}
+
+/// Tick from which a weapon being drawn can be fired.
+pub struct WeaponDraw {
+    pub ready_tick: usize,
+}
+
+/// Makes the weapon in `slot` the active one.
+///
+/// The previous weapon is holstered right away, cancelling its reload, while the new one has to be
+/// drawn for its `draw_time` before `WeaponEnabled` moves to it. Holstered weapons are hidden by
+/// scaling them down to nothing, which also hides their model scene whenever it finishes spawning.
+pub fn select_weapon_slot(
+    commands: &mut Commands,
+    inventory: &mut WeaponInventory,
+    slot: usize,
+    tick: usize,
+    simulation_settings: &SimulationSettings,
+    definitions: &Assets<WeaponDefinition>,
+    weapon_query: &mut Query<(
+        &Weapon,
+        &mut WeaponTrigger,
+        &mut WeaponReload,
+        &mut Transform,
+    )>,
+) {
+    if let Some(previous) = inventory.active_weapon() {
+        if let Ok((_, mut trigger, mut reload, mut transform)) = weapon_query.get_mut(previous) {
+            trigger.release_required = true;
+            trigger.burst_remaining = 0;
+            reload.done_tick = None;
+            transform.scale = Vec3::ZERO;
+        }
+
+        commands
+            .entity(previous)
+            .remove::<WeaponEnabled>()
+            .remove::<WeaponDraw>();
+    }
+
+    inventory.active = slot;
+
+    if let Some(next) = inventory.active_weapon() {
+        if let Ok((weapon, _, _, mut transform)) = weapon_query.get_mut(next) {
+            let draw_time = definitions
+                .get(&weapon.definition)
+                .map_or(0.0, |definition| definition.draw_time);
+
+            transform.scale = Vec3::ONE;
+            commands.entity(next).insert(WeaponDraw {
+                ready_tick: tick + simulation_settings.ticks(draw_time),
+            });
+        }
+    }
+}
+
+/// Switches the weapons of characters whose input asks for it, so that replays switch on the same
+/// ticks.
+pub(super) fn switch_weapon(
+    mut commands: Commands,
+    tick: Res<Tick>,
+    simulation_settings: Res<SimulationSettings>,
+    definitions: Res<Assets<WeaponDefinition>>,
+    mut inventory_query: Query<(&WeaponInput, &mut WeaponInventory)>,
+    mut weapon_query: Query<(
+        &Weapon,
+        &mut WeaponTrigger,
+        &mut WeaponReload,
+        &mut Transform,
+    )>,
+) {
+    for (input, mut inventory) in inventory_query.iter_mut() {
+        let slot = match input
+            .switch
+            .and_then(|switch| inventory.requested_slot(switch))
+        {
+            Some(slot) => slot,
+            None => continue,
+        };
+
+        select_weapon_slot(
+            &mut commands,
+            &mut inventory,
+            slot,
+            tick.0,
+            &simulation_settings,
+            &definitions,
+            &mut weapon_query,
+        );
+    }
+}
+
+pub(super) fn draw_weapon(
+    mut commands: Commands,
+    tick: Res<Tick>,
+    query: Query<(Entity, &WeaponDraw)>,
+) {
+    for (entity, draw) in query.iter() {
+        if tick.0 >= draw.ready_tick {
+            commands
+                .entity(entity)
+                .remove::<WeaponDraw>()
+                .insert(WeaponEnabled);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use bevy::prelude::*;
+use bevy_rapier3d::{prelude::*, rapier::parry::shape::Ball};
+
+use super::WeaponInput;
+use crate::{
+    damage::{DamageEvent, DamageRules, PlayerCharacter},
+    enemy::{perception::NoiseEvent, EnemyBehavior, EnemyHitEvent},
+    resources::SimulationSettings,
+    sound::SoundResources,
+    PhysicsFlags, Tick,
+};
+
+/// Tick from which a character may strike again.
+#[derive(Default)]
+pub struct MeleeCooldown {
+    pub ready_tick: usize,
+}
+
+const MELEE_DAMAGE: f32 = 35.0;
+/// Distance the strike reaches in front of the character.
+const MELEE_RANGE: f32 = 1.2;
+const MELEE_RADIUS: f32 = 0.4;
+/// Height of the strike relative to the character.
+const MELEE_HEIGHT: f32 = 1.0;
+const MELEE_COOLDOWN: f32 = 0.6;
+/// Horizontal speed enemies are knocked back with.
+const MELEE_KNOCKBACK: f32 = 6.0;
+const MELEE_KNOCKBACK_LIFT: f32 = 1.5;
+const MELEE_NOISE_RADIUS: f32 = 4.0;
+
+/// Strikes with whatever is in front of characters that want to melee.
+///
+/// The strike is a short ball cast along the facing of the character, stopping at the first
+/// enemy, character or piece of environment. Enemies take an `EnemyHitEvent` and are knocked back,
+/// and characters a `DamageEvent` following the damage rules. The cooldown is counted in ticks, so
+/// that replays strike on the same ticks.
+pub(super) fn melee_attack(
+    tick: Res<Tick>,
+    simulation_settings: Res<SimulationSettings>,
+    audio: Res<Audio>,
+    sound_resources: Res<SoundResources>,
+    damage_rules: Res<DamageRules>,
+    query_pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    mut enemy_hit_events: EventWriter<EnemyHitEvent>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut noise_events: EventWriter<NoiseEvent>,
+    mut character_query: Query<(
+        Entity,
+        &WeaponInput,
+        &PlayerCharacter,
+        &Transform,
+        &mut MeleeCooldown,
+    )>,
+    target_character_query: Query<&PlayerCharacter>,
+    mut enemy_query: Query<(
+        &EnemyBehavior,
+        &mut RigidBodyVelocity,
+        &mut RigidBodyActivation,
+    )>,
+) {
+    let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+    let groups = InteractionGroups::new(
+        PhysicsFlags::PLAYER.bits(),
+        (PhysicsFlags::ENEMY | PhysicsFlags::PLAYER | PhysicsFlags::ENVIRONMENT).bits(),
+    );
+
+    for (entity, input, character, transform, mut cooldown) in character_query.iter_mut() {
+        if !input.melee || tick.0 < cooldown.ready_tick {
+            continue;
+        }
+
+        cooldown.ready_tick = tick.0 + simulation_settings.ticks(MELEE_COOLDOWN);
+
+        audio.play(sound_resources.melee_swing.clone());
+
+        let origin = transform.translation + Vec3::Y * MELEE_HEIGHT;
+
+        noise_events.send(NoiseEvent {
+            source: entity,
+            position: origin,
+            radius: MELEE_NOISE_RADIUS,
+        });
+
+        let forward = transform.rotation * Vec3::Z;
+        let hit = query_pipeline.cast_shape(
+            &collider_set,
+            &Isometry::translation(origin.x, origin.y, origin.z),
+            &forward.into(),
+            &Ball::new(MELEE_RADIUS),
+            MELEE_RANGE,
+            groups,
+            Some(&|handle: ColliderHandle| handle.entity() != entity),
+        );
+
+        let (target, toi) = match hit {
+            Some((handle, toi)) => (handle.entity(), toi.toi),
+            None => continue,
+        };
+
+        let position: Point<f32> = (origin + forward * toi).into();
+        let direction = UnitVector::new_normalize(forward.into());
+
+        if let Ok((behavior, mut velocity, mut activation)) = enemy_query.get_mut(target) {
+            if let EnemyBehavior::Death = behavior {
+                continue;
+            }
+
+            enemy_hit_events.send(EnemyHitEvent {
+                enemy: target,
+                damage: MELEE_DAMAGE,
+                position,
+                direction,
+            });
+
+            let knockback = forward * MELEE_KNOCKBACK + Vec3::Y * MELEE_KNOCKBACK_LIFT;
+            velocity.linvel = knockback.into();
+            activation.wake_up(true);
+        } else if let Ok(target_character) = target_character_query.get(target) {
+            if let Some(damage_multiplier) =
+                damage_rules.player_damage_multiplier(Some(character.0), target_character.0)
+            {
+                damage_events.send(DamageEvent {
+                    target,
+                    attacker: Some(character.0),
+                    amount: MELEE_DAMAGE * damage_multiplier,
+                    position,
+                    direction,
+                });
+            }
+        }
+    }
+}
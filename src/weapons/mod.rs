@@ -0,0 +1,687 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_rapier3d::{na::UnitQuaternion, prelude::*};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    damage::{DamageEvent, DamageRules, PlayerCharacter},
+    despawn::DespawnAfter,
+    enemy::perception::NoiseEvent,
+    json_asset::AddJsonAsset,
+    player::PlayerId,
+    pool::{EntityPools, PoolKind, PoolSettings, PoolSlot, Pooled, PooledBodyBundle},
+    random::Random,
+    resources::SimulationSettings,
+    tick::{on_tick, tick_due, AddTickEvent},
+    PhysicsFlags, Tick,
+};
+
+use self::{
+    ammo::{manual_reload, start_reload, AmmoReserve},
+    definition::{
+        load_weapon_definitions, prepare_weapon_definition_assets, DamageFalloff, WeaponDefinition,
+        WeaponDefinitions,
+    },
+    explosive::{
+        detonate_projectiles, explode, fuse_grenades, init_explosive_resources, throw_grenade,
+        ExplosionDefinition, ExplosionEvent,
+    },
+    hitscan::{init_tracer_resources, resolve_hitscan_shots, HitscanEvent},
+    inventory::{draw_weapon, switch_weapon, WeaponSwitch},
+    melee::melee_attack,
+    pickup::{
+        animate_pickups, collect_pickups, enemy_drops, init_pickup_resources, spawn_world_pickups,
+    },
+    recoil::{settle_weapon_spread, WeaponSpread},
+};
+
+pub mod ammo;
+pub mod definition;
+pub mod explosive;
+pub mod hitscan;
+pub mod inventory;
+pub mod melee;
+pub mod pickup;
+pub mod recoil;
+
+pub struct WeaponsPlugin;
+
+impl Plugin for WeaponsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_json_asset::<WeaponDefinition>(&["weapon.json"])
+            .add_tick_event::<HitscanEvent>()
+            .add_tick_event::<ExplosionEvent>()
+            .add_startup_system(init_weapon_resources.system())
+            .add_startup_system(init_tracer_resources.system())
+            .add_startup_system(init_explosive_resources.system())
+            .add_startup_system(load_weapon_definitions.system())
+            .add_startup_system(init_pickup_resources.system())
+            .add_system(prepare_weapon_definition_assets.system())
+            .add_system(init_weapons.system().before("fire_weapon"))
+            .add_system(animate_pickups.system())
+            .add_system_set(
+                SystemSet::on_enter(AppState::InGame).with_system(spawn_world_pickups.system()),
+            )
+            .add_system_set(
+                on_tick(AppState::InGame)
+                    .with_system(collect_pickups.system())
+                    .with_system(enemy_drops.system())
+                    .with_system(projectile_hit_characters.system().before("hit_enemy"))
+                    .with_system(throw_grenade.system().after("character_input"))
+                    .with_system(fuse_grenades.system().before("explode"))
+                    .with_system(detonate_projectiles.system().before("explode"))
+                    .with_system(explode.system().label("explode"))
+                    .with_system(
+                        melee_attack
+                            .system()
+                            .after("character_input")
+                            .before("hit_enemy")
+                            .before("enemy_movement"),
+                    ),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(tick_due.system())
+                    .with_system(
+                        switch_weapon
+                            .system()
+                            .label("switch_weapon")
+                            .after("character_input")
+                            .before("fire_weapon"),
+                    )
+                    .with_system(draw_weapon.system().after("switch_weapon"))
+                    .with_system(release_weapon_trigger.system().before("fire_weapon"))
+                    .with_system(
+                        fire_weapon
+                            .system()
+                            .label("fire_weapon")
+                            .after("character_input"),
+                    )
+                    .with_system(settle_weapon_spread.system().after("fire_weapon"))
+                    .with_system(resolve_hitscan_shots.system().after("fire_weapon"))
+                    .with_system(
+                        manual_reload
+                            .system()
+                            .after("character_input")
+                            .before("fire_weapon"),
+                    )
+                    .with_system(reload_weapon.system().after("fire_weapon")),
+            );
+    }
+}
+
+#[derive(Bundle)]
+pub struct WeaponBundle {
+    pub weapon: Weapon,
+    pub trigger: WeaponTrigger,
+    pub ammo_count: WeaponAmmoCount,
+    pub cooldown: WeaponCooldown,
+    pub reload: WeaponReload,
+    pub spread: WeaponSpread,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+}
+
+impl WeaponBundle {
+    /// A weapon of the given definition, filled up once the definition has loaded.
+    pub fn new(definition: Handle<WeaponDefinition>, transform: Transform) -> Self {
+        Self {
+            weapon: Weapon {
+                definition,
+                model: None,
+            },
+            trigger: WeaponTrigger {
+                release_required: true,
+                burst_remaining: 0,
+            },
+            ammo_count: WeaponAmmoCount(0),
+            cooldown: WeaponCooldown::default(),
+            reload: WeaponReload::default(),
+            spread: WeaponSpread::default(),
+            transform,
+            global_transform: GlobalTransform::identity(),
+        }
+    }
+}
+
+/// Where characters hold their weapons, relative to the character.
+pub fn held_weapon_transform() -> Transform {
+    Transform::from_xyz(0.0, 1.5, 0.7)
+}
+
+pub struct Weapon {
+    pub definition: Handle<WeaponDefinition>,
+    /// Model child, spawned once the definition has loaded.
+    pub model: Option<Entity>,
+}
+
+pub struct WeaponEnabled;
+
+/// What a character wants to do with its active weapon during a tick. Set from the local input,
+/// or from the recorded input during replays.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WeaponInput {
+    pub fire: bool,
+    /// Tightens the spread and slows the character down.
+    pub aim: bool,
+    /// Strikes in front of the character, whatever weapon it holds.
+    pub melee: bool,
+    /// Only set on the tick the switch is asked for.
+    pub switch: Option<WeaponSwitch>,
+    /// Throws a grenade. Only set on the tick the throw is asked for.
+    pub throw: bool,
+    /// Reloads the active weapon.
+    pub reload: bool,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum WeaponFireMode {
+    Semi,
+    /// Fires the given number of shots per trigger pull.
+    Burst(u32),
+    Auto,
+}
+
+pub struct WeaponTrigger {
+    pub release_required: bool,
+    /// Shots left in the current burst. They are fired even if the trigger is released.
+    pub burst_remaining: u32,
+}
+
+pub struct WeaponAmmoCount(pub i32);
+
+/// Tick from which a weapon may fire again.
+#[derive(Default)]
+pub struct WeaponCooldown {
+    pub ready_tick: usize,
+}
+
+/// Tick on which the reload in progress completes, if any.
+#[derive(Default)]
+pub struct WeaponReload {
+    pub done_tick: Option<usize>,
+}
+
+#[derive(Bundle)]
+pub struct ProjectileBundle {
+    pub projectile: Projectile,
+    pub despawn_after: DespawnAfter,
+    #[bundle]
+    pub rigid_body: RigidBodyBundle,
+    #[bundle]
+    pub collider: ColliderBundle,
+    pub rigid_body_position_sync: RigidBodyPositionSync,
+}
+
+/// Seconds a projectile flies before vanishing, if it hits nothing.
+const PROJECTILE_LIFETIME: f32 = 1.0;
+
+impl ProjectileBundle {
+    /// A projectile with nothing set up but its lifetime and the physics shared by all projectiles.
+    pub fn new(simulation_settings: &SimulationSettings) -> Self {
+        Self {
+            projectile: Projectile::default(),
+            despawn_after: DespawnAfter(simulation_settings.ticks(PROJECTILE_LIFETIME)),
+            rigid_body: RigidBodyBundle {
+                body_type: RigidBodyType::Dynamic,
+                forces: RigidBodyForces {
+                    gravity_scale: 0.0,
+                    ..Default::default()
+                },
+                ccd: RigidBodyCcd {
+                    ccd_thickness: 0.0,
+                    ccd_max_dist: 0.4,
+                    ccd_active: false,
+                    ccd_enabled: true,
+                },
+                ..Default::default()
+            },
+            collider: ColliderBundle {
+                collider_type: ColliderType::Sensor,
+                material: ColliderMaterial {
+                    friction: 0.2,
+                    restitution: 0.8,
+                    ..Default::default()
+                },
+                flags: ColliderFlags {
+                    active_events: ActiveEvents::INTERSECTION_EVENTS,
+                    collision_groups: InteractionGroups::new(
+                        PhysicsFlags::PROJECTILE.bits(),
+                        u32::MAX,
+                    ),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            rigid_body_position_sync: RigidBodyPositionSync::Discrete,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Projectile {
+    /// Player whose character fired the projectile.
+    pub shooter: Option<PlayerId>,
+    pub damage: f32,
+    /// Where the projectile was fired from, for damage falloff.
+    pub origin: Vec3,
+    pub falloff: Option<DamageFalloff>,
+    /// Number of enemies the projectile goes through before stopping.
+    pub penetration: u32,
+    /// Enemies already hit, which the projectile does not hit again.
+    pub hits: Vec<Entity>,
+    pub explosion: Option<ExplosionDefinition>,
+}
+
+/// Scene shown for a projectile, which pooled projectiles keep between uses.
+pub struct ProjectileModel(pub Handle<Scene>);
+
+pub struct WeaponResources {
+    projectile_shape: SharedShape,
+}
+
+fn init_weapon_resources(mut commands: Commands) {
+    commands.insert_resource(WeaponResources {
+        projectile_shape: SharedShape::capsule(point!(0.0, 0.0, -0.1), point!(0.0, 0.0, 0.1), 0.1),
+    });
+}
+
+/// Finishes setting up weapons once their definition has loaded: spawns the model and fills the
+/// magazine.
+fn init_weapons(
+    mut commands: Commands,
+    definitions: Res<Assets<WeaponDefinition>>,
+    weapon_definitions: Res<WeaponDefinitions>,
+    mut query: Query<(Entity, &mut Weapon, &mut WeaponAmmoCount)>,
+) {
+    for (entity, mut weapon, mut ammo_count) in query.iter_mut() {
+        if weapon.model.is_some() {
+            continue;
+        }
+
+        let (definition, assets) = match (
+            definitions.get(&weapon.definition),
+            weapon_definitions.assets.get(&weapon.definition),
+        ) {
+            (Some(definition), Some(assets)) => (definition, assets),
+            _ => continue,
+        };
+
+        let model = commands
+            .spawn_bundle((
+                Transform::from_xyz(0.0, -0.2, 0.0)
+                    * Transform::from_rotation(Quat::from_axis_angle(Vec3::Y, -PI / 2.0))
+                    * Transform::from_scale(definition.model_scale * Vec3::ONE),
+                GlobalTransform::identity(),
+            ))
+            .with_children(|parent| {
+                parent.spawn_scene(assets.model.clone());
+            })
+            .id();
+
+        commands.entity(entity).push_children(&[model]);
+
+        weapon.model = Some(model);
+        ammo_count.0 = definition.magazine_size;
+    }
+}
+
+fn release_weapon_trigger(
+    input_query: Query<&WeaponInput>,
+    mut query: Query<(&mut WeaponTrigger, Option<&Parent>)>,
+) {
+    for (mut trigger, parent) in query.iter_mut() {
+        let firing = parent
+            .and_then(|parent| input_query.get(parent.0).ok())
+            .map_or(false, |input| input.fire);
+
+        if !firing {
+            trigger.release_required = false;
+        }
+    }
+}
+
+fn fire_weapon(
+    mut commands: Commands,
+    tick: Res<Tick>,
+    simulation_settings: Res<SimulationSettings>,
+    audio: Res<Audio>,
+    mut random: ResMut<Random>,
+    weapon_resources: Res<WeaponResources>,
+    definitions: Res<Assets<WeaponDefinition>>,
+    weapon_definitions: Res<WeaponDefinitions>,
+    mut noise_events: EventWriter<NoiseEvent>,
+    mut hitscan_events: EventWriter<HitscanEvent>,
+    pool_settings: Res<PoolSettings>,
+    mut pools: ResMut<EntityPools>,
+    projectile_model_query: Query<(&ProjectileModel, Option<&Children>)>,
+    holder_query: Query<(
+        Option<&WeaponInput>,
+        Option<&PlayerCharacter>,
+        Option<&AmmoReserve>,
+        Option<&RigidBodyVelocity>,
+    )>,
+    mut query: Query<
+        (
+            Entity,
+            &Weapon,
+            &mut WeaponTrigger,
+            &mut WeaponAmmoCount,
+            &mut WeaponReload,
+            &mut WeaponCooldown,
+            &mut WeaponSpread,
+            &GlobalTransform,
+            Option<&Parent>,
+        ),
+        With<WeaponEnabled>,
+    >,
+) {
+    for (
+        weapon_entity,
+        weapon,
+        mut weapon_trigger,
+        mut weapon_ammo_count,
+        mut weapon_reload,
+        mut weapon_cooldown,
+        mut weapon_spread,
+        weapon_transform,
+        weapon_parent,
+    ) in query.iter_mut()
+    {
+        let (definition, assets) = match (
+            definitions.get(&weapon.definition),
+            weapon_definitions.assets.get(&weapon.definition),
+        ) {
+            (Some(definition), Some(assets)) => (definition, assets),
+            _ => continue,
+        };
+
+        let (input, character, reserve, holder_velocity) =
+            match weapon_parent.and_then(|parent| holder_query.get(parent.0).ok()) {
+                Some(holder) => holder,
+                None => (None, None, None, None),
+            };
+        let input = input.copied().unwrap_or_default();
+        let bursting = weapon_trigger.burst_remaining > 0;
+
+        if (!bursting && (!input.fire || weapon_trigger.release_required))
+            || weapon_reload.done_tick.is_some()
+            || tick.0 < weapon_cooldown.ready_tick
+        {
+            continue;
+        }
+
+        if weapon_ammo_count.0 <= 0 {
+            start_reload(
+                definition,
+                &weapon_ammo_count,
+                &mut weapon_reload,
+                reserve,
+                tick.0,
+                &simulation_settings,
+            );
+            weapon_trigger.release_required = true;
+            weapon_trigger.burst_remaining = 0;
+            continue;
+        }
+
+        weapon_ammo_count.0 -= 1;
+
+        match definition.fire_mode {
+            WeaponFireMode::Semi => {
+                weapon_trigger.release_required = true;
+            }
+            WeaponFireMode::Burst(shots) => {
+                if !bursting {
+                    weapon_trigger.burst_remaining = shots;
+                }
+
+                weapon_trigger.burst_remaining = weapon_trigger.burst_remaining.saturating_sub(1);
+
+                // The trigger may have been let go of during the burst
+                if weapon_trigger.burst_remaining == 0 {
+                    weapon_trigger.release_required = input.fire;
+                }
+            }
+            WeaponFireMode::Auto => {}
+        }
+
+        if weapon_ammo_count.0 <= 0 {
+            start_reload(
+                definition,
+                &weapon_ammo_count,
+                &mut weapon_reload,
+                reserve,
+                tick.0,
+                &simulation_settings,
+            );
+            weapon_trigger.release_required = true;
+            weapon_trigger.burst_remaining = 0;
+        }
+
+        // At least one tick apart, whatever the rate of fire
+        weapon_cooldown.ready_tick = tick.0
+            + simulation_settings
+                .ticks(1.0 / definition.rate_of_fire)
+                .max(1);
+
+        audio.play(assets.shoot_sound.clone());
+
+        noise_events.send(NoiseEvent {
+            source: weapon_parent.map_or(weapon_entity, |parent| parent.0),
+            position: weapon_transform.translation,
+            radius: definition.noise_radius,
+        });
+
+        let holder_speed = holder_velocity.map_or(0.0, |velocity| {
+            Vec2::new(velocity.linvel.x, velocity.linvel.z).length()
+        });
+        let half_spread = weapon_spread
+            .spread(definition, holder_speed, input.aim)
+            .to_radians()
+            / 2.0;
+        let aim_rotation =
+            UnitQuaternion::from(weapon_transform.rotation) * weapon_spread.recoil_rotation();
+        let shooter = character.map(|character| character.0);
+
+        weapon_spread.shot_fired(definition);
+
+        for _ in 0..definition.pellets {
+            let random_rotation = UnitQuaternion::from_euler_angles(
+                random.generator.gen_range(0.0..=half_spread),
+                0.0,
+                random.generator.gen_range(-PI..=PI),
+            );
+            let rotation = aim_rotation * random_rotation;
+
+            if let Some(range) = definition.hitscan_range {
+                hitscan_events.send(HitscanEvent {
+                    shooter,
+                    origin: weapon_transform.translation,
+                    direction: (rotation * Vector::z()).into(),
+                    range,
+                    damage: definition.damage,
+                    falloff: definition.falloff,
+                    penetration: definition.penetration,
+                    explosion: definition.explosion,
+                });
+                continue;
+            }
+
+            let mut projectile_bundle = ProjectileBundle::new(&simulation_settings);
+            projectile_bundle.projectile = Projectile {
+                shooter,
+                damage: definition.damage,
+                origin: weapon_transform.translation,
+                falloff: definition.falloff,
+                penetration: definition.penetration,
+                hits: Vec::new(),
+                explosion: definition.explosion,
+            };
+            projectile_bundle.rigid_body.position.position =
+                Isometry::from_parts(weapon_transform.translation.into(), rotation);
+            projectile_bundle.rigid_body.velocity = RigidBodyVelocity {
+                linvel: rotation * Vector::z() * definition.projectile_speed,
+                ..Default::default()
+            };
+            projectile_bundle.collider.shape = weapon_resources.projectile_shape.clone();
+
+            let projectile_scene = &assets.projectile_scene;
+            let slot = pools
+                .projectiles
+                .acquire(
+                    pool_settings.projectiles,
+                    |entity| match projectile_model_query.get(entity) {
+                        Ok((model, _)) => model.0 == *projectile_scene,
+                        Err(_) => false,
+                    },
+                );
+
+            match slot {
+                PoolSlot::Reused(entity) => {
+                    commands
+                        .entity(entity)
+                        .insert_bundle(PooledBodyBundle::new(
+                            PoolKind::Projectile,
+                            projectile_bundle.rigid_body,
+                            projectile_bundle.collider,
+                        ))
+                        .insert(projectile_bundle.projectile)
+                        .insert(projectile_bundle.despawn_after);
+
+                    // Entities of other weapons have their model swapped
+                    if let Ok((model, children)) = projectile_model_query.get(entity) {
+                        if model.0 != *projectile_scene {
+                            for child in children.iter().flat_map(|children| children.iter()) {
+                                commands.entity(*child).despawn_recursive();
+                            }
+
+                            commands
+                                .entity(entity)
+                                .insert(ProjectileModel(projectile_scene.clone()))
+                                .with_children(|parent| {
+                                    parent.spawn_scene(projectile_scene.clone());
+                                });
+                        }
+                    }
+                }
+                PoolSlot::New | PoolSlot::Overflow => {
+                    let mut projectile = commands.spawn_bundle(projectile_bundle);
+
+                    projectile
+                        .insert(ProjectileModel(projectile_scene.clone()))
+                        .with_children(|parent| {
+                            parent.spawn_scene(projectile_scene.clone());
+                        });
+
+                    if let PoolSlot::New = slot {
+                        projectile.insert(Pooled::new(PoolKind::Projectile));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Turns projectile intersections with player characters into damage, following the
+/// `DamageRules`. Hits on characters count towards the penetration of the projectile like enemies.
+fn projectile_hit_characters(
+    mut commands: Commands,
+    damage_rules: Res<DamageRules>,
+    mut intersection_events: EventReader<IntersectionEvent>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut projectile_query: Query<(&mut Projectile, &RigidBodyPosition, &RigidBodyVelocity)>,
+    character_query: Query<&PlayerCharacter>,
+) {
+    for intersection_event in intersection_events.iter() {
+        if !intersection_event.intersecting {
+            continue;
+        }
+
+        let entity1 = intersection_event.collider1.entity();
+        let entity2 = intersection_event.collider2.entity();
+
+        let (projectile_entity, character_entity, character) =
+            match (character_query.get(entity1), character_query.get(entity2)) {
+                (Err(_), Ok(character)) => (entity1, entity2, character),
+                (Ok(character), Err(_)) => (entity2, entity1, character),
+                _ => continue,
+            };
+
+        let (mut projectile, position, velocity) = match projectile_query.get_mut(projectile_entity)
+        {
+            Ok(components) => components,
+            Err(_) => continue,
+        };
+
+        if projectile.hits.len() > projectile.penetration as usize
+            || projectile.hits.contains(&character_entity)
+        {
+            continue;
+        }
+
+        let damage_multiplier =
+            match damage_rules.player_damage_multiplier(projectile.shooter, character.0) {
+                Some(damage_multiplier) => damage_multiplier,
+                None => continue,
+            };
+
+        let translation: Vec3 = position.position.translation.into();
+        let falloff = projectile.falloff.map_or(1.0, |falloff| {
+            falloff.multiplier(translation.distance(projectile.origin))
+        });
+
+        damage_events.send(DamageEvent {
+            target: character_entity,
+            attacker: projectile.shooter,
+            amount: projectile.damage * damage_multiplier * falloff,
+            position: position.position.translation.vector.into(),
+            direction: UnitVector::new_normalize(velocity.linvel),
+        });
+
+        projectile.hits.push(character_entity);
+
+        if projectile.hits.len() > projectile.penetration as usize {
+            commands.entity(projectile_entity).insert(DespawnAfter(0));
+        }
+    }
+}
+
+/// Finishes reloads, moving as many rounds as the holder has in reserve into the magazine.
+fn reload_weapon(
+    audio: Res<Audio>,
+    tick: Res<Tick>,
+    definitions: Res<Assets<WeaponDefinition>>,
+    weapon_definitions: Res<WeaponDefinitions>,
+    mut reserve_query: Query<&mut AmmoReserve>,
+    mut query: Query<(
+        &Weapon,
+        &mut WeaponAmmoCount,
+        &mut WeaponReload,
+        Option<&Parent>,
+    )>,
+) {
+    for (weapon, mut ammo_count, mut reload, parent) in query.iter_mut() {
+        match reload.done_tick {
+            Some(done_tick) if tick.0 >= done_tick => reload.done_tick = None,
+            _ => continue,
+        }
+
+        if let Some(definition) = definitions.get(&weapon.definition) {
+            let missing = (definition.magazine_size - ammo_count.0).max(0) as u32;
+            let loaded = match parent.and_then(|parent| reserve_query.get_mut(parent.0).ok()) {
+                Some(mut reserve) => reserve.take(definition.ammo_type, missing),
+                None => missing,
+            };
+
+            ammo_count.0 += loaded as i32;
+        }
+
+        if let Some(assets) = weapon_definitions.assets.get(&weapon.definition) {
+            audio.play(assets.reload_sound.clone());
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use std::f32::consts::PI;
+
+use bevy::{prelude::*, render::mesh::shape};
+use bevy_rapier3d::prelude::*;
+use rand::Rng;
+use serde::Deserialize;
+
+use super::{
+    ammo::AmmoReserve,
+    definition::{WeaponDefinition, WeaponDefinitions},
+    held_weapon_transform,
+    inventory::{WeaponDraw, WeaponInventory},
+    Weapon, WeaponBundle,
+};
+use crate::{
+    despawn::DespawnAfter,
+    enemy::{archetype::EnemyArchetype, death::EnemyDeathEvent, Enemy},
+    random::seeded_generator,
+    resources::SimulationSettings,
+    PhysicsFlags, Tick,
+};
+
+/// Item lying in the world that characters collect by walking into it.
+pub struct Pickup {
+    pub kind: PickupKind,
+}
+
+pub enum PickupKind {
+    /// Adds the weapon to the inventory, or a magazine of its ammo to the reserve if it is already
+    /// carried.
+    Weapon(Handle<WeaponDefinition>),
+    /// Adds a few magazines of the ammo of the active weapon to the reserve.
+    Ammo,
+}
+
+/// Visual child of a pickup, spun and bobbed in place.
+pub struct PickupModel;
+
+/// Something an enemy may leave behind, as written in the `drops` field of an archetype.
+#[derive(Deserialize)]
+pub struct PickupDrop {
+    pub pickup: PickupDefinition,
+    /// Probability of the drop, between 0 and 1.
+    pub chance: f32,
+}
+
+#[derive(Deserialize)]
+pub enum PickupDefinition {
+    /// Name of a weapon definition.
+    Weapon(String),
+    Ammo,
+}
+
+pub struct PickupResources {
+    weapon_mesh: Handle<Mesh>,
+    weapon_material: Handle<StandardMaterial>,
+    ammo_mesh: Handle<Mesh>,
+    ammo_material: Handle<StandardMaterial>,
+    shape: SharedShape,
+}
+
+/// Key of the generator rolling enemy drops, "drop" in ASCII.
+const DROPS_RANDOM_KEY: u64 = 0x6472_6f70;
+
+/// Seconds dropped pickups stay in the world.
+const DROP_LIFETIME: f32 = 30.0;
+
+/// Magazines worth of ammo given by an ammo pickup.
+const AMMO_PICKUP_MAGAZINES: i32 = 2;
+
+/// Pickups placed in the arena at the start of a game.
+const WORLD_PICKUPS: &[(Option<&str>, [f32; 2])] = &[
+    (Some("pistol_1"), [0.0, -6.0]),
+    (Some("pistol_3"), [6.0, 0.0]),
+    (Some("pistol_4"), [-6.0, 0.0]),
+    (Some("grenade_launcher"), [0.0, -14.0]),
+    (None, [0.0, 6.0]),
+];
+
+pub(super) fn init_pickup_resources(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(PickupResources {
+        weapon_mesh: meshes.add(Mesh::from(shape::Box::new(0.6, 0.25, 0.25))),
+        weapon_material: materials.add(Color::ORANGE.into()),
+        ammo_mesh: meshes.add(Mesh::from(shape::Box::new(0.3, 0.3, 0.3))),
+        ammo_material: materials.add(Color::YELLOW_GREEN.into()),
+        shape: SharedShape::ball(0.6),
+    });
+}
+
+pub fn spawn_pickup(
+    commands: &mut Commands,
+    resources: &PickupResources,
+    kind: PickupKind,
+    position: Vec3,
+) -> Entity {
+    let (mesh, material) = match kind {
+        PickupKind::Weapon(_) => (
+            resources.weapon_mesh.clone(),
+            resources.weapon_material.clone(),
+        ),
+        PickupKind::Ammo => (resources.ammo_mesh.clone(), resources.ammo_material.clone()),
+    };
+
+    commands
+        .spawn_bundle(RigidBodyBundle {
+            body_type: RigidBodyType::Static,
+            position: (position + Vec3::Y * 0.6).into(),
+            ..Default::default()
+        })
+        .insert_bundle(ColliderBundle {
+            collider_type: ColliderType::Sensor,
+            shape: resources.shape.clone(),
+            flags: ColliderFlags {
+                active_events: ActiveEvents::INTERSECTION_EVENTS,
+                collision_groups: InteractionGroups::new(
+                    PhysicsFlags::PICKUP.bits(),
+                    PhysicsFlags::PLAYER.bits(),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Pickup { kind })
+        .insert(Transform::default())
+        .insert(GlobalTransform::default())
+        .insert(RigidBodyPositionSync::Discrete)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(PbrBundle {
+                    mesh,
+                    material,
+                    ..Default::default()
+                })
+                .insert(PickupModel);
+        })
+        .id()
+}
+
+pub(super) fn spawn_world_pickups(
+    mut commands: Commands,
+    resources: Res<PickupResources>,
+    weapon_definitions: Res<WeaponDefinitions>,
+) {
+    for &(weapon, [x, z]) in WORLD_PICKUPS.iter() {
+        let kind = match weapon {
+            Some(name) => match weapon_definitions.handles.get(name) {
+                Some(definition) => PickupKind::Weapon(definition.clone()),
+                None => continue,
+            },
+            None => PickupKind::Ammo,
+        };
+
+        spawn_pickup(&mut commands, &resources, kind, Vec3::new(x, 0.0, z));
+    }
+}
+
+pub(super) fn animate_pickups(
+    time: Res<Time>,
+    mut query: Query<&mut Transform, With<PickupModel>>,
+) {
+    let seconds = time.seconds_since_startup() as f32;
+
+    for mut transform in query.iter_mut() {
+        transform.rotation = Quat::from_rotation_y(seconds * PI / 2.0);
+        transform.translation.y = 0.15 * (seconds * 2.0).sin();
+    }
+}
+
+/// Rolls the drops of the enemies that died this tick, with a generator of its own so that the
+/// rolls do not depend on how many numbers other systems drew from `Random` first.
+pub(super) fn enemy_drops(
+    mut commands: Commands,
+    tick: Res<Tick>,
+    simulation_settings: Res<SimulationSettings>,
+    resources: Res<PickupResources>,
+    weapon_definitions: Res<WeaponDefinitions>,
+    archetypes: Res<Assets<EnemyArchetype>>,
+    mut death_events: EventReader<EnemyDeathEvent>,
+    enemy_query: Query<(&Enemy, &RigidBodyPosition)>,
+) {
+    let mut generator = seeded_generator(tick.0, DROPS_RANDOM_KEY);
+
+    for death_event in death_events.iter() {
+        let (enemy, position) = match enemy_query.get(death_event.enemy) {
+            Ok(components) => components,
+            Err(_) => continue,
+        };
+        let archetype = match archetypes.get(&enemy.archetype) {
+            Some(archetype) => archetype,
+            None => continue,
+        };
+        let position: Vec3 = position.position.translation.into();
+
+        for (index, drop) in archetype.drops.iter().enumerate() {
+            if generator.gen_range(0.0..1.0) >= drop.chance {
+                continue;
+            }
+
+            let kind = match &drop.pickup {
+                PickupDefinition::Weapon(name) => match weapon_definitions.handles.get(name) {
+                    Some(definition) => PickupKind::Weapon(definition.clone()),
+                    None => {
+                        warn!("Unknown weapon {} in enemy drops", name);
+                        continue;
+                    }
+                },
+                PickupDefinition::Ammo => PickupKind::Ammo,
+            };
+
+            // Spread several drops around the body
+            let angle = index as f32 * 2.0 * PI / archetype.drops.len() as f32;
+            let offset = Vec3::new(angle.cos(), 0.0, angle.sin()) * 0.8;
+
+            let pickup = spawn_pickup(
+                &mut commands,
+                &resources,
+                kind,
+                Vec3::new(position.x, 0.0, position.z) + offset,
+            );
+
+            commands
+                .entity(pickup)
+                .insert(DespawnAfter(simulation_settings.ticks(DROP_LIFETIME)));
+        }
+    }
+}
+
+/// Hands pickups over to the characters walking into them.
+///
+/// A weapon goes to the first empty slot. With a full inventory it replaces the active weapon,
+/// which is dropped in its place.
+pub(super) fn collect_pickups(
+    mut commands: Commands,
+    tick: Res<Tick>,
+    simulation_settings: Res<SimulationSettings>,
+    resources: Res<PickupResources>,
+    definitions: Res<Assets<WeaponDefinition>>,
+    mut intersection_events: EventReader<IntersectionEvent>,
+    pickup_query: Query<&Pickup>,
+    mut character_query: Query<
+        (
+            Entity,
+            &mut WeaponInventory,
+            &Transform,
+            Option<&mut AmmoReserve>,
+        ),
+        Without<Weapon>,
+    >,
+    weapon_query: Query<&Weapon>,
+) {
+    let mut collected = Vec::new();
+
+    for intersection_event in intersection_events.iter() {
+        if !intersection_event.intersecting {
+            continue;
+        }
+
+        let entity1 = intersection_event.collider1.entity();
+        let entity2 = intersection_event.collider2.entity();
+
+        let (pickup_entity, character_entity) = if pickup_query.get(entity1).is_ok() {
+            (entity1, entity2)
+        } else if pickup_query.get(entity2).is_ok() {
+            (entity2, entity1)
+        } else {
+            continue;
+        };
+
+        if collected.contains(&pickup_entity) {
+            continue;
+        }
+
+        let (character, mut inventory, character_transform, reserve) =
+            match character_query.get_mut(character_entity) {
+                Ok(components) => components,
+                Err(_) => continue,
+            };
+
+        let restock = match &pickup_query.get(pickup_entity).unwrap().kind {
+            PickupKind::Ammo => inventory
+                .active_weapon()
+                .map(|weapon| (weapon, AMMO_PICKUP_MAGAZINES)),
+            PickupKind::Weapon(definition) => {
+                let carried = inventory.slots.iter().flatten().copied().find(|&weapon| {
+                    weapon_query
+                        .get(weapon)
+                        .map_or(false, |weapon| weapon.definition == *definition)
+                });
+
+                if let Some(carried) = carried {
+                    Some((carried, 1))
+                } else if let Some(slot) = inventory.first_empty_slot() {
+                    let mut transform = held_weapon_transform();
+                    transform.scale = Vec3::ZERO;
+
+                    let weapon = commands
+                        .spawn_bundle(WeaponBundle::new(definition.clone(), transform))
+                        .id();
+
+                    commands.entity(character).push_children(&[weapon]);
+                    inventory.slots[slot] = Some(weapon);
+                    None
+                } else {
+                    let active = inventory.active;
+
+                    if let Some(dropped) = inventory.slots[active] {
+                        if let Ok(dropped_weapon) = weapon_query.get(dropped) {
+                            let forward = character_transform.rotation * Vec3::Z;
+                            let drop_position = character_transform.translation - forward * 1.5;
+
+                            let pickup = spawn_pickup(
+                                &mut commands,
+                                &resources,
+                                PickupKind::Weapon(dropped_weapon.definition.clone()),
+                                Vec3::new(drop_position.x, 0.0, drop_position.z),
+                            );
+
+                            commands
+                                .entity(pickup)
+                                .insert(DespawnAfter(simulation_settings.ticks(DROP_LIFETIME)));
+                        }
+
+                        commands.entity(dropped).despawn_recursive();
+                    }
+
+                    let draw_time = definitions
+                        .get(definition)
+                        .map_or(0.0, |definition| definition.draw_time);
+                    let weapon = commands
+                        .spawn_bundle(WeaponBundle::new(
+                            definition.clone(),
+                            held_weapon_transform(),
+                        ))
+                        .insert(WeaponDraw {
+                            ready_tick: tick.0 + simulation_settings.ticks(draw_time),
+                        })
+                        .id();
+
+                    commands.entity(character).push_children(&[weapon]);
+                    inventory.slots[active] = Some(weapon);
+                    None
+                }
+            }
+        };
+
+        if let (Some((weapon, magazines)), Some(mut reserve)) = (restock, reserve) {
+            if let Some(definition) = weapon_query
+                .get(weapon)
+                .ok()
+                .and_then(|weapon| definitions.get(&weapon.definition))
+            {
+                reserve.add(
+                    definition.ammo_type,
+                    (magazines * definition.magazine_size) as u32,
+                );
+            }
+        }
+
+        collected.push(pickup_entity);
+        commands.entity(pickup_entity).despawn_recursive();
+    }
+}
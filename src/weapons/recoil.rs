@@ -0,0 +1,118 @@
+use bevy::prelude::*;
+use bevy_rapier3d::na::UnitQuaternion;
+use serde::Deserialize;
+
+use super::{definition::WeaponDefinition, Weapon};
+use crate::resources::SimulationSettings;
+
+/// Spread growth of a weapon, as written in its `bloom` field. Angles are in degrees.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct SpreadBloom {
+    /// Added to the spread with each shot.
+    pub per_shot: f32,
+    /// Added to the spread per meter per second the holder moves at.
+    #[serde(default)]
+    pub per_speed: f32,
+    /// Most the spread may grow by, from shots and movement together.
+    pub max: f32,
+    /// Degrees per second the bloom from shots recovers at.
+    pub recovery: f32,
+}
+
+/// Kick of a weapon, as written in its `recoil` field.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Recoil {
+    /// Kick of each shot of a string of fire, as yaw and pitch in degrees. The last entry repeats
+    /// for longer strings.
+    pub pattern: Vec<[f32; 2]>,
+    /// Degrees per second the weapon settles back at. The pattern starts over once it has.
+    pub recovery: f32,
+}
+
+/// Current bloom and recoil of a weapon.
+#[derive(Default)]
+pub struct WeaponSpread {
+    /// Degrees added to the spread by recent shots.
+    pub bloom: f32,
+    /// Yaw and pitch the next shot is kicked by, in degrees.
+    pub recoil: Vec2,
+    /// Position in the recoil pattern.
+    pub recoil_shot: usize,
+}
+
+impl WeaponSpread {
+    /// Width of the cone the next shot is spread in, in degrees.
+    pub fn spread(&self, definition: &WeaponDefinition, holder_speed: f32, aiming: bool) -> f32 {
+        let bloom = match definition.bloom {
+            Some(bloom) => (self.bloom + bloom.per_speed * holder_speed).min(bloom.max),
+            None => 0.0,
+        };
+        let multiplier = if aiming {
+            definition.aim_spread_multiplier
+        } else {
+            1.0
+        };
+
+        (definition.spread + bloom) * multiplier
+    }
+
+    /// Rotation of the next shot away from where the weapon points.
+    pub fn recoil_rotation(&self) -> UnitQuaternion<f32> {
+        UnitQuaternion::from_euler_angles(
+            -self.recoil.y.to_radians(),
+            self.recoil.x.to_radians(),
+            0.0,
+        )
+    }
+
+    /// Grows the bloom and kicks the weapon after a shot.
+    pub fn shot_fired(&mut self, definition: &WeaponDefinition) {
+        if let Some(bloom) = definition.bloom {
+            self.bloom = (self.bloom + bloom.per_shot).min(bloom.max);
+        }
+
+        if let Some(recoil) = &definition.recoil {
+            let kick = recoil
+                .pattern
+                .get(self.recoil_shot)
+                .or_else(|| recoil.pattern.last())
+                .copied()
+                .unwrap_or_default();
+
+            self.recoil += Vec2::from(kick);
+            self.recoil_shot += 1;
+        }
+    }
+}
+
+/// Lets bloom and recoil recover. Runs once per tick, so that replays spread shots the same way.
+pub(super) fn settle_weapon_spread(
+    simulation_settings: Res<SimulationSettings>,
+    definitions: Res<Assets<WeaponDefinition>>,
+    mut query: Query<(&Weapon, &mut WeaponSpread)>,
+) {
+    let delta = simulation_settings.tick_duration();
+
+    for (weapon, mut spread) in query.iter_mut() {
+        let definition = match definitions.get(&weapon.definition) {
+            Some(definition) => definition,
+            None => continue,
+        };
+
+        if let Some(bloom) = definition.bloom {
+            spread.bloom = (spread.bloom - bloom.recovery * delta).max(0.0);
+        }
+
+        if let Some(recoil) = &definition.recoil {
+            let length = spread.recoil.length();
+            let settled = recoil.recovery * delta;
+
+            if length <= settled {
+                spread.recoil = Vec2::ZERO;
+                spread.recoil_shot = 0;
+            } else {
+                spread.recoil *= 1.0 - settled / length;
+            }
+        }
+    }
+}